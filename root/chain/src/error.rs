@@ -16,6 +16,10 @@ error_chain! {
 			description("load spec failed"),
 			display("Load spec failed"),
 		}
+		LayoutMismatch(dir: String, expected: String, found: String) {
+			description("database created for another chain spec"),
+			display("Database {} was created for chain spec {}, not {}", dir, found, expected),
+		}
 	}
 
 }
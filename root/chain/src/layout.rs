@@ -0,0 +1,111 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Storage layout of the branch and root chain databases.
+//!
+//! Every database directory records the chain spec it was created for, and nodes refuse to
+//! start on a directory created for another chain spec.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use log::{info, warn};
+use parity_codec::Encode;
+use sr_primitives::BuildStorage;
+use substrate_primitives::{blake2_256, hexdisplay::HexDisplay};
+use substrate_service::{ChainSpec, RuntimeGenesis};
+use crate::error;
+
+/// Name of the file recording the chain spec of a database directory.
+const LAYOUT_FILE: &str = "layout";
+
+/// Default base of the root chain databases: the base path of the branch chain database.
+pub fn default_root_base_path(database_path: &str) -> PathBuf {
+	// <base>/chains/<branch chain spec id>/db
+	Path::new(database_path).ancestors().nth(3)
+		.map(Path::to_path_buf)
+		.unwrap_or_else(|| PathBuf::from(database_path))
+}
+
+/// Directory of the root chain databases (one per shard) of the branch.
+pub fn root_database_path(root_base_path: &Path, branch_id: &[u8]) -> PathBuf {
+	root_base_path.join("root_chains").join(format!("{}", HexDisplay::from(&branch_id)))
+}
+
+/// Move the root chain databases of earlier versions to the directory of the branch.
+///
+/// They were kept next to the branch chain database, at `<base>/root_chains/<branch chain spec id>/db`:
+/// first a single database of the shard the branch attached to, then one database per shard below it.
+pub fn migrate_legacy_databases(database_path: &str, root_database_path: &Path, shard_num: u16) -> error::Result<()> {
+	let legacy = PathBuf::from(database_path.replace("chains", "root_chains"));
+	if !legacy.is_dir() || legacy == root_database_path {
+		return Ok(());
+	}
+
+	fs::create_dir_all(root_database_path).map_err(|e| format!("{:?}", e))?;
+
+	if legacy.join("CURRENT").exists() {
+		return move_database(&legacy, &root_database_path.join(shard_num.to_string()));
+	}
+
+	for entry in fs::read_dir(&legacy).map_err(|e| format!("{:?}", e))? {
+		let path = entry.map_err(|e| format!("{:?}", e))?.path();
+		let shard = path.file_name().and_then(|name| name.to_str()).and_then(|name| name.parse::<u16>().ok());
+		if let Some(shard) = shard {
+			move_database(&path, &root_database_path.join(shard.to_string()))?;
+		}
+	}
+	// only removed once empty
+	let _ = fs::remove_dir(&legacy);
+	Ok(())
+}
+
+fn move_database(from: &Path, to: &Path) -> error::Result<()> {
+	if to.exists() {
+		warn!("Root chain database {} already exists, keeping {}", to.display(), from.display());
+		return Ok(());
+	}
+	info!("Moving root chain database {} to {}", from.display(), to.display());
+	fs::rename(from, to).map_err(|e| {
+		format!("Failed to move root chain database {} to {}: {:?}, move it manually", from.display(), to.display(), e).into()
+	})
+}
+
+/// Check that the directory was created for the chain spec, recording it if it is new.
+pub fn check_layout<G: RuntimeGenesis>(dir: &Path, spec: &ChainSpec<G>) -> error::Result<()> {
+	let expected = format!("{} {}", spec.id(), genesis_fingerprint(spec)?);
+	let file = dir.join(LAYOUT_FILE);
+
+	if file.exists() {
+		let found = fs::read_to_string(&file).map_err(|e| format!("{:?}", e))?;
+		if found.trim() != expected {
+			return Err(error::ErrorKind::LayoutMismatch(dir.to_string_lossy().into_owned(), expected, found.trim().to_string()).into());
+		}
+		return Ok(());
+	}
+
+	fs::create_dir_all(dir).map_err(|e| format!("{:?}", e))?;
+	fs::write(&file, expected + "\n").map_err(|e| format!("{:?}", e))?;
+	Ok(())
+}
+
+/// Hash of the genesis storage of the chain spec.
+fn genesis_fingerprint<G: RuntimeGenesis>(spec: &ChainSpec<G>) -> error::Result<String> {
+	let (storage, _) = spec.clone().build_storage()?;
+	let mut storage = storage.into_iter().collect::<Vec<_>>();
+	storage.sort();
+	Ok(format!("{}", HexDisplay::from(&blake2_256(&storage.encode()))))
+}
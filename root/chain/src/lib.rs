@@ -16,69 +16,443 @@
 // along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
 
 
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::RwLock;
 use substrate_service::{
 	ChainSpec, RuntimeGenesis, FactoryFullConfiguration, Configuration, ServiceFactory,
 	Roles, TaskExecutor, Arc, LightComponents, Components, Service, FactoryBlock,
 	FullClient, LightClient
 };
-use names::{Generator, Name};
 use yee_cli::{Factory, NodeConfig, get_initial_info, InitialInfo, FactoryBlockNumber};
 use yee_bootnodes_router::BootnodesRouterConf;
 use std::time;
 use log::{info, warn};
+use substrate_telemetry::{telemetry, SUBSTRATE_INFO};
 use substrate_network::{SyncState};
 use ansi_term::Colour;
 use std::fmt;
 use substrate_network::service::SyncProvider;
-use substrate_network::multiaddr::Protocol;
-use futures::stream::Stream;
+use substrate_network::multiaddr::{Protocol, Multiaddr};
+use substrate_network::config::{NetworkConfiguration, NonReservedPeerMode, NodeKeyConfig, Secret};
+use futures::{future::Future, stream::Stream};
 use sr_primitives::traits::As;
 use std::net::Ipv4Addr;
 use std::iter;
-use yee_sharding::{ShardingDigestItem, ScaleOutPhaseDigestItem};
-use sr_primitives::traits::{DigestItemFor, ProvideRuntimeApi};
+use yee_sharding::{ShardingDigestItem, ScaleOutPhaseDigestItem, ScaleOutPhase};
+use sr_primitives::traits::{DigestItemFor, ProvideRuntimeApi, Header, Digest};
 use substrate_client::ChainHead;
 use yee_sharding_primitives::{ShardingAPI};
 use yee_pow_primitives::YeePOWApi;
+use sr_primitives::generic::BlockId;
+use substrate_primitives::{hexdisplay::HexDisplay, H256};
+use substrate_transaction_pool::txpool::{IntoPoolError, error::ErrorKind as PoolErrorKind};
+use parity_codec::{Encode, Decode};
+use yee_root_port::{
+	Checkpoint, ForcedCall, SignedMetaTx, ROOT_CHECKPOINTS_PREFIX, ROOT_FORCED_CALLS_PREFIX,
+	ROOT_FIRST_CALL_INDEX_PREFIX, ROOT_NEXT_CALL_INDEX_PREFIX, MAX_FORCED_CALLS,
+};
 
 pub mod error;
+pub mod layout;
+pub mod relay;
+
+pub use layout::{check_layout, default_root_base_path, migrate_legacy_databases, root_database_path};
+pub use relay::Relayer;
 
 const IMPL_NAME : &str = "yee-node";
-const NODE_NAME_MAX_LENGTH: usize = 32;
+/// Node names must be shorter than this, in characters.
+pub const NODE_NAME_MAX_LENGTH: usize = 32;
+const ROOT_NODE_NAME_SUFFIX: &str = "-root";
+
+/// Called with the latest checkpoint of the branch in every finalized root chain block.
+pub type CheckpointHandler = Arc<dyn Fn(Checkpoint) + Send + Sync>;
+
+/// Called with the calls posted for the branch whenever they change in a finalized root chain block.
+pub type ForcedCallsHandler = Arc<dyn Fn(Vec<ForcedCall>) + Send + Sync>;
+
+/// Network options of the root light node.
+#[derive(Clone, Debug, Default)]
+pub struct NetworkParams {
+	/// Addresses advertised to peers, for nodes behind NAT.
+	pub public_addresses: Vec<String>,
+	/// Peers always connected to.
+	pub reserved_nodes: Vec<String>,
+	/// Only connect to the reserved peers.
+	pub reserved_only: bool,
+	/// Maximum number of peers, shared between incoming and outgoing.
+	pub max_peers: Option<u32>,
+	/// File of the ed25519 node key, created if missing, so that the peer ID is stable.
+	pub node_key_file: Option<PathBuf>,
+	/// Do not discover peers on the local network.
+	pub no_mdns: bool,
+}
 
 pub struct Params {
 	pub database_path : String, // branch chain database_path
-	pub keystore_path: String, // branch chain keystore_path
+	pub branch_name: String, // node name of the branch, linked to the root node in telemetry
+	pub node_name: String, // node name of the root light node
+	pub root_base_path: PathBuf, // base of the root chain databases
 	pub version_commit: &'static str,
 	pub version_version: &'static str,
 	pub trigger_exit: Arc<dyn yee_consensus::TriggerExit>,
 	pub root_bootnodes_router_conf: Option<BootnodesRouterConf>,
 	pub root_port: Option<u16>,
+	pub network: NetworkParams,
+	pub telemetry: bool, // whether to report the root light node in the telemetry of the branch
+	pub shard_num: u16, // root chain shard the branch attaches to
+	pub sharding_count: Arc<RwLock<Option<u16>>>, // root chain sharding count, shared with the branch inherent data provider
+	pub branch_id: Vec<u8>, // branch genesis hash, keying the branch checkpoints in the root chain
+	pub on_checkpoint: Option<CheckpointHandler>,
+	pub on_forced_calls: Option<ForcedCallsHandler>,
+	pub relayer: Option<Relayer>, // root chain account relaying the meta transactions, none to not relay
 }
 
-pub struct RootChain;
+pub struct RootChain {
+	params: Params,
+	executor: TaskExecutor,
+	service: Option<LightComponents<Factory>>,
+	/// Relayer nonce and extrinsic of the meta transactions submitted, by meta transaction hash.
+	submitted: HashMap<H256, (u64, Vec<u8>)>,
+	next_nonce: u64,
+}
 
 impl RootChain {
 
-	pub fn new(params: Params, executor: &TaskExecutor) -> error::Result<()> {
+	pub fn new(params: Params, executor: &TaskExecutor) -> error::Result<Self> {
+
+		let service = start_service(&params, executor)?;
+
+		Ok(RootChain {
+			params,
+			executor: executor.clone(),
+			service: Some(service),
+			submitted: HashMap::new(),
+			next_nonce: 0,
+		})
+	}
+
+	pub fn shard_num(&self) -> u16 {
+		self.params.shard_num
+	}
+
+	/// Submit a signed meta transaction of the branch to the root chain transaction pool, in a
+	/// branch port call signed by the relayer.
+	///
+	/// Submitting it again resubmits the same extrinsic, until the relayer nonce it was signed
+	/// with is used by another extrinsic.
+	pub fn submit_meta_tx(&mut self, signed_meta_tx: &SignedMetaTx) -> error::Result<()> {
+
+		self.restart_if_stopped()?;
+		let service = self.service.as_ref().ok_or("Root chain is not running")?;
+		let relayer = self.params.relayer.as_ref().ok_or("No root chain relayer account")?;
+
+		let info = service.client().info().map_err(|e| format!("{:?}", e))?.chain;
+		let at = BlockId::hash(info.best_hash);
+		if !is_branch_registered(&service.client(), &at, &self.params.branch_id)? {
+			return Err(BRANCH_PORT_MISSING.into());
+		}
+
+		let key = relay::account_nonce_key(relayer);
+		let state_nonce = match service.client().storage(&at, &key).map_err(|e| format!("{:?}", e))? {
+			Some(data) => u64::decode(&mut &data.0[..]).ok_or("Invalid relayer account nonce")?,
+			None => 0,
+		};
+
+		let hash = relay::meta_tx_hash(&self.params.branch_id, signed_meta_tx);
+		let extrinsic = match self.submitted.get(&hash) {
+			Some((nonce, extrinsic)) if *nonce >= state_nonce => extrinsic.clone(),
+			_ => {
+				let nonce = self.next_nonce.max(state_nonce);
+				let extrinsic = relay::submit_meta_tx_extrinsic(relayer, nonce, &self.params.branch_id, signed_meta_tx, info.genesis_hash);
+				self.next_nonce = nonce + 1;
+				self.submitted.insert(hash, (nonce, extrinsic.clone()));
+				extrinsic
+			},
+		};
+
+		// opaque extrinsics are the length prefixed extrinsic
+		let xt = Decode::decode(&mut &extrinsic.encode()[..])
+			.ok_or("Failed to encode meta transaction as root chain extrinsic")?;
+		match service.transaction_pool().submit_one(&at, xt) {
+			Ok(_) => Ok(()),
+			Err(e) => match e.into_pool_error() {
+				Ok(ref e) if matches_already_imported(e) => Ok(()),
+				Ok(e) => Err(format!("Submit meta transaction failed: {:?}", e).into()),
+				Err(e) => Err(format!("Submit meta transaction failed: {:?}", e).into()),
+			},
+		}
+	}
+
+	/// Whether the meta transaction of the branch is relayed in a finalized root chain block.
+	pub fn is_meta_tx_relayed(&mut self, signed_meta_tx: &SignedMetaTx) -> error::Result<bool> {
+
+		self.restart_if_stopped()?;
+		let service = self.service.as_ref().ok_or("Root chain is not running")?;
+
+		let finalized_hash = service.client().info().map_err(|e| format!("{:?}", e))?.chain.finalized_hash;
+		let hash = relay::meta_tx_hash(&self.params.branch_id, signed_meta_tx);
+		let relayed = service.client().storage(&BlockId::hash(finalized_hash), &relay::relayed_key(&hash))
+			.map_err(|e| format!("{:?}", e))?
+			.and_then(|data| bool::decode(&mut &data.0[..]))
+			.unwrap_or(false);
+
+		if relayed {
+			self.submitted.remove(&hash);
+		}
+		Ok(relayed)
+	}
+
+	/// Attach to another root chain shard by restarting the root chain light client.
+	///
+	/// If the light client of the new shard fails to start, the one of the current shard is
+	/// restarted and the error returned, so that the switch can be retried.
+	pub fn switch_shard(&mut self, shard_num: u16) -> error::Result<()> {
+
+		if shard_num == self.params.shard_num {
+			return self.restart_if_stopped();
+		}
+
+		info!("root chain: switch shard from {} to {}", self.params.shard_num, shard_num);
+
+		// both services would listen on the root port, the tasks of the old one end with it
+		self.service.take();
+		let old_shard_num = self.params.shard_num;
+		self.params.shard_num = shard_num;
+		match start_service(&self.params, &self.executor) {
+			Ok(service) => {
+				self.service = Some(service);
+				Ok(())
+			},
+			Err(e) => {
+				warn!("root chain: failed to start shard {}, restoring shard {}", shard_num, old_shard_num);
+				self.params.shard_num = old_shard_num;
+				self.service = start_service(&self.params, &self.executor)
+					.map_err(|e| warn!("root chain: failed to restore shard {}: {:?}", old_shard_num, e))
+					.ok();
+				Err(e)
+			},
+		}
+	}
+
+	/// Start the light client of the current shard again if it failed to start before.
+	fn restart_if_stopped(&mut self) -> error::Result<()> {
+
+		if self.service.is_none() {
+			info!("root chain: restarting shard {}", self.params.shard_num);
+			self.service = Some(start_service(&self.params, &self.executor)?);
+		}
+		Ok(())
+	}
+
+}
+
+/// Branch port features are idle without the module in the root runtime, which the pinned yeeroot
+/// runtime does not have: it has to be built with `BranchPort` (yee-branch-port).
+const BRANCH_PORT_MISSING: &str = "Branch not registered in the root chain, whose runtime needs the BranchPort module";
+
+/// Whether the branch is registered in the branch port of the root chain at the block.
+fn is_branch_registered(client: &LightClient<Factory>, at: &BlockId<FactoryBlock<Factory>>, branch_id: &[u8]) -> error::Result<bool> {
+	client.storage(at, &relay::owner_key(branch_id))
+		.map(|owner| owner.is_some())
+		.map_err(|e| format!("{:?}", e).into())
+}
+
+/// Warns once per start of the root chain light client that the branch port is missing, until it
+/// is found in a finalized root chain block.
+struct BranchPortCheck {
+	branch_id: Vec<u8>,
+	what: &'static str,
+	warned: bool,
+}
+
+impl BranchPortCheck {
+
+	fn new(branch_id: &[u8], what: &'static str) -> Self {
+		BranchPortCheck { branch_id: branch_id.to_vec(), what, warned: false }
+	}
+
+	fn is_available(&mut self, client: &LightClient<Factory>, at: &BlockId<FactoryBlock<Factory>>) -> bool {
+		match is_branch_registered(client, at, &self.branch_id) {
+			Ok(true) => {
+				self.warned = false;
+				true
+			},
+			Ok(false) => {
+				if !self.warned {
+					warn!("root chain: {}, {} not followed", BRANCH_PORT_MISSING, self.what);
+					self.warned = true;
+				}
+				false
+			},
+			Err(e) => {
+				warn!("root chain: failed to read branch registration: {:?}", e);
+				false
+			},
+		}
+	}
+}
+
+fn start_service(params: &Params, executor: &TaskExecutor) -> error::Result<LightComponents<Factory>> {
+
+	let config = create_config::<Factory>(params)?;
+
+	// the spec count is the lower bound until the root chain scales out, never overwrite a larger observed one
+	if let Ok(mut sharding_count) = params.sharding_count.write() {
+		*sharding_count = Some(sharding_count.map_or(config.custom.shard_count, |count| count.max(config.custom.shard_count)));
+	}
+
+	let service = LightComponents::<Factory>::new(config, executor.clone()).map_err(|e|format!("Start root chain failed: {:?}", e))?;
+
+	monitor_network(&*service, params, executor);
+
+	monitor_scale_out(&*service, params.sharding_count.clone(), executor);
+
+	if let Some(on_checkpoint) = params.on_checkpoint.clone() {
+		monitor_checkpoints(&*service, &params.branch_id, on_checkpoint, executor);
+	}
+
+	if let Some(on_forced_calls) = params.on_forced_calls.clone() {
+		monitor_forced_calls(&*service, &params.branch_id, on_forced_calls, executor);
+	}
+
+	Ok(service)
+}
+
+fn monitor_checkpoints(service: &Service<LightComponents<Factory>>, branch_id: &[u8], on_checkpoint: CheckpointHandler, executor: &TaskExecutor) {
+
+	let client = service.client();
+
+	let key = relay::map_key(ROOT_CHECKPOINTS_PREFIX, &branch_id);
+
+	let mut last_checkpoint = None;
 
-		let config = create_config::<Factory>(&params)?;
+	let mut branch_port = BranchPortCheck::new(branch_id, "checkpoints");
 
-		let service = &*LightComponents::<Factory>::new(config, executor.clone()).map_err(|e|format!("Start root chain failed: {:?}", e))?;
+	let checkpoints = client.finality_notification_stream().for_each(move |notification| {
+
+		if !branch_port.is_available(&client, &BlockId::hash(notification.hash)) {
+			return Ok(());
+		}
 
-		monitor_network(&service, &executor);
+		match client.storage(&BlockId::hash(notification.hash), &key) {
+			Ok(Some(data)) => {
+				match Checkpoint::decode(&mut &data.0[..]) {
+					Some(checkpoint) => {
+						if last_checkpoint.as_ref() != Some(&checkpoint) {
+							info!("root chain: branch checkpoint #{} finalized in root block #{}", checkpoint.number, notification.header.number());
+							last_checkpoint = Some(checkpoint.clone());
+							on_checkpoint(checkpoint);
+						}
+					},
+					None => warn!("root chain: invalid branch checkpoint in root block {}", notification.hash),
+				}
+			},
+			Ok(None) => (),
+			Err(e) => warn!("root chain: failed to read branch checkpoint: {:?}", e),
+		}
 
 		Ok(())
+	});
+
+	executor.spawn(checkpoints.select(service.on_exit()).then(|_| Ok(())));
+}
+
+/// Most calls posted for the branch handed over at once, from the first one not pruned.
+const MAX_TRACKED_FORCED_CALLS: u64 = 4 * MAX_FORCED_CALLS as u64;
+
+fn monitor_forced_calls(service: &Service<LightComponents<Factory>>, branch_id: &[u8], on_forced_calls: ForcedCallsHandler, executor: &TaskExecutor) {
+
+	let client = service.client();
+
+	let branch_id = branch_id.to_vec();
+
+	let mut last_forced_calls = None;
+
+	let mut branch_port = BranchPortCheck::new(&branch_id, "posted calls");
+
+	let forced_calls = client.finality_notification_stream().for_each(move |notification| {
+
+		let at = BlockId::hash(notification.hash);
+
+		if !branch_port.is_available(&client, &at) {
+			return Ok(());
+		}
+
+		match read_forced_calls(&*client, &at, &branch_id) {
+			Ok(forced_calls) => {
+				if last_forced_calls.as_ref() != Some(&forced_calls) {
+					info!("root chain: {} branch calls posted in root block #{}", forced_calls.len(), notification.header.number());
+					last_forced_calls = Some(forced_calls.clone());
+					on_forced_calls(forced_calls);
+				}
+			},
+			Err(e) => warn!("root chain: failed to read branch calls in root block {}: {}", notification.hash, e),
+		}
+
+		Ok(())
+	});
+
+	executor.spawn(forced_calls.select(service.on_exit()).then(|_| Ok(())));
+}
+
+/// Calls posted for the branch and not pruned yet at the root block, up to `MAX_TRACKED_FORCED_CALLS`.
+fn read_forced_calls(client: &LightClient<Factory>, at: &BlockId<FactoryBlock<Factory>>, branch_id: &[u8]) -> error::Result<Vec<ForcedCall>> {
+	let read_index = |prefix: &[u8]| -> error::Result<u64> {
+		match client.storage(at, &relay::map_key(prefix, &branch_id)).map_err(|e| format!("{:?}", e))? {
+			Some(data) => Ok(u64::decode(&mut &data.0[..]).ok_or("Invalid branch call index")?),
+			None => Ok(0),
+		}
+	};
+	let first = read_index(ROOT_FIRST_CALL_INDEX_PREFIX)?;
+	let next = read_index(ROOT_NEXT_CALL_INDEX_PREFIX)?;
+
+	let mut forced_calls = Vec::new();
+	for index in first..next.min(first + MAX_TRACKED_FORCED_CALLS) {
+		let key = relay::map_key(ROOT_FORCED_CALLS_PREFIX, &(branch_id, index));
+		let data = client.storage(at, &key).map_err(|e| format!("{:?}", e))?
+			.ok_or_else(|| format!("Branch call #{} not found", index))?;
+		forced_calls.push(ForcedCall::decode(&mut &data.0[..]).ok_or("Invalid branch call")?);
 	}
+	Ok(forced_calls)
+}
 
+fn matches_already_imported(e: &substrate_transaction_pool::txpool::error::Error) -> bool {
+	match e.kind() {
+		PoolErrorKind::AlreadyImported(_) => true,
+		_ => false,
+	}
 }
 
-fn monitor_network<C: Components>(service: &Service<C>, executor: &TaskExecutor) {
+fn monitor_scale_out(service: &Service<LightComponents<Factory>>, sharding_count: Arc<RwLock<Option<u16>>>, executor: &TaskExecutor) {
+
+	let scale_out = service.client().import_notification_stream().for_each(move |notification| {
+
+		for log in notification.header.digest().logs() {
+			if let Some(ScaleOutPhase::Committing { shard_count }) = log.as_scale_out_phase() {
+				info!("root chain: scale out committed, sharding count: {}", shard_count);
+				if let Ok(mut sharding_count) = sharding_count.write() {
+					*sharding_count = Some(sharding_count.map_or(shard_count, |count| count.max(shard_count)));
+				}
+			}
+		}
+
+		Ok(())
+	});
+
+	executor.spawn(scale_out.select(service.on_exit()).then(|_| Ok(())));
+}
+
+fn monitor_network<C: Components>(service: &Service<C>, params: &Params, executor: &TaskExecutor) {
 
 	let network = service.network();
 	let client = service.client();
 	let txpool = service.transaction_pool();
+	let branch_name = params.branch_name.clone();
+	let node_name = params.node_name.clone();
+	let branch_id = format!("{}", HexDisplay::from(&params.branch_id));
+	let shard_num = params.shard_num;
+	let report = params.telemetry;
 
 	let mut last_number = None;
 	let mut last_update = time::Instant::now();
@@ -112,6 +486,30 @@ fn monitor_network<C: Components>(service: &Service<C>, executor: &TaskExecutor)
 				TransferRateFormat(bandwidth_download),
 				TransferRateFormat(bandwidth_upload),
 			);
+			if !report {
+				return Ok(());
+			}
+			// links the two nodes of the process on the dashboard
+			telemetry!(SUBSTRATE_INFO; "yee.root.linked";
+				"branch_name" => branch_name.clone(),
+				"root_name" => node_name.clone(),
+				"branch_id" => branch_id.clone(),
+				"shard_num" => shard_num,
+				"best" => ?best_hash,
+				"height" => best_number,
+			);
+			telemetry!(SUBSTRATE_INFO; "yee.root.interval";
+				"branch_id" => branch_id.clone(),
+				"shard_num" => shard_num,
+				"status" => status.clone(),
+				"peers" => sync_status.num_peers,
+				"height" => best_number,
+				"best" => ?best_hash,
+				"finalized_height" => finalized_number,
+				"finalized_hash" => ?info.chain.finalized_hash,
+				"bandwidth_download" => bandwidth_download,
+				"bandwidth_upload" => bandwidth_upload,
+			);
 
 		} else {
 			warn!("Error getting best block information");
@@ -120,7 +518,7 @@ fn monitor_network<C: Components>(service: &Service<C>, executor: &TaskExecutor)
 		Ok(())
 	});
 
-	executor.spawn(display_notifications);
+	executor.spawn(display_notifications.select(service.on_exit()).then(|_| Ok(())));
 }
 
 fn create_config<F>(params: &Params) -> error::Result<FactoryFullConfiguration<F>> where
@@ -132,7 +530,7 @@ fn create_config<F>(params: &Params) -> error::Result<FactoryFullConfiguration<F
 	<LightClient<F> as ProvideRuntimeApi>::Api: ShardingAPI<FactoryBlock<F>> + YeePOWApi<FactoryBlock<F>>,
 {
 
-	let shard_num = 0; //TODO
+	let shard_num = params.shard_num;
 
 	let spec_path = params.database_path.clone() + "/../../../conf/root-chain-spec.json";
 
@@ -143,11 +541,17 @@ fn create_config<F>(params: &Params) -> error::Result<FactoryFullConfiguration<F
 	config.impl_commit = params.version_commit;
 	config.impl_version = params.version_version;
 
-	config.name = generate_node_name();
-
-	config.keystore_path = params.keystore_path.clone();
+	config.name = params.node_name.clone();
+	// the telemetry logger is process-wide, a worker of the root node would take over the messages
+	// of the branch: root messages go through the telemetry of the branch
+	config.telemetry_endpoints = None;
 
-	config.database_path = params.database_path.clone().replace("chains", "root_chains");
+	let root_database_path = root_database_path(&params.root_base_path, &params.branch_id);
+	migrate_legacy_databases(&params.database_path, &root_database_path, shard_num)?;
+	check_layout(&root_database_path, &spec)?;
+	config.database_path = root_database_path.join(shard_num.to_string()).to_string_lossy().into_owned();
+	// the root light node keeps its keys apart from the branch keystore
+	config.keystore_path = root_database_path.join("keystore").to_string_lossy().into_owned();
 
 	config.roles = Roles::LIGHT;
 
@@ -170,6 +574,8 @@ fn create_config<F>(params: &Params) -> error::Result<FactoryFullConfiguration<F
 		_ => (),
 	}
 
+	configure_network(&mut config.network, &params.network)?;
+
 	let InitialInfo{context, shard_num, shard_count, ..} = get_initial_info::<F>(&config, shard_num)?;
 
 	config.custom.shard_num = shard_num;
@@ -180,23 +586,46 @@ fn create_config<F>(params: &Params) -> error::Result<FactoryFullConfiguration<F
 
 }
 
-fn load_spec<G: RuntimeGenesis>(spec_path: PathBuf) -> error::Result<ChainSpec<G>> {
+fn configure_network(config: &mut NetworkConfiguration, params: &NetworkParams) -> error::Result<()> {
 
-	ChainSpec::from_json_file(spec_path).map_err(|_|error::ErrorKind::LoadSpecFailed.into())
+	for address in &params.public_addresses {
+		let address = address.parse::<Multiaddr>().map_err(|e| format!("Invalid root public address {}: {:?}", address, e))?;
+		config.public_addresses.push(address);
+	}
+
+	config.reserved_nodes.extend(params.reserved_nodes.iter().cloned());
+	if params.reserved_only {
+		config.non_reserved_mode = NonReservedPeerMode::Deny;
+	}
+
+	if let Some(max_peers) = params.max_peers {
+		config.out_peers = max_peers / 2;
+		config.in_peers = max_peers - config.out_peers;
+	}
+
+	if let Some(ref node_key_file) = params.node_key_file {
+		config.node_key = NodeKeyConfig::Ed25519(Secret::File(node_key_file.clone()));
+	}
+
+	config.enable_mdns = !params.no_mdns;
 
+	Ok(())
 }
 
-fn generate_node_name() -> String {
-	let result = loop {
-		let node_name = Generator::with_naming(Name::Numbered).next().unwrap();
-		let count = node_name.chars().count();
+/// Name of the root light node: the given name, or the branch node name with a `-root` suffix.
+pub fn root_node_name(branch_name: &str, name: Option<&str>) -> String {
+	if let Some(name) = name {
+		return name.to_string();
+	}
+	// keep the suffix when the branch name is long
+	let branch_name = branch_name.chars().take(NODE_NAME_MAX_LENGTH - 1 - ROOT_NODE_NAME_SUFFIX.len()).collect::<String>();
+	format!("{}{}", branch_name, ROOT_NODE_NAME_SUFFIX)
+}
 
-		if count < NODE_NAME_MAX_LENGTH {
-			break node_name
-		}
-	};
+fn load_spec<G: RuntimeGenesis>(spec_path: PathBuf) -> error::Result<ChainSpec<G>> {
+
+	ChainSpec::from_json_file(spec_path).map_err(|_|error::ErrorKind::LoadSpecFailed.into())
 
-	result
 }
 
 fn speed(best_number: u64, last_number: Option<u64>, last_update: time::Instant) -> String {
@@ -0,0 +1,125 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Root chain extrinsics relaying the meta transactions of the branch to the branch port module
+//! of the root runtime (yee-branch-port), signed by a root chain account paying their fees.
+//!
+//! Root chain extrinsics are encoded like `UncheckedMortalCompactExtrinsic` of sr25519 accounts
+//! addressed by ID: length prefixed version byte, address, signature, compact nonce, era and
+//! call, the signature covering the encoded `(compact nonce, call, era, genesis hash)`, or its
+//! blake2 hash if longer than 256 bytes.
+
+use std::sync::Arc;
+use parity_codec::{Compact, Encode, Output};
+use sr_primitives::generic::Era;
+use substrate_primitives::{blake2_256, sr25519, storage::StorageKey, Pair, H256};
+use yee_root_port::SignedMetaTx;
+
+/// Version byte of signed root chain extrinsics.
+const SIGNED_EXTRINSIC_VERSION: u8 = 0b1000_0000 + 1;
+
+/// Payloads longer than this are signed by their hash.
+const MAX_PAYLOAD_LEN: usize = 256;
+
+/// Prefix of the root chain storage map of account nonces.
+const ACCOUNT_NONCE_PREFIX: &[u8] = b"System AccountNonce";
+
+/// Prefix of the branch port storage map of the meta transactions already relayed.
+const RELAYED_PREFIX: &[u8] = b"BranchPort Relayed";
+
+/// Prefix of the branch port storage map of branch owners, set for registered branches.
+const OWNERS_PREFIX: &[u8] = b"BranchPort Owners";
+
+/// Root chain account relaying the meta transactions of the branch.
+#[derive(Clone)]
+pub struct Relayer {
+	/// sr25519 key of the account, paying the fees.
+	pub key: Arc<sr25519::Pair>,
+	/// Index of `BranchPort` among the modules with calls of the root runtime.
+	pub branch_port_index: u8,
+}
+
+/// `submit_meta_tx` call of the branch port, encoded as a call of the root runtime.
+struct SubmitMetaTx<'a> {
+	module_index: u8,
+	branch_id: &'a [u8],
+	signed: &'a SignedMetaTx,
+}
+
+impl<'a> Encode for SubmitMetaTx<'a> {
+	fn encode_to<W: Output>(&self, dest: &mut W) {
+		dest.push_byte(self.module_index);
+		dest.push_byte(yee_branch_port::SUBMIT_META_TX_CALL_INDEX);
+		self.branch_id.encode_to(dest);
+		self.signed.encode_to(dest);
+	}
+}
+
+/// Root chain extrinsic of the relayer with the nonce, submitting the meta transaction of the branch.
+pub fn submit_meta_tx_extrinsic(relayer: &Relayer, nonce: u64, branch_id: &[u8], signed: &SignedMetaTx, genesis_hash: H256) -> Vec<u8> {
+	let call = SubmitMetaTx {
+		module_index: relayer.branch_port_index,
+		branch_id,
+		signed,
+	};
+
+	let signature = (Compact(nonce), &call, Era::Immortal, genesis_hash).using_encoded(|payload| {
+		if payload.len() > MAX_PAYLOAD_LEN {
+			relayer.key.sign(&blake2_256(payload)[..])
+		} else {
+			relayer.key.sign(payload)
+		}
+	});
+
+	let mut extrinsic = vec![SIGNED_EXTRINSIC_VERSION];
+	// account ID addresses are tagged 0xff
+	extrinsic.push(0xff);
+	relayer.key.public().encode_to(&mut extrinsic);
+	signature.encode_to(&mut extrinsic);
+	Compact(nonce).encode_to(&mut extrinsic);
+	Era::Immortal.encode_to(&mut extrinsic);
+	call.encode_to(&mut extrinsic);
+	extrinsic.encode()
+}
+
+/// Hash identifying a meta transaction of the branch on the root chain.
+pub fn meta_tx_hash(branch_id: &[u8], signed: &SignedMetaTx) -> H256 {
+	H256(blake2_256(&(branch_id, signed).encode()))
+}
+
+/// Key of the relayer account nonce in the root chain state.
+pub fn account_nonce_key(relayer: &Relayer) -> StorageKey {
+	map_key(ACCOUNT_NONCE_PREFIX, &relayer.key.public())
+}
+
+/// Key of the flag of a relayed meta transaction in the root chain state.
+pub fn relayed_key(hash: &H256) -> StorageKey {
+	map_key(RELAYED_PREFIX, hash)
+}
+
+/// Key of the owner of the branch in the root chain state, only set if the root runtime has the
+/// branch port and the branch is registered in it.
+pub fn owner_key(branch_id: &[u8]) -> StorageKey {
+	map_key(OWNERS_PREFIX, &branch_id)
+}
+
+/// Key of an entry of a root chain storage map.
+pub fn map_key<K: Encode>(prefix: &[u8], key: &K) -> StorageKey {
+	let mut data = prefix.to_vec();
+	key.encode_to(&mut data);
+	StorageKey(blake2_256(&data).to_vec())
+}
@@ -0,0 +1,76 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Runtime APIs for wallets building transactions.
+
+#[cfg(feature = "std")]
+use serde::{Serialize, Deserialize};
+use parity_codec::{Encode, Decode};
+use rstd::prelude::*;
+use client::decl_runtime_apis;
+use crate::{AccountId, Balance, Nonce};
+
+/// Fee of a transaction, from its encoded length and call weight.
+#[derive(Encode, Decode, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+pub struct FeeInfo {
+	/// Weight of the call.
+	pub weight: yee_fee::Weight,
+	/// Encoded length the fee is charged for.
+	pub encoded_len: u32,
+	/// Fee charged to the sender.
+	pub fee: Balance,
+}
+
+/// How the sender of a transaction is addressed, which the length of its signature depends on.
+#[derive(Encode, Decode, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "std", serde(rename_all = "camelCase"))]
+pub enum AddressKind {
+	/// By account ID.
+	Id,
+	/// By account index.
+	Index,
+}
+
+decl_runtime_apis! {
+	/// Account nonces, for the next transaction of an account.
+	pub trait AccountNonceApi {
+		/// Nonce of the account in the state.
+		fn account_nonce(account: AccountId) -> Nonce;
+	}
+
+	/// Authorities offline for the offline report inherent.
+	pub trait StakingApi {
+		/// Indices of the authorities that authored no block for two rounds of slots.
+		fn offline_authorities() -> Vec<u32>;
+	}
+
+	/// Funds of the treasury.
+	pub trait TreasuryApi {
+		/// Free balance of the treasury pot.
+		fn pot_balance() -> Balance;
+	}
+
+	/// Fees under the current fee configuration.
+	pub trait TransactionPaymentApi {
+		/// Fee of an encoded signed extrinsic, or of an encoded call signed by a sender addressed
+		/// the given way; none if it is neither.
+		fn query_info(encoded: Vec<u8>, address: AddressKind) -> Option<FeeInfo>;
+	}
+}
@@ -7,20 +7,22 @@
 
 #[cfg(feature = "std")]
 use serde::{Serialize, Deserialize};
-use parity_codec::{Encode, Decode};
+use parity_codec::{Compact, Encode, Decode};
 use rstd::prelude::*;
 #[cfg(feature = "std")]
 use primitives::bytes;
 use primitives::{ed25519, sr25519, OpaqueMetadata};
 use runtime_primitives::{
-	ApplyResult, transaction_validity::TransactionValidity, generic, create_runtime_str,
-	traits::{self, NumberFor, BlakeTwo256, Block as BlockT, StaticLookup, Verify}
+	ApplyResult, transaction_validity::{TransactionValidity, TransactionPriority}, generic, create_runtime_str,
+	traits::{self, NumberFor, BlakeTwo256, Block as BlockT, DigestFor, StaticLookup, Verify}
 };
 use client::{
 	block_builder::api::{CheckInherentsResult, InherentData, self as block_builder_api},
 	runtime_api, impl_runtime_apis
 };
 use version::RuntimeVersion;
+use fg_primitives::ScheduledChange;
+use yee_fee::Weighable;
 #[cfg(feature = "std")]
 use version::NativeVersion;
 
@@ -30,9 +32,16 @@ pub use runtime_primitives::BuildStorage;
 pub use consensus::Call as ConsensusCall;
 pub use timestamp::Call as TimestampCall;
 pub use balances::Call as BalancesCall;
+pub use yee_fee::Call as FeeCall;
+pub use yee_treasury::Call as TreasuryCall;
+pub use yee_assets::Call as AssetsCall;
+pub use yee_governance::Call as GovernanceCall;
+pub use yee_utility::Call as UtilityCall;
+pub use yee_staking::Call as StakingCall;
 pub use runtime_primitives::{Permill, Perbill};
 pub use timestamp::BlockPeriod;
 pub use support::{StorageValue, construct_runtime};
+pub use payment::UNKNOWN_ERROR;
 
 /// The type that is used for identifying authorities.
 pub type AuthorityId = <AuthoritySignature as Verify>::Signer;
@@ -55,9 +64,21 @@ pub type BlockNumber = u64;
 /// Index of an account's extrinsic in the chain.
 pub type Nonce = u64;
 
+/// Balance of an account.
+pub type Balance = u128;
+
+/// Short index of an account, usable in place of its ID in addresses.
+pub type AccountIndex = u32;
+
 /// Used for the module template in `./template.rs`
 mod template;
 
+/// Runtime APIs for wallets.
+pub mod api;
+
+/// Block execution charging fees by call weight.
+mod payment;
+
 /// Opaque types. These are used by the CLI to instantiate machinery that don't need to know
 /// the specifics of the runtime. They can then be made to be agnostic over specific formats
 /// of data like extrinsics, allowing for them to continue syncing the network through upgrades
@@ -95,8 +116,8 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 	spec_name: create_runtime_str!("node-template"),
 	impl_name: create_runtime_str!("node-template"),
 	authoring_version: 3,
-	spec_version: 4,
-	impl_version: 4,
+	spec_version: 30,
+	impl_version: 13,
 	apis: RUNTIME_API_VERSIONS,
 };
 
@@ -135,23 +156,51 @@ impl system::Trait for Runtime {
 }
 
 impl aura::Trait for Runtime {
-	type HandleReport = ();
+	type HandleReport = Staking;
 }
 
 impl consensus::Trait for Runtime {
 	/// The identifier we use to refer to authorities.
 	type SessionKey = AuthorityId;
-	// The aura module handles offline-reports internally
-	// rather than using an explicit report system.
-	type InherentOfflineReport = ();
+	/// Authorities found offline by block authors, noted by staking.
+	type InherentOfflineReport = yee_staking::OfflineReport<Runtime>;
 	/// The ubiquitous log type.
 	type Log = Log;
 }
 
+impl grandpa::Trait for Runtime {
+	type SessionKey = AuthorityId;
+	/// The ubiquitous log type.
+	type Log = Log;
+	/// The uniquitous event type.
+	type Event = Event;
+}
+
+/// Schedules GRANDPA authority set changes following the authorities elected by staking.
+pub struct GrandpaAuthorities;
+
+impl yee_staking::OnNewAuthorities<AuthorityId> for GrandpaAuthorities {
+	fn on_new_authorities(authorities: &[AuthorityId]) -> support::dispatch::Result {
+		let next: Vec<_> = authorities.iter().cloned().map(|key| (key, 1)).collect();
+		let current = Grandpa::grandpa_authorities();
+		if current == next {
+			return Ok(());
+		}
+		if current.is_empty() {
+			// chains upgraded by `set_code` have no GRANDPA authorities to finalize a standard
+			// change, which a forced change does not need
+			Grandpa::schedule_change(next, 0, Some(0))
+		} else {
+			// fails while a change is pending, staking notifies again in the next block
+			Grandpa::schedule_change(next, 0, None)
+		}
+	}
+}
+
 impl indices::Trait for Runtime {
 	/// The type for recording indexing into the account enumeration. If this ever overflows, there
 	/// will be problems!
-	type AccountIndex = u32;
+	type AccountIndex = AccountIndex;
 	/// Use the standard means of resolving an index hint from an id.
 	type ResolveHint = indices::SimpleResolveHint<Self::AccountId, Self::AccountIndex>;
 	/// Determine whether an account is dead.
@@ -168,7 +217,7 @@ impl timestamp::Trait for Runtime {
 
 impl balances::Trait for Runtime {
 	/// The type for recording an account's balance.
-	type Balance = u128;
+	type Balance = Balance;
 	/// What to do if an account's free balance gets zeroed.
 	type OnFreeBalanceZero = ();
 	/// What to do if a new account is created.
@@ -177,19 +226,158 @@ impl balances::Trait for Runtime {
 	type Event = Event;
 
 	type TransactionPayment = ();
-	type DustRemoval = ();
-	type TransferPayment = ();
+	type DustRemoval = Treasury;
+	type TransferPayment = Treasury;
 }
 
-impl sudo::Trait for Runtime {
+impl yee_governance::Trait for Runtime {
+	type Proposal = Call;
 	/// The uniquitous event type.
 	type Event = Event;
-	type Proposal = Call;
+}
+
+impl yee_fee::Trait for Runtime {
+	type Currency = Balances;
+	type SessionKey = AuthorityId;
+	type FindAuthor = AuraAuthor;
+	type OnFeeShare = Treasury;
+	/// The uniquitous event type.
+	type Event = Event;
+}
+
+/// Finds the author of the current block from the Aura slot of its timestamp.
+pub struct AuraAuthor;
+
+impl yee_fee::FindAuthor<AuthorityId> for AuraAuthor {
+	fn find_author() -> Option<AuthorityId> {
+		let slot_duration = Aura::slot_duration();
+		let authorities = Consensus::authorities();
+		if slot_duration == 0 || authorities.is_empty() {
+			return None;
+		}
+		let slot = Timestamp::now() / slot_duration;
+		authorities.get((slot % authorities.len() as u64) as usize).cloned()
+	}
+}
+
+/// Weight of a call doing a handful of storage reads and writes.
+const BASE_WEIGHT: yee_fee::Weight = 10_000;
+
+/// Weight schedule of the runtime calls, relative to `BASE_WEIGHT`: calls moving balances, looping
+/// over members or signatories, and checking signatures or proofs weigh more.
+impl Weighable for Call {
+	fn weight(&self) -> yee_fee::Weight {
+		let per_item = |len: usize| (len as yee_fee::Weight).saturating_mul(BASE_WEIGHT);
+		match self {
+			Call::Timestamp(_) => BASE_WEIGHT,
+
+			Call::Consensus(consensus::Call::set_code(..)) => 100 * BASE_WEIGHT,
+			Call::Consensus(consensus::Call::set_storage(items)) => per_item(items.len()).saturating_add(BASE_WEIGHT),
+			Call::Consensus(consensus::Call::kill_storage(keys)) => per_item(keys.len()).saturating_add(BASE_WEIGHT),
+			Call::Consensus(consensus::Call::report_misbehavior(..)) => 20 * BASE_WEIGHT,
+			Call::Consensus(_) => BASE_WEIGHT,
+
+			Call::Grandpa(_) => 20 * BASE_WEIGHT,
+
+			Call::Balances(balances::Call::transfer(..)) => 2 * BASE_WEIGHT,
+			Call::Balances(_) => BASE_WEIGHT,
+
+			Call::Governance(yee_governance::Call::propose(proposal)) => proposal.weight().saturating_add(5 * BASE_WEIGHT),
+			Call::Governance(yee_governance::Call::vote(..)) => 2 * BASE_WEIGHT,
+			Call::Governance(yee_governance::Call::remove_member(..)) => 5 * BASE_WEIGHT,
+			Call::Governance(yee_governance::Call::cancel_enactment(..)) => 2 * BASE_WEIGHT,
+			Call::Governance(_) => BASE_WEIGHT,
+
+			Call::Staking(yee_staking::Call::report_equivocation(..)) => 20 * BASE_WEIGHT,
+			Call::Staking(yee_staking::Call::bond(..)) |
+			Call::Staking(yee_staking::Call::unbond(..)) |
+			Call::Staking(yee_staking::Call::withdraw_unbonded(..)) |
+			Call::Staking(yee_staking::Call::validate(..)) |
+			Call::Staking(yee_staking::Call::chill(..)) => 2 * BASE_WEIGHT,
+			Call::Staking(_) => BASE_WEIGHT,
+
+			Call::Utility(yee_utility::Call::batch_non_atomic(calls)) =>
+				calls.iter().fold(BASE_WEIGHT, |weight, call| weight.saturating_add(call.weight())),
+			Call::Utility(yee_utility::Call::as_multi(_, signatories, call)) =>
+				call.weight().saturating_add(per_item(signatories.len())).saturating_add(2 * BASE_WEIGHT),
+			Call::Utility(yee_utility::Call::approve_as_multi(_, signatories, _)) |
+			Call::Utility(yee_utility::Call::cancel_as_multi(_, signatories, _)) =>
+				per_item(signatories.len()).saturating_add(2 * BASE_WEIGHT),
+			Call::Utility(_) => BASE_WEIGHT,
+
+			Call::Fee(_) => BASE_WEIGHT,
+
+			Call::Treasury(yee_treasury::Call::propose_spend(..)) |
+			Call::Treasury(yee_treasury::Call::reject_proposal(..)) => 2 * BASE_WEIGHT,
+			Call::Treasury(_) => BASE_WEIGHT,
+
+			Call::Assets(yee_assets::Call::bridge_out(..)) => 5 * BASE_WEIGHT,
+			Call::Assets(yee_assets::Call::issue(..)) |
+			Call::Assets(yee_assets::Call::transfer(..)) |
+			Call::Assets(yee_assets::Call::mint(..)) |
+			Call::Assets(yee_assets::Call::burn(..)) |
+			Call::Assets(yee_assets::Call::bridge_in(..)) => 2 * BASE_WEIGHT,
+			Call::Assets(_) => BASE_WEIGHT,
+
+			Call::RootPort(yee_root_port::Call::import_root(_, forced_calls)) =>
+				per_item(forced_calls.len()).saturating_mul(5).saturating_add(BASE_WEIGHT),
+			Call::RootPort(yee_root_port::Call::challenge_checkpoint(..)) => 100 * BASE_WEIGHT,
+			Call::RootPort(_) => BASE_WEIGHT,
+
+			// modules without weighed calls of their own
+			_ => BASE_WEIGHT,
+		}
+	}
+}
+
+impl yee_staking::Trait for Runtime {
+	type Currency = Balances;
+	type Signature = AuthoritySignature;
+	type FindAuthor = AuraAuthor;
+	type OnSlash = Treasury;
+	type OnNewAuthorities = GrandpaAuthorities;
+	/// The uniquitous event type.
+	type Event = Event;
+}
+
+impl yee_utility::Trait for Runtime {
+	type Currency = Balances;
+	type Call = Call;
+	/// The uniquitous event type.
+	type Event = Event;
+}
+
+impl yee_treasury::Trait for Runtime {
+	type Currency = Balances;
+	/// The uniquitous event type.
+	type Event = Event;
+}
+
+impl yee_assets::Trait for Runtime {
+	type Balance = u128;
+	type RootBridge = RootPortBridge;
+	/// The uniquitous event type.
+	type Event = Event;
+}
+
+/// Bridges assets to the root chain through root port meta transactions.
+pub struct RootPortBridge;
+
+impl yee_assets::RootBridge<u128> for RootPortBridge {
+	fn bridge_out(root_token: Vec<u8>, recipient: Vec<u8>, amount: u128) -> support::dispatch::Result {
+		RootPort::queue_token_transfer(root_token, recipient, amount);
+		Ok(())
+	}
 }
 
 impl yee_root_port::Trait for Runtime {
 	type SessionKey = AuthorityId;
+	type Signature = AuthoritySignature;
+	type AccountSignature = AccountSignature;
+	type Call = Call;
 	type ShardNum = u16;
+	/// The uniquitous event type.
+	type Event = Event;
 }
 
 /// Used for the module template in `./template.rs`
@@ -207,10 +395,16 @@ construct_runtime!(
 		Timestamp: timestamp::{Module, Call, Storage, Config<T>, Inherent},
 		Consensus: consensus::{Module, Call, Storage, Config<T>, Log(AuthoritiesChange), Inherent},
 		Aura: aura::{Module},
+		Grandpa: grandpa::{Module, Call, Storage, Config<T>, Log(), Event<T>},
 		Indices: indices,
 		Balances: balances,
-		Sudo: sudo,
-		RootPort: yee_root_port::{Module, Call, Storage, Config<T>},
+		Governance: yee_governance::{Module, Call, Storage, Config<T>, Event<T>},
+		Staking: yee_staking::{Module, Call, Storage, Config<T>, Event<T>},
+		Utility: yee_utility::{Module, Call, Storage, Config<T>, Event<T>},
+		Fee: yee_fee::{Module, Call, Storage, Config<T>, Event<T>},
+		Treasury: yee_treasury::{Module, Call, Storage, Config<T>, Event<T>},
+		Assets: yee_assets::{Module, Call, Storage, Event<T>},
+		RootPort: yee_root_port::{Module, Call, Storage, Config<T>, Event<T>, Inherent},
 		// Used for the module template in `./template.rs`
 		TemplateModule: template::{Module, Call, Storage, Event<T>},
 	}
@@ -219,7 +413,7 @@ construct_runtime!(
 /// The type used as a helper for interpreting the sender of transactions.
 type Context = system::ChainContext<Runtime>;
 /// The address format for describing accounts.
-type Address = <Indices as StaticLookup>::Source;
+pub type Address = <Indices as StaticLookup>::Source;
 /// Block header type as expected by this runtime.
 pub type Header = generic::Header<BlockNumber, BlakeTwo256, Log>;
 /// Block type as expected by this runtime.
@@ -230,8 +424,53 @@ pub type BlockId = generic::BlockId<Block>;
 pub type UncheckedExtrinsic = generic::UncheckedMortalCompactExtrinsic<Address, Nonce, Call, AccountSignature>;
 /// Extrinsic type that has already been checked.
 pub type CheckedExtrinsic = generic::CheckedExtrinsic<AccountId, Nonce, Call>;
-/// Executive: handles dispatch to the various modules.
-pub type Executive = executive::Executive<Runtime, Block, Context, Balances, AllModules>;
+/// Executive: handles dispatch to the various modules, charging fees by call weight.
+pub type Executive = payment::Executive;
+
+/// Priorities of checkpoint challenges start here, above those of all other transactions.
+const CHECKPOINT_PRIORITY: TransactionPriority = TransactionPriority::max_value() / 2 + 1;
+
+/// Pool priority of a transaction: its fee, so that a same-nonce transaction paying more
+/// replaces it. Checkpoint challenges, bound to dispute windows, go first, by fee among them.
+fn transaction_priority(tx: &UncheckedExtrinsic, weight: yee_fee::Weight) -> TransactionPriority {
+	let fee = Fee::compute_fee(tx.encode().len(), weight);
+	let fee = fee.min((CHECKPOINT_PRIORITY - 1) as u128) as TransactionPriority;
+	match tx.function {
+		Call::RootPort(yee_root_port::Call::challenge_checkpoint(..)) => CHECKPOINT_PRIORITY + fee,
+		_ => fee,
+	}
+}
+
+/// Worst case length a signature adds to a call in an extrinsic besides the address and the
+/// length prefix: version, sr25519 signature, compact nonce and mortal era.
+const SIGNATURE_LENGTH: usize = 1 + 64 + 9 + 2;
+
+/// Length of an encoded address of the kind: tagged account ID, or tagged account index at most.
+fn address_length(address: api::AddressKind) -> usize {
+	match address {
+		api::AddressKind::Id => 1 + 32,
+		api::AddressKind::Index => 1 + 4,
+	}
+}
+
+/// Fee of an encoded extrinsic, or of a call signed by a sender addressed the given way.
+fn query_fee_info(encoded: Vec<u8>, address: api::AddressKind) -> Option<api::FeeInfo> {
+	let input = &mut &encoded[..];
+	let (weight, encoded_len) = match UncheckedExtrinsic::decode(input) {
+		Some(ref xt) if input.is_empty() => (xt.function.weight(), encoded.len()),
+		_ => {
+			let input = &mut &encoded[..];
+			let call = Call::decode(input).filter(|_| input.is_empty())?;
+			let len = encoded.len() + address_length(address) + SIGNATURE_LENGTH;
+			(call.weight(), Compact(len as u32).encode().len() + len)
+		},
+	};
+	Some(api::FeeInfo {
+		weight,
+		encoded_len: encoded_len as u32,
+		fee: Fee::compute_fee(encoded_len, weight),
+	})
+}
 
 // Implement our runtime API endpoints. This is just a bunch of proxying.
 impl_runtime_apis! {
@@ -283,7 +522,12 @@ impl_runtime_apis! {
 
 	impl runtime_api::TaggedTransactionQueue<Block> for Runtime {
 		fn validate_transaction(tx: <Block as BlockT>::Extrinsic) -> TransactionValidity {
-			Executive::validate_transaction(tx)
+			let priority = transaction_priority(&tx, tx.function.weight());
+			match Executive::validate_transaction(tx) {
+				TransactionValidity::Valid { requires, provides, longevity, .. } =>
+					TransactionValidity::Valid { priority, requires, provides, longevity },
+				validity => validity,
+			}
 		}
 	}
 
@@ -304,4 +548,58 @@ impl_runtime_apis! {
 			Consensus::authorities()
 		}
 	}
+
+	impl api::AccountNonceApi<Block> for Runtime {
+		fn account_nonce(account: AccountId) -> Nonce {
+			System::account_nonce(account)
+		}
+	}
+
+	impl api::StakingApi<Block> for Runtime {
+		fn offline_authorities() -> Vec<u32> {
+			Staking::offline_authorities()
+		}
+	}
+
+	impl api::TreasuryApi<Block> for Runtime {
+		fn pot_balance() -> Balance {
+			Treasury::pot()
+		}
+	}
+
+	impl api::TransactionPaymentApi<Block> for Runtime {
+		fn query_info(encoded: Vec<u8>, address: api::AddressKind) -> Option<api::FeeInfo> {
+			query_fee_info(encoded, address)
+		}
+	}
+
+	impl fg_primitives::GrandpaApi<Block> for Runtime {
+		fn grandpa_pending_change(digest: &DigestFor<Block>) -> Option<ScheduledChange<NumberFor<Block>>> {
+			for log in digest.logs.iter().filter_map(|l| match l {
+				Log(InternalLog::grandpa(grandpa_signal)) => Some(grandpa_signal),
+				_ => None
+			}) {
+				if let Some(change) = Grandpa::scrape_digest_change(log) {
+					return Some(change);
+				}
+			}
+			None
+		}
+
+		fn grandpa_forced_change(digest: &DigestFor<Block>) -> Option<(NumberFor<Block>, ScheduledChange<NumberFor<Block>>)> {
+			for log in digest.logs.iter().filter_map(|l| match l {
+				Log(InternalLog::grandpa(grandpa_signal)) => Some(grandpa_signal),
+				_ => None
+			}) {
+				if let Some(change) = Grandpa::scrape_digest_forced_change(log) {
+					return Some(change);
+				}
+			}
+			None
+		}
+
+		fn grandpa_authorities() -> Vec<(AuthorityId, u64)> {
+			Grandpa::grandpa_authorities()
+		}
+	}
 }
@@ -0,0 +1,113 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Block execution charging the weight fee of transaction calls.
+//!
+//! `executive::Executive` charges the base and byte fees through `yee_fee`'s `MakePayment`, which
+//! only sees the encoded length of a transaction. The weight fee of its call is charged here
+//! before handing it over. Blocks are executed the way they are built, through the block building
+//! functions of `executive::Executive`, so that every extrinsic goes through the same charge.
+
+use runtime_primitives::{
+	ApplyError, ApplyResult,
+	transaction_validity::TransactionValidity,
+	traits::{Block as BlockT, Header as HeaderT, StaticLookup},
+};
+use yee_fee::Weighable;
+use crate::{AccountId, AllModules, Block, Context, Fee, Runtime, UncheckedExtrinsic};
+
+/// Code of invalid transactions `executive::Executive` reports for failures besides `ApplyError`.
+pub const UNKNOWN_ERROR: i8 = -127;
+
+type Inner = executive::Executive<Runtime, Block, Context, Fee, AllModules>;
+
+/// Executes blocks, charging signed extrinsics the weight fee of their call.
+pub struct Executive;
+
+impl Executive {
+	/// Start the execution of a block.
+	pub fn initialize_block(header: &<Block as BlockT>::Header) {
+		Inner::initialize_block(header)
+	}
+
+	/// Execute a whole block, panicking if it is invalid.
+	pub fn execute_block(block: Block) {
+		let (header, extrinsics) = block.deconstruct();
+		Inner::initialize_block(&header);
+
+		for uxt in extrinsics {
+			match Self::apply_extrinsic(uxt) {
+				Ok(_) => (),
+				Err(ApplyError::CantPay) => panic!("All extrinsics should have sender able to pay their fees"),
+				Err(ApplyError::BadSignature) => panic!("All extrinsics should be properly signed"),
+				Err(ApplyError::Stale) | Err(ApplyError::Future) => panic!("All extrinsics should have the correct nonce"),
+				Err(ApplyError::FullBlock) => panic!("Extrinsics should not exceed block limit"),
+			}
+		}
+
+		// parent hash, extrinsics root, digest and state root all as built from the extrinsics
+		let new_header = Inner::finalize_block();
+		assert!(header == new_header, "Header must match the one built from the block extrinsics.");
+	}
+
+	/// Apply an extrinsic to the block being built.
+	///
+	/// The weight fee is charged before the extrinsic is checked: an extrinsic failing afterwards
+	/// is left out of the block, its changes with it.
+	pub fn apply_extrinsic(uxt: UncheckedExtrinsic) -> ApplyResult {
+		if let Some(sender) = Self::sender(&uxt) {
+			Fee::charge_weight(&sender, uxt.function.weight()).map_err(|_| ApplyError::CantPay)?;
+		}
+		Inner::apply_extrinsic(uxt)
+	}
+
+	/// Finish the block being built.
+	pub fn finalize_block() -> <Block as BlockT>::Header {
+		Inner::finalize_block()
+	}
+
+	/// Check a transaction for the pool, charging its fees to check they can be paid.
+	///
+	/// The weight fee is charged only once the signature is checked, so that a forged transaction
+	/// is never reported as unpayable by its claimed sender.
+	pub fn validate_transaction(uxt: UncheckedExtrinsic) -> TransactionValidity {
+		let sender = Self::sender(&uxt);
+		let weight = uxt.function.weight();
+		match Inner::validate_transaction(uxt) {
+			TransactionValidity::Valid { priority, requires, provides, longevity } => {
+				if let Some(sender) = sender {
+					if Fee::charge_weight(&sender, weight).is_err() {
+						return TransactionValidity::Invalid(ApplyError::CantPay as i8);
+					}
+				}
+				TransactionValidity::Valid { priority, requires, provides, longevity }
+			},
+			validity => validity,
+		}
+	}
+
+	/// Run the offchain workers of the modules.
+	pub fn offchain_worker(number: <<Block as BlockT>::Header as HeaderT>::Number) {
+		Inner::offchain_worker(number)
+	}
+
+	/// Account a signed extrinsic claims to be sent by, if its address resolves.
+	fn sender(uxt: &UncheckedExtrinsic) -> Option<AccountId> {
+		let (address, ..) = uxt.signature.as_ref()?;
+		<Runtime as system::Trait>::Lookup::lookup(address.clone()).ok()
+	}
+}
@@ -1,7 +1,8 @@
 use primitives::{ed25519, sr25519, Pair};
 use yee_branch_runtime::{
 	AccountId, GenesisConfig, ConsensusConfig, TimestampConfig, BalancesConfig,
-	SudoConfig, IndicesConfig, RootPortConfig
+	GovernanceConfig, IndicesConfig, RootPortConfig, FeeConfig,
+	TreasuryConfig, StakingConfig, UtilityConfig, GrandpaConfig, Permill
 };
 use substrate_service;
 
@@ -47,9 +48,9 @@ impl Alternative {
 					authority_key("Alice")
 				], vec![
 					account_key("Alice")
-				],
+				], vec![
 					account_key("Alice")
-				),
+				]),
 				vec![],
 				None,
 				None,
@@ -69,9 +70,11 @@ impl Alternative {
 					account_key("Dave"),
 					account_key("Eve"),
 					account_key("Ferdie"),
-				],
+				], vec![
 					account_key("Alice"),
-				),
+					account_key("Bob"),
+					account_key("Charlie"),
+				]),
 				vec![],
 				None,
 				None,
@@ -90,13 +93,16 @@ impl Alternative {
 	}
 }
 
-fn testnet_genesis(initial_authorities: Vec<AuthorityId>, endowed_accounts: Vec<AccountId>, root_key: AccountId) -> GenesisConfig {
+fn testnet_genesis(initial_authorities: Vec<AuthorityId>, endowed_accounts: Vec<AccountId>, council: Vec<AccountId>) -> GenesisConfig {
 	GenesisConfig {
 		consensus: Some(ConsensusConfig {
 			code: include_bytes!("../runtime/wasm/target/wasm32-unknown-unknown/release/yee_branch_runtime_wasm.compact.wasm").to_vec(),
 			authorities: initial_authorities.clone(),
 		}),
 		system: None,
+		grandpa: Some(GrandpaConfig {
+			authorities: initial_authorities.iter().cloned().map(|key| (key, 1)).collect(),
+		}),
 		timestamp: Some(TimestampConfig {
 			minimum_period: 5, // 10 second block time.
 		}),
@@ -104,7 +110,7 @@ fn testnet_genesis(initial_authorities: Vec<AuthorityId>, endowed_accounts: Vec<
 			ids: endowed_accounts.clone(),
 		}),
 		balances: Some(BalancesConfig {
-			transaction_base_fee: 1,
+			transaction_base_fee: 0,
 			transaction_byte_fee: 0,
 			existential_deposit: 500,
 			transfer_fee: 0,
@@ -112,12 +118,45 @@ fn testnet_genesis(initial_authorities: Vec<AuthorityId>, endowed_accounts: Vec<
 			balances: endowed_accounts.iter().cloned().map(|k|(k, 1 << 60)).collect(),
 			vesting: vec![],
 		}),
-		sudo: Some(SudoConfig {
-			key: root_key,
+		yee_fee: Some(FeeConfig {
+			base_fee: 1_000_000,
+			byte_fee: 10_000,
+			weight_fee: 100,
+			fee_share: Permill::from_percent(20),
+			author_accounts: initial_authorities.iter().cloned().zip(endowed_accounts.iter().cloned()).collect(),
+		}),
+		yee_treasury: Some(TreasuryConfig {
+			proposal_bond: 1 << 40,
+			block_reward: 0,
+		}),
+		yee_staking: Some(StakingConfig {
+			validator_count: initial_authorities.len() as u32,
+			minimum_bond: 1 << 50,
+			era_length: 8_640, // 1 day at 10 second blocks.
+			bonding_duration: 7,
+			era_reward: 1 << 50,
+			equivocation_slash: Permill::from_percent(10),
+			offline_slash: Permill::from_percent(1),
+			offline_slash_grace: 100,
+			stakers: endowed_accounts.iter().cloned().zip(initial_authorities.iter().cloned())
+				.map(|(account, key)| (account, 1 << 50, key))
+				.collect(),
+		}),
+		yee_governance: Some(GovernanceConfig {
+			members: council,
+			threshold: Permill::from_percent(50),
+			voting_period: 8_640, // 1 day at 10 second blocks.
+			enactment_delay: 100,
+		}),
+		yee_utility: Some(UtilityConfig {
+			multisig_deposit: 1 << 40,
 		}),
 		yee_root_port: Some(RootPortConfig {
 			genesis_owner_session_key: initial_authorities.get(0).unwrap().clone(),
 			genesis_sharding_count: 4,
+			checkpoint_interval: 60, // 10 minutes at 10 second blocks.
+			dispute_window: 360, // 1 hour at 10 second blocks.
+			forced_inclusion_period: 30, // 5 minutes at 10 second blocks.
 		}),
 
 	}
@@ -2,7 +2,7 @@ use crate::service;
 use futures::{future, Future, sync::oneshot};
 use tokio::runtime::Runtime;
 pub use substrate_cli::{VersionInfo, IntoExit, error};
-use substrate_cli::{informant, parse_and_execute, NoCustom, TriggerExit};
+use substrate_cli::{informant, parse_and_execute, TriggerExit};
 use substrate_service::{ServiceFactory, Roles as ServiceRoles, Arc};
 use crate::chain_spec;
 use std::ops::Deref;
@@ -11,9 +11,12 @@ use parking_lot::Mutex;
 use futures::sync::oneshot::Sender;
 use signal_hook::{iterator::Signals, SIGUSR1, SIGINT, SIGTERM};
 use std::thread;
+use std::path::Path;
 use std::fmt::Debug;
 use crate::custom_param::{YeeCliConfig, process_custom_args};
 use crate::dev_param::process_dev_param;
+use crate::custom_command::{self, CustomCommand};
+use crate::config_file;
 
 /// Parse command line arguments into service configuration.
 pub fn run<I, T, E>(args: I, exit: E, version: VersionInfo) -> error::Result<()> where
@@ -21,7 +24,10 @@ pub fn run<I, T, E>(args: I, exit: E, version: VersionInfo) -> error::Result<()>
 	T: Into<std::ffi::OsString> + Clone,
 	E: IntoExit<TriggerExit=CliTriggerExit<CliSignal>>,
 {
-	parse_and_execute::<service::Factory, NoCustom, YeeCliConfig, _, _, _, _, _>(
+	let (args, dump_config) = config_file::route_dump(args.into_iter().map(Into::into).collect());
+	let args = config_file::apply(args)?;
+
+	let command = parse_and_execute::<service::Factory, CustomCommand, YeeCliConfig, _, _, _, _, _>(
 		load_spec, &version, service::IMPL_NAME, args, exit,
 	 	|exit, mut custom_args, mut config| {
 			info!("{}", version.name);
@@ -34,6 +40,13 @@ pub fn run<I, T, E>(args: I, exit: E, version: VersionInfo) -> error::Result<()>
 		    process_dev_param::<service::Factory>(&mut config, &mut custom_args).map_err(|e| format!("{:?}", e))?;
 		    process_custom_args::<service::Factory>(&mut config, &custom_args, &version).map_err(|e| format!("{:?}", e))?;
 
+		    if dump_config {
+		        return config_file::dump(&config).map_err(|e| format!("{}", e));
+		    }
+
+		    let chain_path = Path::new(&config.database_path).parent().unwrap_or(Path::new(&config.database_path)).to_path_buf();
+		    yee_root_chain::check_layout(&chain_path, &config.chain_spec).map_err(|e| format!("{}", e))?;
+
 		    let (exit, trigger_exit) = exit.into_exit();
 
 		    config.custom.trigger_exit = Some(Arc::new(trigger_exit));
@@ -53,10 +66,15 @@ pub fn run<I, T, E>(args: I, exit: E, version: VersionInfo) -> error::Result<()>
 				),
 			}.map_err(|e| format!("{:?}", e))
 		}
-	).map_err(Into::into).map(|_| ())
+	)?;
+
+	match command {
+		Some(command) => custom_command::run(command, &version),
+		None => Ok(()),
+	}
 }
 
-fn load_spec(id: &str) -> Result<Option<chain_spec::ChainSpec>, String> {
+pub fn load_spec(id: &str) -> Result<Option<chain_spec::ChainSpec>, String> {
 	Ok(match chain_spec::Alternative::from(id) {
 		Some(spec) => Some(spec.load()?),
 		None => None,
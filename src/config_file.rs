@@ -0,0 +1,307 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Node options from a TOML file given with `--config`.
+//!
+//! Keys are the long names of the command line options, at the top level or grouped in tables
+//! like `[node]`, `[root]`, `[pool]` and `[dev]`:
+//!
+//! ```toml
+//! [node]
+//! name = "branch-0"
+//! base-path = "/data/yee-branch"
+//!
+//! [root]
+//! bootnodes-routers = ["http://router.example:50001"]
+//! foreign-port = 31333
+//! ```
+//!
+//! Options are turned into command line arguments, which take precedence when given both ways.
+
+use std::ffi::OsString;
+use std::fs;
+use serde::Serialize;
+use structopt::{StructOpt, clap::ArgMatches};
+use substrate_cli::CoreParams;
+use substrate_service::FactoryFullConfiguration;
+use toml::Value;
+use crate::custom_command::CustomCommand;
+use crate::custom_param::YeeCliConfig;
+use crate::error;
+use crate::service::Factory;
+
+/// Add the options of the `--config` file missing from the command line arguments of a node run.
+pub fn apply(args: Vec<OsString>) -> error::Result<Vec<OsString>> {
+	let path = match config_path(&args) {
+		Some(path) => path,
+		None => return Ok(args),
+	};
+	// options of the file are node run options, subcommands have their own
+	if args.get(1).and_then(|arg| arg.to_str()).map_or(false, |arg| !arg.starts_with('-')) {
+		return Ok(args);
+	}
+
+	let content = fs::read_to_string(&path).map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+	merge(args, &content).map_err(|e| format!("Invalid config file {}: {}", path, e).into())
+}
+
+/// Add the options of the config file content missing from the command line arguments.
+fn merge(mut args: Vec<OsString>, content: &str) -> error::Result<Vec<OsString>> {
+	let table = match content.parse::<Value>().map_err(|e| format!("{}", e))? {
+		Value::Table(table) => table,
+		_ => return Err("not a table".into()),
+	};
+
+	let mut options = Vec::new();
+	collect_options(&table, &mut options)?;
+
+	// clap knows the short and long forms of every option, invalid arguments are reported by the run
+	let matches = CoreParams::<CustomCommand, YeeCliConfig>::clap().get_matches_from_safe(args.iter()).ok();
+	let skipped = options.iter()
+		.filter(|(name, _)| name == "config" || given(matches.as_ref(), &args, name))
+		.map(|(name, _)| name.clone())
+		.collect::<Vec<_>>();
+
+	for (name, value) in options {
+		if skipped.contains(&name) {
+			continue;
+		}
+		match value {
+			Some(value) => args.push(format!("--{}={}", name, value).into()),
+			None => args.push(format!("--{}", name).into()),
+		}
+	}
+	Ok(args)
+}
+
+/// Route `config dump` to a node run printing its configuration, returning whether to dump.
+pub fn route_dump(mut args: Vec<OsString>) -> (Vec<OsString>, bool) {
+	let dump = args.get(1).map_or(false, |arg| arg == "config") && args.get(2).map_or(false, |arg| arg == "dump");
+	if dump {
+		args.drain(1..3);
+	}
+	(args, dump)
+}
+
+/// Print the effective configuration of the node as TOML.
+pub fn dump(config: &FactoryFullConfiguration<Factory>) -> error::Result<()> {
+	let pool = crate::tx_pool::policy();
+	let effective = EffectiveConfig {
+		node: NodeSection {
+			name: config.name.clone(),
+			chain: config.chain_spec.id().to_string(),
+			roles: format!("{:?}", config.roles),
+			database_path: config.database_path.clone(),
+			keystore_path: config.keystore_path.clone(),
+			listen_addresses: config.network.listen_addresses.iter().map(ToString::to_string).collect(),
+			boot_nodes: config.network.boot_nodes.clone(),
+			finality: format!("{:?}", config.custom.finality),
+			sync_mode: format!("{:?}", config.custom.sync_mode),
+			signer: config.custom.signer.as_ref().map(|signer| format!("{:?}", signer)),
+		},
+		root: RootSection {
+			name: crate::service::root_name(config),
+			foreign_port: config.custom.root_port,
+			public_addresses: config.custom.root_network.public_addresses.clone(),
+			reserved_nodes: config.custom.root_network.reserved_nodes.clone(),
+			reserved_only: config.custom.root_network.reserved_only,
+			max_peers: config.custom.root_network.max_peers,
+			node_key_file: config.custom.root_network.node_key_file.as_ref().map(|file| file.to_string_lossy().into_owned()),
+			no_mdns: config.custom.root_network.no_mdns,
+			telemetry: config.custom.root_telemetry,
+			base_path: crate::service::root_base_path(config).to_string_lossy().into_owned(),
+			bootnodes_router_conf: config.custom.root_bootnodes_router_conf.as_ref().map(|conf| format!("{:?}", conf)),
+		},
+		pool: PoolSection {
+			pool_ready_limit: pool.ready_limit,
+			pool_ready_kbytes: pool.ready_bytes / 1024,
+			pool_future_limit: pool.future_limit,
+			pool_future_kbytes: pool.future_bytes / 1024,
+			pool_sender_limit: pool.sender_limit,
+			pool_ban_threshold: pool.ban_threshold,
+			pool_ban_duration: pool.ban_duration,
+		},
+	};
+	print!("{}", toml::to_string(&effective).map_err(|e| format!("{:?}", e))?);
+	Ok(())
+}
+
+#[derive(Serialize)]
+struct EffectiveConfig {
+	node: NodeSection,
+	root: RootSection,
+	pool: PoolSection,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct NodeSection {
+	name: String,
+	chain: String,
+	roles: String,
+	database_path: String,
+	keystore_path: String,
+	listen_addresses: Vec<String>,
+	boot_nodes: Vec<String>,
+	finality: String,
+	sync_mode: String,
+	signer: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct RootSection {
+	name: String,
+	foreign_port: Option<u16>,
+	public_addresses: Vec<String>,
+	reserved_nodes: Vec<String>,
+	reserved_only: bool,
+	max_peers: Option<u32>,
+	node_key_file: Option<String>,
+	no_mdns: bool,
+	telemetry: bool,
+	base_path: String,
+	bootnodes_router_conf: Option<String>,
+}
+
+/// Named after the command line options, so that the section can be used as a config file.
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct PoolSection {
+	pool_ready_limit: usize,
+	pool_ready_kbytes: usize,
+	pool_future_limit: usize,
+	pool_future_kbytes: usize,
+	pool_sender_limit: u64,
+	pool_ban_threshold: u32,
+	pool_ban_duration: u64,
+}
+
+fn config_path(args: &[OsString]) -> Option<String> {
+	let args = args.iter().filter_map(|arg| arg.to_str()).collect::<Vec<_>>();
+	for (i, arg) in args.iter().enumerate() {
+		if arg.starts_with("--config=") {
+			return Some(arg["--config=".len()..].to_string());
+		}
+		if *arg == "--config" {
+			return args.get(i + 1).map(|path| path.to_string());
+		}
+	}
+	None
+}
+
+/// Options of the table, nested tables being groups of options: a value for `--name=value`
+/// or none for a `--name` flag.
+fn collect_options(table: &toml::value::Table, options: &mut Vec<(String, Option<String>)>) -> error::Result<()> {
+	for (name, value) in table {
+		match value {
+			Value::Table(table) => collect_options(table, options)?,
+			Value::Array(values) => {
+				for value in values {
+					options.push((name.clone(), Some(scalar(name, value)?)));
+				}
+			},
+			Value::Boolean(true) => options.push((name.clone(), None)),
+			Value::Boolean(false) => (),
+			value => options.push((name.clone(), Some(scalar(name, value)?))),
+		}
+	}
+	Ok(())
+}
+
+fn scalar(name: &str, value: &Value) -> error::Result<String> {
+	match value {
+		Value::String(s) => Ok(s.clone()),
+		Value::Integer(i) => Ok(i.to_string()),
+		Value::Float(f) => Ok(f.to_string()),
+		_ => Err(format!("Invalid value of config option {}", name).into()),
+	}
+}
+
+/// Whether the option is on the command line, in any of its forms.
+fn given(matches: Option<&ArgMatches>, args: &[OsString], name: &str) -> bool {
+	// options are named after their fields, their long names being the kebab case of the fields
+	if matches.map_or(false, |matches| matches.occurrences_of(name.replace('-', "_")) > 0) {
+		return true;
+	}
+	let flag = format!("--{}", name);
+	let prefix = format!("--{}=", name);
+	args.iter().filter_map(|arg| arg.to_str()).any(|arg| arg == flag || arg.starts_with(&prefix))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn args(args: &[&str]) -> Vec<OsString> {
+		args.iter().map(OsString::from).collect()
+	}
+
+	fn options(content: &str) -> error::Result<Vec<(String, Option<String>)>> {
+		let mut options = Vec::new();
+		match content.parse::<Value>().unwrap() {
+			Value::Table(table) => collect_options(&table, &mut options)?,
+			_ => unreachable!(),
+		}
+		Ok(options)
+	}
+
+	#[test]
+	fn options_are_collected_from_tables() {
+		let options = options(r#"
+			name = "branch-0"
+			[root]
+			bootnodes-routers = ["http://a", "http://b"]
+			foreign-port = 31333
+			no-mdns = true
+			reserved-only = false
+		"#).unwrap();
+		assert_eq!(options, vec![
+			("name".to_string(), Some("branch-0".to_string())),
+			("bootnodes-routers".to_string(), Some("http://a".to_string())),
+			("bootnodes-routers".to_string(), Some("http://b".to_string())),
+			("foreign-port".to_string(), Some("31333".to_string())),
+			("no-mdns".to_string(), None),
+		]);
+	}
+
+	#[test]
+	fn nested_values_are_rejected() {
+		assert!(options("name = [[1]]").is_err());
+		assert!(options("name = 1979-05-27T07:32:00Z").is_err());
+	}
+
+	#[test]
+	fn command_line_takes_precedence() {
+		let merged = merge(
+			args(&["yee-branch", "--config=node.toml", "-d", "/cli", "--name=cli"]),
+			"[node]\nbase-path = \"/file\"\nname = \"file\"\nport = 30334\n",
+		).unwrap();
+		assert_eq!(merged, args(&["yee-branch", "--config=node.toml", "-d", "/cli", "--name=cli", "--port=30334"]));
+	}
+
+	#[test]
+	fn invalid_files_are_rejected() {
+		assert!(merge(args(&["yee-branch"]), "name = ").is_err());
+	}
+
+	#[test]
+	fn subcommands_are_left_alone() {
+		let args = args(&["yee-branch", "purge-chain", "--config=missing.toml"]);
+		assert_eq!(apply(args.clone()).unwrap(), args);
+	}
+}
@@ -0,0 +1,597 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Subcommands of the branch node on top of the substrate ones.
+
+use std::fs;
+use std::io::{self, stdin, stdout, Write};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+use substrate_cli::{GetLogFilter, SharedParams, VersionInfo};
+use substrate_service::{FactoryFullConfiguration, ChainSpec};
+use substrate_client::genesis::construct_genesis_block;
+use parity_codec::Encode;
+use primitives::{Blake2Hasher, sr25519, crypto::Ss58Codec, hexdisplay::HexDisplay, Pair};
+use runtime_primitives::{BuildStorage, traits::{Block as BlockT, Header as HeaderT}};
+use state_machine::{Backend as StateBackend, InMemory};
+use serde_json::{json, Value};
+use yee_branch_runtime::{AccountIndex, Balance, BlockNumber, Hash, Nonce, opaque::{Block, BlockId}};
+use yee_branch_tx::TransactionBuilder;
+use crate::error;
+use crate::cli::load_spec;
+use crate::service::Factory;
+use crate::snapshot;
+use crate::keystore::{Keystore, KeyRole, parse_public, public_of};
+use crate::signer::{self, Endpoint};
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum CustomCommand {
+	/// Export the branch state at a block, with the root chain databases, to a snapshot file
+	#[structopt(name = "export-state")]
+	ExportState(ExportStateCmd),
+
+	/// Import a state snapshot into the branch and root chain databases
+	#[structopt(name = "import-state")]
+	ImportState(ImportStateCmd),
+
+	/// Remove the root chain databases of the branch
+	#[structopt(name = "purge-root-chain")]
+	PurgeRootChain(PurgeRootChainCmd),
+
+	/// Manage the keys of the branch keystore
+	#[structopt(name = "key")]
+	Key(KeyCmd),
+
+	/// Run a reference remote signer serving the authority and owner keys of the keystore
+	#[structopt(name = "signer")]
+	Signer(SignerCmd),
+
+	/// Build, sign and submit transactions to a node
+	#[structopt(name = "tx")]
+	Tx(TxCmd),
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct ExportStateCmd {
+	/// Snapshot file to write
+	#[structopt(parse(from_os_str))]
+	pub output: PathBuf,
+
+	/// Block number or hash of the state, the best block if not given
+	#[structopt(long = "at", value_name = "BLOCK")]
+	pub at: Option<String>,
+
+	/// Base path of the root chain databases, the branch base path by default
+	#[structopt(long = "root-base-path", value_name = "PATH", parse(from_os_str))]
+	pub root_base_path: Option<PathBuf>,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: SharedParams,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct ImportStateCmd {
+	/// Snapshot file to read
+	#[structopt(parse(from_os_str))]
+	pub input: PathBuf,
+
+	/// Base path of the root chain databases, the branch base path by default
+	#[structopt(long = "root-base-path", value_name = "PATH", parse(from_os_str))]
+	pub root_base_path: Option<PathBuf>,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: SharedParams,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct PurgeRootChainCmd {
+	/// Skip interactive prompt by answering yes automatically
+	#[structopt(short = "y")]
+	pub yes: bool,
+
+	/// Base path of the root chain databases, the branch base path by default
+	#[structopt(long = "root-base-path", value_name = "PATH", parse(from_os_str))]
+	pub root_base_path: Option<PathBuf>,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: SharedParams,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum KeyCmd {
+	/// Generate a key into the keystore and print its mnemonic phrase
+	#[structopt(name = "generate")]
+	Generate(KeyParams),
+
+	/// Insert the key of a secret URI into the keystore
+	#[structopt(name = "insert")]
+	Insert(KeyInsertCmd),
+
+	/// List the keys of the keystore
+	#[structopt(name = "list")]
+	List(KeyListCmd),
+
+	/// Print the public key of a secret URI, or check the password of a stored key
+	#[structopt(name = "inspect")]
+	Inspect(KeyInspectCmd),
+
+	/// Remove a key from the keystore
+	#[structopt(name = "remove")]
+	Remove(KeyRemoveCmd),
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct KeyParams {
+	/// Role of the key: authority (ed25519), account (sr25519), owner (ed25519 root-port owner session key) or relayer (sr25519 root chain account relaying meta transactions)
+	#[structopt(long = "role", value_name = "ROLE", default_value = "authority")]
+	pub role: KeyRole,
+
+	/// Password the key is derived with
+	#[structopt(long = "password", value_name = "PASSWORD")]
+	pub password: Option<String>,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: SharedParams,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct KeyInsertCmd {
+	/// Secret URI of the key: mnemonic phrase, hex seed or derivation like //Alice
+	#[structopt(long = "suri", value_name = "SURI")]
+	pub suri: String,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub key_params: KeyParams,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct KeyListCmd {
+	/// Only list the keys of the role
+	#[structopt(long = "role", value_name = "ROLE")]
+	pub role: Option<KeyRole>,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: SharedParams,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct KeyInspectCmd {
+	/// Secret URI, or hex public key of a stored key
+	pub key: String,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub key_params: KeyParams,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct KeyRemoveCmd {
+	/// Hex public key of the key
+	pub public: String,
+
+	/// Skip interactive prompt by answering yes automatically
+	#[structopt(short = "y")]
+	pub yes: bool,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub key_params: KeyParams,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct SignerCmd {
+	/// Where to listen: unix://<path> or http://<host:port>
+	#[structopt(long = "listen", value_name = "URL")]
+	pub listen: Endpoint,
+
+	/// Password of the role-tagged keystore keys, like the owner key
+	#[structopt(long = "key-password", value_name = "PASSWORD")]
+	pub key_password: Option<String>,
+
+	/// File holding the token clients must present, created if missing, `signer/token` in the chain directory by default
+	#[structopt(long = "token-file", value_name = "FILE", parse(from_os_str))]
+	pub token_file: Option<PathBuf>,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: SharedParams,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum TxCmd {
+	/// Transfer balance to an address
+	#[structopt(name = "transfer")]
+	Transfer(TxTransferCmd),
+
+	/// Send an encoded runtime call
+	#[structopt(name = "call")]
+	Call(TxCallCmd),
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct TxTransferCmd {
+	/// Recipient: account index, SS58 or hex account ID
+	#[structopt(long = "to", value_name = "ADDRESS")]
+	pub to: String,
+
+	/// Amount to transfer
+	#[structopt(long = "amount", value_name = "AMOUNT")]
+	pub amount: Balance,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub tx_params: TxParams,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct TxCallCmd {
+	/// Hex encoded call
+	pub call: String,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub tx_params: TxParams,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct TxParams {
+	/// Secret URI of the sender key, an account key of the keystore by default
+	#[structopt(long = "suri", value_name = "SURI")]
+	pub suri: Option<String>,
+
+	/// Hex public key of the keystore account key, the first one by default
+	#[structopt(long = "key", value_name = "PUBLIC")]
+	pub key: Option<String>,
+
+	/// Password the key is derived with
+	#[structopt(long = "password", value_name = "PASSWORD")]
+	pub password: Option<String>,
+
+	/// Send from the account index instead of the account ID
+	#[structopt(long = "index", value_name = "INDEX")]
+	pub index: Option<AccountIndex>,
+
+	/// Nonce of the transaction, the next nonce of the sender on the node by default
+	#[structopt(long = "nonce", value_name = "NONCE")]
+	pub nonce: Option<Nonce>,
+
+	/// Number of blocks the transaction stays valid for, 0 for an immortal transaction
+	#[structopt(long = "era-period", value_name = "BLOCKS", default_value = "64")]
+	pub era_period: u64,
+
+	/// JSON-RPC endpoint of the node: http://<host:port> or unix://<path>
+	#[structopt(long = "rpc", value_name = "URL", default_value = "http://127.0.0.1:9933")]
+	pub rpc: Endpoint,
+
+	/// Print the signed transaction instead of submitting it
+	#[structopt(long = "print-only")]
+	pub print_only: bool,
+
+	#[allow(missing_docs)]
+	#[structopt(flatten)]
+	pub shared_params: SharedParams,
+}
+
+impl TxCmd {
+	fn tx_params(&self) -> &TxParams {
+		match self {
+			TxCmd::Transfer(cmd) => &cmd.tx_params,
+			TxCmd::Call(cmd) => &cmd.tx_params,
+		}
+	}
+}
+
+impl KeyCmd {
+	fn shared_params(&self) -> &SharedParams {
+		match self {
+			KeyCmd::Generate(cmd) => &cmd.shared_params,
+			KeyCmd::Insert(cmd) => &cmd.key_params.shared_params,
+			KeyCmd::List(cmd) => &cmd.shared_params,
+			KeyCmd::Inspect(cmd) => &cmd.key_params.shared_params,
+			KeyCmd::Remove(cmd) => &cmd.key_params.shared_params,
+		}
+	}
+}
+
+impl GetLogFilter for CustomCommand {
+	fn get_log_filter(&self) -> Option<String> {
+		match self {
+			CustomCommand::ExportState(cmd) => cmd.shared_params.log.clone(),
+			CustomCommand::ImportState(cmd) => cmd.shared_params.log.clone(),
+			CustomCommand::PurgeRootChain(cmd) => cmd.shared_params.log.clone(),
+			CustomCommand::Key(cmd) => cmd.shared_params().log.clone(),
+			CustomCommand::Signer(cmd) => cmd.shared_params.log.clone(),
+			CustomCommand::Tx(cmd) => cmd.tx_params().shared_params.log.clone(),
+		}
+	}
+}
+
+/// Run the subcommand.
+pub fn run(command: CustomCommand, version: &VersionInfo) -> error::Result<()> {
+	match command {
+		CustomCommand::ExportState(cmd) => {
+			let config = create_config(&cmd.shared_params, version)?;
+			let root_base_path = root_base_path(&config, &cmd.root_base_path);
+			let at = cmd.at.as_ref().map(|at| parse_block_id(at)).transpose()?;
+			snapshot::export_state(config, &root_base_path, at, &cmd.output)
+		},
+		CustomCommand::ImportState(cmd) => {
+			let config = create_config(&cmd.shared_params, version)?;
+			let root_base_path = root_base_path(&config, &cmd.root_base_path);
+			snapshot::import_state_snapshot(config, &root_base_path, &cmd.input)
+		},
+		CustomCommand::PurgeRootChain(cmd) => {
+			let config = create_config(&cmd.shared_params, version)?;
+			let root_base_path = root_base_path(&config, &cmd.root_base_path);
+			purge_root_chain(config, &root_base_path, cmd.yes)
+		},
+		CustomCommand::Key(cmd) => {
+			let config = create_config(cmd.shared_params(), version)?;
+			let keystore = Keystore::open(config.keystore_path.clone())?;
+			run_key(cmd, &keystore)
+		},
+		CustomCommand::Signer(cmd) => {
+			let config = create_config(&cmd.shared_params, version)?;
+			let keystore = Keystore::open(config.keystore_path.clone())?;
+			let password = cmd.key_password.as_ref().map(String::as_str);
+			let keys = keystore.list()?.into_iter()
+				.filter(|(role, _)| role.scheme() == "ed25519")
+				.map(|(role, public)| match role {
+					// authority keys are derived without password, like the service loads them
+					KeyRole::Authority => keystore.load_ed25519(role, &public, None),
+					_ => keystore.load_ed25519(role, &public, password),
+				})
+				.collect::<error::Result<Vec<_>>>()?;
+			// next to the keystore, in the chain directory
+			let signer_path = PathBuf::from(&config.keystore_path).with_file_name("signer");
+			let token = signer::daemon_token(&cmd.token_file.clone().unwrap_or_else(|| signer_path.join("token")))?;
+			fs::create_dir_all(&signer_path)?;
+			let daemon = signer::Daemon::new(keys, token, signer_path.join("slots"))?;
+			signer::serve(&cmd.listen, daemon)
+		},
+		CustomCommand::Tx(cmd) => run_tx(cmd, version),
+	}
+}
+
+fn run_tx(command: TxCmd, version: &VersionInfo) -> error::Result<()> {
+	let (call, params) = match command {
+		TxCmd::Transfer(cmd) => {
+			let dest = yee_branch_tx::parse_address(&cmd.to).map_err(|e| format!("{}", e))?;
+			(yee_branch_tx::transfer(dest, cmd.amount), cmd.tx_params)
+		},
+		TxCmd::Call(cmd) => {
+			let call = yee_branch_tx::decode_call(&signer::parse_hex(&cmd.call)?).map_err(|e| format!("{}", e))?;
+			(call, cmd.tx_params)
+		},
+	};
+	let pair = tx_pair(&params, version)?;
+	let rpc = &params.rpc;
+
+	let genesis_hash = parse_hash(&rpc.call("chain_getBlockHash", json!([0]))?)?;
+	let nonce = match params.nonce {
+		Some(nonce) => nonce,
+		None => rpc.call("account_nextIndex", json!([pair.public().to_ss58check()]))?
+			.as_u64().ok_or("Invalid account nonce")?,
+	};
+
+	let mut builder = TransactionBuilder::new(call).nonce(nonce);
+	if params.era_period > 0 {
+		// the finalized head can not be reverted, dropping the era with its birth block
+		let finalized_hash = parse_hash(&rpc.call("chain_getFinalizedHead", json!([]))?)?;
+		let header = rpc.call("chain_getHeader", json!([format!("{:?}", finalized_hash)]))?;
+		let number = header.get("number").and_then(parse_number).ok_or("Invalid block header")?;
+		let birth = yee_branch_tx::era_birth(params.era_period, number);
+		let birth_hash = parse_hash(&rpc.call("chain_getBlockHash", json!([birth]))?)?;
+		builder = builder.mortal(params.era_period, birth, birth_hash);
+	}
+	if let Some(index) = params.index {
+		builder = builder.index(index);
+	}
+	let xt = builder.sign(&pair, genesis_hash).map_err(|e| format!("{}", e))?;
+	let encoded = format!("0x{}", HexDisplay::from(&xt.encode()));
+
+	if params.print_only {
+		println!("{}", encoded);
+		return Ok(());
+	}
+	let hash = rpc.call("author_submitExtrinsic", json!([encoded]))?;
+	println!("Submitted transaction {} with nonce {}", hash.as_str().unwrap_or_default(), nonce);
+	Ok(())
+}
+
+/// Sender key of the transaction: the secret URI, or an account key of the keystore.
+fn tx_pair(params: &TxParams, version: &VersionInfo) -> error::Result<sr25519::Pair> {
+	let password = params.password.as_ref().map(String::as_str);
+	if let Some(ref suri) = params.suri {
+		return sr25519::Pair::from_string(suri, password).map_err(|e| format!("Invalid secret URI: {:?}", e).into());
+	}
+
+	let config = create_config(&params.shared_params, version)?;
+	let keystore = Keystore::open(config.keystore_path.clone())?;
+	let key = params.key.as_ref().map(|key| parse_public(key)).transpose()?;
+	let public = keystore.list()?.into_iter()
+		.filter(|(role, _)| *role == KeyRole::Account)
+		.map(|(_, public)| public)
+		.find(|public| key.map_or(true, |key| key == *public))
+		.ok_or("No account key in the keystore, give one with --suri")?;
+	keystore.load_sr25519(KeyRole::Account, &public, password)
+}
+
+fn parse_hash(value: &Value) -> error::Result<Hash> {
+	value.as_str()
+		.and_then(|hash| hash.trim_start_matches("0x").parse::<Hash>().ok())
+		.ok_or_else(|| format!("Invalid block hash: {}", value).into())
+}
+
+/// Block number of a header, a JSON number or a hex string.
+fn parse_number(value: &Value) -> Option<BlockNumber> {
+	value.as_u64().or_else(|| value.as_str().and_then(|n| BlockNumber::from_str_radix(n.trim_start_matches("0x"), 16).ok()))
+}
+
+fn run_key(command: KeyCmd, keystore: &Keystore) -> error::Result<()> {
+	match command {
+		KeyCmd::Generate(cmd) => {
+			let (public, phrase) = keystore.generate(cmd.role, cmd.password.as_ref().map(String::as_str))?;
+			println!("Generated {} key ({})", cmd.role, cmd.role.scheme());
+			print_public(&public);
+			println!("Secret phrase: {}", phrase);
+		},
+		KeyCmd::Insert(cmd) => {
+			let params = cmd.key_params;
+			let public = keystore.insert(params.role, &cmd.suri, params.password.as_ref().map(String::as_str))?;
+			println!("Inserted {} key ({})", params.role, params.role.scheme());
+			print_public(&public);
+		},
+		KeyCmd::List(cmd) => {
+			for (role, public) in keystore.list()? {
+				if cmd.role.map_or(true, |r| r == role) {
+					println!("{:<9} {:<7} 0x{}", role, role.scheme(), HexDisplay::from(&public));
+				}
+			}
+		},
+		KeyCmd::Inspect(cmd) => {
+			let params = cmd.key_params;
+			let password = params.password.as_ref().map(String::as_str);
+			match parse_public(&cmd.key) {
+				Ok(public) => {
+					match params.role.scheme() {
+						"ed25519" => keystore.load_ed25519(params.role, &public, password).map(|_| ())?,
+						_ => keystore.load_sr25519(params.role, &public, password).map(|_| ())?,
+					}
+					println!("Stored {} key ({}), password matches", params.role, params.role.scheme());
+					print_public(&public);
+				},
+				Err(_) => {
+					let public = public_of(params.role, &cmd.key, password)?;
+					println!("{} key ({})", params.role, params.role.scheme());
+					print_public(&public);
+				},
+			}
+		},
+		KeyCmd::Remove(cmd) => {
+			let params = cmd.key_params;
+			let public = parse_public(&cmd.public)?;
+			if !cmd.yes && !confirm(&format!("Are you sure to remove {} key 0x{}?", params.role, HexDisplay::from(&public)))? {
+				println!("Aborted");
+				return Ok(());
+			}
+			if keystore.remove(params.role, &public)? {
+				println!("Removed {} key 0x{}", params.role, HexDisplay::from(&public));
+			} else {
+				eprintln!("No {} key 0x{}", params.role, HexDisplay::from(&public));
+			}
+		},
+	}
+	Ok(())
+}
+
+fn print_public(public: &[u8; 32]) {
+	println!("Public key (hex): 0x{}", HexDisplay::from(public));
+	println!("Address (SS58): {}", sr25519::Public(*public).to_ss58check());
+}
+
+fn confirm(question: &str) -> error::Result<bool> {
+	print!("{} (y/n)", question);
+	stdout().flush()?;
+	let mut input = String::new();
+	stdin().read_line(&mut input)?;
+	Ok(match input.chars().nth(0) {
+		Some('y') | Some('Y') => true,
+		_ => false,
+	})
+}
+
+/// Configuration of the branch chain, with the database and keystore paths the node uses.
+fn create_config(shared_params: &SharedParams, version: &VersionInfo) -> error::Result<FactoryFullConfiguration<Factory>> {
+	let chain_key = if shared_params.dev {
+		"dev".to_string()
+	} else {
+		shared_params.chain.clone().unwrap_or_default()
+	};
+	let spec = match load_spec(&chain_key)? {
+		Some(spec) => spec,
+		None => ChainSpec::from_json_file(PathBuf::from(chain_key))?,
+	};
+
+	let base_path = match shared_params.base_path {
+		Some(ref base_path) => base_path.clone(),
+		None => app_dirs::get_app_root(
+			app_dirs::AppDataType::UserData,
+			&app_dirs::AppInfo { name: version.executable_name, author: version.author },
+		).map_err(|e| format!("{:?}", e))?,
+	};
+
+	let mut config = FactoryFullConfiguration::<Factory>::default_with_spec(spec);
+	let chain_path = base_path.join("chains").join(config.chain_spec.id());
+	config.database_path = chain_path.join("db").to_string_lossy().into();
+	config.keystore_path = chain_path.join("keystore").to_string_lossy().into();
+
+	Ok(config)
+}
+
+fn root_base_path(config: &FactoryFullConfiguration<Factory>, root_base_path: &Option<PathBuf>) -> PathBuf {
+	root_base_path.clone().unwrap_or_else(|| yee_root_chain::default_root_base_path(&config.database_path))
+}
+
+/// Hash of the genesis block of the chain spec, built from its storage as the client builds
+/// it, so that the branch databases are neither read nor created.
+fn genesis_hash(config: &FactoryFullConfiguration<Factory>) -> error::Result<Hash> {
+	let (storage, children) = config.chain_spec.clone().build_storage()?;
+	if !children.is_empty() {
+		return Err("Chain spec genesis with child storage".into());
+	}
+	let (state_root, _) = InMemory::<Blake2Hasher>::from(storage).storage_root(Vec::new());
+	Ok(construct_genesis_block::<Block>(state_root).header().hash())
+}
+
+fn purge_root_chain(config: FactoryFullConfiguration<Factory>, root_base_path: &Path, yes: bool) -> error::Result<()> {
+	let genesis_hash = genesis_hash(&config)?;
+	let db_path = yee_root_chain::root_database_path(root_base_path, genesis_hash.as_ref());
+
+	if !yes && !confirm(&format!("Are you sure to remove {:?}?", &db_path))? {
+		println!("Aborted");
+		return Ok(());
+	}
+
+	match fs::remove_dir_all(&db_path) {
+		Ok(()) => {
+			println!("{:?} removed.", &db_path);
+			Ok(())
+		},
+		Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+			eprintln!("{:?} did not exist.", &db_path);
+			Ok(())
+		},
+		Err(e) => Err(e.into()),
+	}
+}
+
+fn parse_block_id(block: &str) -> error::Result<BlockId> {
+	if block.starts_with("0x") {
+		let hash = block[2..].parse::<Hash>().map_err(|e| format!("Invalid block hash {}: {:?}", block, e))?;
+		Ok(BlockId::hash(hash))
+	} else {
+		let number = block.parse::<u64>().map_err(|e| format!("Invalid block number {}: {:?}", block, e))?;
+		Ok(BlockId::number(number))
+	}
+}
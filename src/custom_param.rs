@@ -15,6 +15,7 @@
 // You should have received a copy of the GNU General Public License
 // along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::path::PathBuf;
 use {
     structopt::StructOpt,
     substrate_cli::{impl_augment_clap},
@@ -22,7 +23,12 @@ use {
 use log::{info, warn};
 use substrate_service::{FactoryFullConfiguration, ServiceFactory};
 use crate::error;
-use crate::service::{NodeConfig};
+use crate::service::{NodeConfig, Finality};
+use crate::warp_sync::SyncMode;
+use crate::signer::{self, Endpoint};
+use crate::keystore::parse_public;
+use crate::tx_pool::{self, PoolPolicy};
+use primitives::ed25519::Public;
 use yee_bootnodes_router;
 use yee_bootnodes_router::BootnodesRouterConf;
 use substrate_cli::VersionInfo;
@@ -30,6 +36,10 @@ use substrate_cli::VersionInfo;
 #[derive(Clone, Debug, Default, StructOpt)]
 pub struct YeeCliConfig {
 
+    /// Load node, root chain and dev options from a TOML file, command line options take precedence
+    #[structopt(long = "config", value_name = "FILE", parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
     /// Specify a list of root chain bootnodes-routers
     #[structopt(long = "bootnodes-routers", value_name = "URL")]
     pub root_bootnodes_routers: Vec<String>,
@@ -38,10 +48,103 @@ pub struct YeeCliConfig {
     #[structopt(long = "foreign-port", value_name = "PORT")]
     pub root_port: Option<u16>,
 
+    /// Specify the addresses of the root node advertised to peers
+    #[structopt(long = "root-public-addr", value_name = "ADDR")]
+    pub root_public_addresses: Vec<String>,
+
+    /// Specify a list of reserved peers of the root node
+    #[structopt(long = "root-reserved-nodes", value_name = "URL")]
+    pub root_reserved_nodes: Vec<String>,
+
+    /// Whether the root node only connects to reserved peers
+    #[structopt(long = "root-reserved-only")]
+    pub root_reserved_only: bool,
+
+    /// Specify the maximum number of peers of the root node
+    #[structopt(long = "root-max-peers", value_name = "COUNT")]
+    pub root_max_peers: Option<u32>,
+
+    /// Specify the file of the root node key, created if missing, for a stable root peer ID
+    #[structopt(long = "root-node-key-file", value_name = "FILE", parse(from_os_str))]
+    pub root_node_key_file: Option<PathBuf>,
+
+    /// Whether to disable mDNS discovery of root peers
+    #[structopt(long = "root-no-mdns")]
+    pub root_no_mdns: bool,
+
+    /// Specify the node name of the root node, the node name with a `-root` suffix by default
+    #[structopt(long = "root-name", value_name = "NAME")]
+    pub root_name: Option<String>,
+
+    /// Whether to leave the root node out of the node telemetry
+    #[structopt(long = "root-no-telemetry")]
+    pub root_no_telemetry: bool,
+
+    /// Specify the base path of the root chain databases, the branch base path by default
+    #[structopt(long = "root-base-path", value_name = "PATH", parse(from_os_str))]
+    pub root_base_path: Option<PathBuf>,
+
+    /// Specify the index of the branch port among the root runtime modules with calls, to relay meta transactions with the relayer key
+    #[structopt(long = "root-branch-port-index", value_name = "INDEX")]
+    pub root_branch_port_index: Option<u8>,
+
+    /// Sign blocks and root-port meta transactions with a remote signer: unix://<path> or http://<host:port>,
+    /// GRANDPA votes can not be signed remotely
+    #[structopt(long = "signer", value_name = "URL")]
+    pub signer: Option<Endpoint>,
+
+    /// Hex public key of the remote signer key to use, its first key by default
+    #[structopt(long = "signer-key", value_name = "PUBLIC")]
+    pub signer_key: Option<String>,
+
+    /// File holding the token of the remote signer, written by `yee-branch signer`
+    #[structopt(long = "signer-token-file", value_name = "FILE", parse(from_os_str))]
+    pub signer_token_file: Option<PathBuf>,
+
+    /// Password of the role-tagged keystore keys, like the owner key
+    #[structopt(long = "key-password", value_name = "PASSWORD")]
+    pub key_password: Option<String>,
+
+    /// Specify the maximum number of ready transactions in the pool
+    #[structopt(long = "pool-ready-limit", value_name = "COUNT")]
+    pub pool_ready_limit: Option<usize>,
+
+    /// Specify the maximum size of ready transactions in the pool, in kB
+    #[structopt(long = "pool-ready-kbytes", value_name = "KB")]
+    pub pool_ready_kbytes: Option<usize>,
+
+    /// Specify the maximum number of future transactions in the pool
+    #[structopt(long = "pool-future-limit", value_name = "COUNT")]
+    pub pool_future_limit: Option<usize>,
+
+    /// Specify the maximum size of future transactions in the pool, in kB
+    #[structopt(long = "pool-future-kbytes", value_name = "KB")]
+    pub pool_future_kbytes: Option<usize>,
+
+    /// Specify the maximum number of transactions of a sender ahead of its account nonce
+    #[structopt(long = "pool-sender-limit", value_name = "COUNT")]
+    pub pool_sender_limit: Option<u64>,
+
+    /// Specify the number of invalid transactions after which their sender is banned, 0 to never ban
+    #[structopt(long = "pool-ban-threshold", value_name = "COUNT")]
+    pub pool_ban_threshold: Option<u32>,
+
+    /// Specify how long a sender stays banned, in seconds
+    #[structopt(long = "pool-ban-duration", value_name = "SECS")]
+    pub pool_ban_duration: Option<u64>,
+
     /// Whether use dev params or not
     #[structopt(long = "dev-params")]
     pub dev_params: bool,
 
+    /// How branch blocks get finalized: grandpa or root (anchored in the root chain)
+    #[structopt(long = "finality", value_name = "FINALITY", default_value = "grandpa")]
+    pub finality: Finality,
+
+    /// How a new node gets the chain: full (from genesis) or warp (from the latest root-anchored checkpoint)
+    #[structopt(long = "sync-mode", value_name = "MODE", default_value = "full")]
+    pub sync_mode: SyncMode,
+
 }
 
 impl_augment_clap!(YeeCliConfig);
@@ -66,12 +169,65 @@ where
     }
 
     config.custom.root_port = custom_args.root_port;
+    config.custom.root_network = yee_root_chain::NetworkParams {
+        public_addresses: custom_args.root_public_addresses.clone(),
+        reserved_nodes: custom_args.root_reserved_nodes.clone(),
+        reserved_only: custom_args.root_reserved_only,
+        max_peers: custom_args.root_max_peers,
+        node_key_file: custom_args.root_node_key_file.clone(),
+        no_mdns: custom_args.root_no_mdns,
+    };
     config.custom.version_commit = version.commit;
     config.custom.version_version = version.version;
+    config.custom.finality = custom_args.finality;
+    config.custom.sync_mode = custom_args.sync_mode;
+    config.custom.root_base_path = custom_args.root_base_path.clone();
+    if let Some(ref root_name) = custom_args.root_name {
+        if root_name.chars().count() >= yee_root_chain::NODE_NAME_MAX_LENGTH {
+            return Err(format!("Root node name {} is too long, it must be shorter than {} characters",
+                root_name, yee_root_chain::NODE_NAME_MAX_LENGTH).into());
+        }
+    }
+    config.custom.root_name = custom_args.root_name.clone();
+    config.custom.root_telemetry = !custom_args.root_no_telemetry;
+    config.custom.root_branch_port_index = custom_args.root_branch_port_index;
+    config.custom.signer = custom_args.signer.clone();
+    config.custom.signer_key = match custom_args.signer_key {
+        Some(ref public) => Some(Public::from_raw(parse_public(public)?)),
+        None => None,
+    };
+    config.custom.signer_token = match (&custom_args.signer, &custom_args.signer_token_file) {
+        (Some(_), Some(file)) => Some(signer::load_token(file)?),
+        (Some(_), None) => return Err("--signer-token-file is required with --signer".into()),
+        (None, _) => None,
+    };
+    config.custom.key_password = custom_args.key_password.clone();
+
+    let mut pool_policy = PoolPolicy::default();
+    pool_policy.ready_limit = custom_args.pool_ready_limit.unwrap_or(pool_policy.ready_limit);
+    pool_policy.ready_bytes = custom_args.pool_ready_kbytes.map(|kbytes| kbytes * 1024).unwrap_or(pool_policy.ready_bytes);
+    pool_policy.future_limit = custom_args.pool_future_limit.unwrap_or(pool_policy.future_limit);
+    pool_policy.future_bytes = custom_args.pool_future_kbytes.map(|kbytes| kbytes * 1024).unwrap_or(pool_policy.future_bytes);
+    pool_policy.sender_limit = custom_args.pool_sender_limit.unwrap_or(pool_policy.sender_limit);
+    pool_policy.ban_threshold = custom_args.pool_ban_threshold.unwrap_or(pool_policy.ban_threshold);
+    pool_policy.ban_duration = custom_args.pool_ban_duration.unwrap_or(pool_policy.ban_duration);
+    config.transaction_pool.ready.count = pool_policy.ready_limit;
+    config.transaction_pool.ready.total_bytes = pool_policy.ready_bytes;
+    config.transaction_pool.future.count = pool_policy.future_limit;
+    config.transaction_pool.future.total_bytes = pool_policy.future_bytes;
 
     info!("Custom params: ");
     info!("  root port: {:?}", config.custom.root_port);
     info!("  root bootnodes router conf: {:?}", config.custom.root_bootnodes_router_conf);
+    info!("  root network: {:?}", config.custom.root_network);
+    info!("  root name: {}", yee_root_chain::root_node_name(&config.name, config.custom.root_name.as_ref().map(String::as_str)));
+    info!("  root telemetry: {}", config.custom.root_telemetry);
+    info!("  root base path: {:?}", config.custom.root_base_path);
+    info!("  finality: {:?}", config.custom.finality);
+    info!("  sync mode: {:?}", config.custom.sync_mode);
+    info!("  signer: {:?}", config.custom.signer);
+    info!("  pool policy: {:?}", pool_policy);
+    tx_pool::set_policy(pool_policy);
     Ok(())
 }
 
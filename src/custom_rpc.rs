@@ -0,0 +1,340 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Branch specific rpc
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use jsonrpc_core::{IoHandler, Result as RpcResult, Error as RpcError, ErrorCode};
+use jsonrpc_derive::rpc;
+use parity_codec::{Encode, Decode};
+use primitives::{Blake2Hasher, Bytes, twox_128, storage::StorageKey};
+use runtime_primitives::traits::{Header as HeaderT, ProvideRuntimeApi};
+use substrate_client::{self as client, Client};
+use substrate_service::{
+	FullComponents, LightComponents, ComponentClient, ComponentExPoolApi,
+	RpcHandlerConstructor,
+};
+use substrate_rpc::Metadata;
+use transaction_pool::txpool::{self, Pool as TransactionPool};
+use yee_branch_runtime::{AccountId, Hash, Nonce, opaque::{Block, BlockId, Header}};
+use yee_branch_runtime::api::{AccountNonceApi, AddressKind, FeeInfo, TransactionPaymentApi, TreasuryApi as TreasuryRuntimeApi};
+use yee_root_port::{Checkpoint, MetaTx, SignedMetaTx, FraudProof};
+use crate::root_port;
+use crate::service::Factory;
+use crate::tx_pool::{self, PoolStatus};
+
+type Balance = u128;
+
+#[rpc]
+pub trait TreasuryApi<Hash> {
+	/// Free balance of the treasury pot.
+	#[rpc(name = "treasury_balance")]
+	fn balance(&self, at: Option<Hash>) -> RpcResult<Balance>;
+}
+
+pub struct Treasury<B, E, RA> {
+	client: Arc<Client<B, E, Block, RA>>,
+}
+
+impl<B, E, RA> Treasury<B, E, RA> {
+	pub fn new(client: Arc<Client<B, E, Block, RA>>) -> Self {
+		Self { client }
+	}
+}
+
+impl<B, E, RA> TreasuryApi<Hash> for Treasury<B, E, RA> where
+	B: client::backend::Backend<Block, Blake2Hasher> + Send + Sync + 'static,
+	E: client::CallExecutor<Block, Blake2Hasher> + Send + Sync + 'static,
+	RA: Send + Sync + 'static,
+	Client<B, E, Block, RA>: ProvideRuntimeApi,
+	<Client<B, E, Block, RA> as ProvideRuntimeApi>::Api: TreasuryRuntimeApi<Block>,
+{
+	fn balance(&self, at: Option<Hash>) -> RpcResult<Balance> {
+		let at = at_or_best(&*self.client, at)?;
+		self.client.runtime_api().pot_balance(&at).map_err(internal_error)
+	}
+}
+
+#[rpc]
+pub trait RootPortApi<Hash> {
+	/// Fraud proof against a checkpoint signed by the owner, if it does not match the local chain.
+	#[rpc(name = "rootPort_fraudProof")]
+	fn fraud_proof(&self, signed_meta_tx: Bytes) -> RpcResult<Option<Bytes>>;
+
+	/// State transition fraud proof against a checkpoint signed by the owner, if the value of `key`
+	/// differs between the canonical checkpointed block and the block `claimed_at`, whose state
+	/// root the checkpoint claims.
+	#[rpc(name = "rootPort_stateTransitionProof")]
+	fn state_transition_proof(&self, signed_meta_tx: Bytes, key: Bytes, claimed_at: Hash) -> RpcResult<Option<Bytes>>;
+}
+
+pub struct RootPort<B, E, RA> {
+	client: Arc<Client<B, E, Block, RA>>,
+}
+
+impl<B, E, RA> RootPort<B, E, RA> {
+	pub fn new(client: Arc<Client<B, E, Block, RA>>) -> Self {
+		Self { client }
+	}
+}
+
+impl<B, E, RA> RootPort<B, E, RA> where
+	B: client::backend::Backend<Block, Blake2Hasher> + Send + Sync + 'static,
+	E: client::CallExecutor<Block, Blake2Hasher> + Send + Sync + 'static,
+	RA: Send + Sync + 'static,
+{
+	/// Checkpoint signed by the owner, and the header of the local block at its height.
+	fn checkpoint_header(&self, signed_meta_tx: &[u8]) -> RpcResult<(Checkpoint, Header)> {
+		let signed: SignedMetaTx = Decode::decode(&mut &signed_meta_tx[..])
+			.ok_or_else(|| invalid_params("Invalid signed meta transaction"))?;
+		let checkpoint = match signed.meta_tx {
+			MetaTx::Checkpoint { checkpoint, .. } => checkpoint,
+			_ => return Err(invalid_params("Not a checkpoint")),
+		};
+
+		let header = self.client.header(&BlockId::number(checkpoint.number)).map_err(internal_error)?
+			.ok_or_else(|| invalid_params("Checkpoint block not found"))?;
+		Ok((checkpoint, header))
+	}
+}
+
+impl<B, E, RA> RootPortApi<Hash> for RootPort<B, E, RA> where
+	B: client::backend::Backend<Block, Blake2Hasher> + Send + Sync + 'static,
+	E: client::CallExecutor<Block, Blake2Hasher> + Send + Sync + 'static,
+	RA: Send + Sync + 'static,
+{
+	fn fraud_proof(&self, signed_meta_tx: Bytes) -> RpcResult<Option<Bytes>> {
+		let (checkpoint, header) = self.checkpoint_header(&signed_meta_tx)?;
+
+		let proof = if checkpoint.hash[..] != *header.hash().as_ref() {
+			FraudProof::ForkedBlock
+		} else if checkpoint.state_root[..] != *header.state_root().as_ref() {
+			FraudProof::StateRoot { header: header.encode() }
+		} else {
+			let at = BlockId::hash(header.hash());
+			let next_forced_index = root_port::next_forced_index(&*self.client, &at).map_err(internal_error)?;
+			if checkpoint.next_forced_index == next_forced_index {
+				return Ok(None);
+			}
+			let key = twox_128(yee_root_port::NEXT_FORCED_INDEX_KEY);
+			FraudProof::ForcedIndex {
+				header: header.encode(),
+				proof: self.client.read_proof(&at, &key).map_err(internal_error)?,
+			}
+		};
+		Ok(Some(proof.encode().into()))
+	}
+
+	fn state_transition_proof(&self, signed_meta_tx: Bytes, key: Bytes, claimed_at: Hash) -> RpcResult<Option<Bytes>> {
+		let (checkpoint, header) = self.checkpoint_header(&signed_meta_tx)?;
+		if checkpoint.hash[..] != *header.hash().as_ref() {
+			return Err(invalid_params("Checkpoint block is not canonical, prove it with rootPort_fraudProof"));
+		}
+
+		let claimed_at = BlockId::hash(claimed_at);
+		let claimed_header = self.client.header(&claimed_at).map_err(internal_error)?
+			.ok_or_else(|| invalid_params("Claimed block not found"))?;
+		if checkpoint.state_root[..] != *claimed_header.state_root().as_ref() {
+			return Err(invalid_params("Checkpoint state root is not the one of the claimed block"));
+		}
+
+		let at = BlockId::hash(header.hash());
+		let key = StorageKey(key.to_vec());
+		let value = self.client.storage(&at, &key).map_err(internal_error)?;
+		let claimed_value = self.client.storage(&claimed_at, &key).map_err(internal_error)?;
+		if value == claimed_value {
+			return Ok(None);
+		}
+
+		let proof = FraudProof::StateTransition {
+			header: header.encode(),
+			proof: self.client.read_proof(&at, &key.0).map_err(internal_error)?,
+			claimed_proof: self.client.read_proof(&claimed_at, &key.0).map_err(internal_error)?,
+			key: key.0,
+		};
+		Ok(Some(proof.encode().into()))
+	}
+}
+
+#[rpc]
+pub trait AccountApi<AccountId, Nonce> {
+	/// Nonce of the next transaction of the account, after its ready transactions in the pool.
+	#[rpc(name = "account_nextIndex")]
+	fn next_index(&self, account: AccountId) -> RpcResult<Nonce>;
+}
+
+pub struct Account<B, E, RA, A: txpool::ChainApi> {
+	client: Arc<Client<B, E, Block, RA>>,
+	pool: Arc<TransactionPool<A>>,
+}
+
+impl<B, E, RA, A: txpool::ChainApi> Account<B, E, RA, A> {
+	pub fn new(client: Arc<Client<B, E, Block, RA>>, pool: Arc<TransactionPool<A>>) -> Self {
+		Self { client, pool }
+	}
+}
+
+impl<B, E, RA, A> AccountApi<AccountId, Nonce> for Account<B, E, RA, A> where
+	B: client::backend::Backend<Block, Blake2Hasher> + Send + Sync + 'static,
+	E: client::CallExecutor<Block, Blake2Hasher> + Send + Sync + 'static,
+	RA: Send + Sync + 'static,
+	Client<B, E, Block, RA>: ProvideRuntimeApi,
+	<Client<B, E, Block, RA> as ProvideRuntimeApi>::Api: AccountNonceApi<Block>,
+	A: txpool::ChainApi + 'static,
+{
+	fn next_index(&self, account: AccountId) -> RpcResult<Nonce> {
+		let at = at_or_best(&*self.client, None)?;
+		let nonce = self.client.runtime_api().account_nonce(&at, account.clone()).map_err(internal_error)?;
+
+		// signed transactions provide the tag of their sender and nonce
+		let pending = self.pool.ready()
+			.flat_map(|tx| tx.provides.clone())
+			.filter_map(|tag| <(AccountId, Nonce)>::decode(&mut &tag[..]))
+			.filter(|(sender, _)| *sender == account)
+			.map(|(_, nonce)| nonce)
+			.collect::<HashSet<_>>();
+
+		let mut next = nonce;
+		while pending.contains(&next) {
+			next += 1;
+		}
+		Ok(next)
+	}
+}
+
+#[rpc]
+pub trait PaymentApi<Hash> {
+	/// Fee of an encoded signed extrinsic, or of an encoded call once signed by a sender
+	/// addressed by account ID, or the given way.
+	#[rpc(name = "payment_queryInfo")]
+	fn query_info(&self, encoded: Bytes, address: Option<AddressKind>, at: Option<Hash>) -> RpcResult<FeeInfo>;
+}
+
+pub struct Payment<B, E, RA> {
+	client: Arc<Client<B, E, Block, RA>>,
+}
+
+impl<B, E, RA> Payment<B, E, RA> {
+	pub fn new(client: Arc<Client<B, E, Block, RA>>) -> Self {
+		Self { client }
+	}
+}
+
+impl<B, E, RA> PaymentApi<Hash> for Payment<B, E, RA> where
+	B: client::backend::Backend<Block, Blake2Hasher> + Send + Sync + 'static,
+	E: client::CallExecutor<Block, Blake2Hasher> + Send + Sync + 'static,
+	RA: Send + Sync + 'static,
+	Client<B, E, Block, RA>: ProvideRuntimeApi,
+	<Client<B, E, Block, RA> as ProvideRuntimeApi>::Api: TransactionPaymentApi<Block>,
+{
+	fn query_info(&self, encoded: Bytes, address: Option<AddressKind>, at: Option<Hash>) -> RpcResult<FeeInfo> {
+		let at = at_or_best(&*self.client, at)?;
+		let address = address.unwrap_or(AddressKind::Id);
+		self.client.runtime_api().query_info(&at, encoded.to_vec(), address).map_err(internal_error)?
+			.ok_or_else(|| invalid_params("Neither an extrinsic nor a call"))
+	}
+}
+
+#[rpc]
+pub trait PoolApi {
+	/// Transaction counts of the pool with its policies.
+	#[rpc(name = "author_poolStatus")]
+	fn pool_status(&self) -> RpcResult<PoolStatus>;
+}
+
+pub struct Pool<A: txpool::ChainApi> {
+	pool: Arc<TransactionPool<A>>,
+}
+
+impl<A: txpool::ChainApi> Pool<A> {
+	pub fn new(pool: Arc<TransactionPool<A>>) -> Self {
+		Self { pool }
+	}
+}
+
+impl<A> PoolApi for Pool<A> where
+	A: txpool::ChainApi + 'static,
+{
+	fn pool_status(&self) -> RpcResult<PoolStatus> {
+		Ok(tx_pool::status(&*self.pool))
+	}
+}
+
+pub struct FullRpcHandlerConstructor;
+
+impl RpcHandlerConstructor<FullComponents<Factory>> for FullRpcHandlerConstructor {
+	type RpcExtra = IoHandler<Metadata>;
+
+	fn build_rpc_extra(
+		client: Arc<ComponentClient<FullComponents<Factory>>>,
+		pool: Arc<TransactionPool<ComponentExPoolApi<FullComponents<Factory>>>>,
+	) -> Self::RpcExtra {
+		let mut io = IoHandler::default();
+		io.extend_with(TreasuryApi::to_delegate(Treasury::new(client.clone())));
+		io.extend_with(RootPortApi::to_delegate(RootPort::new(client.clone())));
+		io.extend_with(AccountApi::to_delegate(Account::new(client.clone(), pool.clone())));
+		io.extend_with(PaymentApi::to_delegate(Payment::new(client)));
+		io.extend_with(PoolApi::to_delegate(Pool::new(pool)));
+		io
+	}
+}
+
+pub struct LightRpcHandlerConstructor;
+
+impl RpcHandlerConstructor<LightComponents<Factory>> for LightRpcHandlerConstructor {
+	type RpcExtra = IoHandler<Metadata>;
+
+	fn build_rpc_extra(
+		client: Arc<ComponentClient<LightComponents<Factory>>>,
+		pool: Arc<TransactionPool<ComponentExPoolApi<LightComponents<Factory>>>>,
+	) -> Self::RpcExtra {
+		let mut io = IoHandler::default();
+		io.extend_with(TreasuryApi::to_delegate(Treasury::new(client.clone())));
+		io.extend_with(AccountApi::to_delegate(Account::new(client.clone(), pool.clone())));
+		io.extend_with(PaymentApi::to_delegate(Payment::new(client)));
+		io.extend_with(PoolApi::to_delegate(Pool::new(pool)));
+		io
+	}
+}
+
+fn at_or_best<B, E, RA>(client: &Client<B, E, Block, RA>, at: Option<Hash>) -> RpcResult<BlockId> where
+	B: client::backend::Backend<Block, Blake2Hasher>,
+	E: client::CallExecutor<Block, Blake2Hasher>,
+{
+	let hash = match at {
+		Some(hash) => hash,
+		None => client.info().map_err(internal_error)?.chain.best_hash,
+	};
+	Ok(BlockId::hash(hash))
+}
+
+fn invalid_params(message: &str) -> RpcError {
+	RpcError {
+		code: ErrorCode::InvalidParams,
+		message: message.into(),
+		data: None,
+	}
+}
+
+fn internal_error<E: std::fmt::Debug>(e: E) -> RpcError {
+	RpcError {
+		code: ErrorCode::InternalError,
+		message: format!("{:?}", e),
+		data: None,
+	}
+}
@@ -0,0 +1,319 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Branch keystore: one file per key holding its secret URI, named after its role and public key.
+//!
+//! The password is not stored, it is part of the key derivation, so a wrong password yields
+//! another key and is detected by the public key mismatch. Role-tagged keys also encrypt the
+//! secret URI with the password: AES-256-CTR under a key derived by PBKDF2-HMAC-SHA256,
+//! authenticated by HMAC-SHA256. Authority keys keep the file naming and format of the
+//! substrate keystore, so the service still loads them.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use aes_ctr::Aes256Ctr;
+use aes_ctr::stream_cipher::{NewStreamCipher, SyncStreamCipher};
+use hmac::{Hmac, Mac};
+use serde::{Serialize, Deserialize};
+use sha2::Sha256;
+use primitives::{ed25519, sr25519, Bytes, Pair, H256, hexdisplay::HexDisplay};
+use crate::error;
+
+/// PBKDF2 rounds deriving the keys encrypting a secret URI from the password.
+const KDF_ROUNDS: u32 = 10240;
+
+/// Secret URI encrypted with the password.
+#[derive(Serialize, Deserialize)]
+struct EncryptedSecret {
+	/// Salt of the key derivation.
+	salt: Bytes,
+	/// Rounds of the key derivation.
+	rounds: u32,
+	/// Nonce of the cipher.
+	nonce: Bytes,
+	/// Encrypted secret URI.
+	ciphertext: Bytes,
+	/// MAC of the nonce and the encrypted secret URI.
+	mac: Bytes,
+}
+
+impl EncryptedSecret {
+	fn encrypt(suri: &str, password: Option<&str>) -> Self {
+		let salt = rand::random::<[u8; 32]>();
+		let nonce = rand::random::<[u8; 16]>();
+		let (cipher_key, mac_key) = derive_keys(password, &salt, KDF_ROUNDS);
+
+		let mut ciphertext = suri.as_bytes().to_vec();
+		Aes256Ctr::new_var(&cipher_key, &nonce).expect("Key and nonce have the cipher lengths; qed")
+			.apply_keystream(&mut ciphertext);
+		let mac = mac_of(&mac_key, &nonce, &ciphertext).result().code().to_vec();
+
+		EncryptedSecret {
+			salt: salt.to_vec().into(),
+			rounds: KDF_ROUNDS,
+			nonce: nonce.to_vec().into(),
+			ciphertext: ciphertext.into(),
+			mac: mac.into(),
+		}
+	}
+
+	fn decrypt(self, password: Option<&str>) -> error::Result<String> {
+		let (cipher_key, mac_key) = derive_keys(password, &self.salt, self.rounds);
+		if mac_of(&mac_key, &self.nonce, &self.ciphertext).verify(&self.mac).is_err() {
+			return Err("Invalid password".into());
+		}
+
+		let mut suri = self.ciphertext.0;
+		Aes256Ctr::new_var(&cipher_key, &self.nonce).map_err(|_| "Invalid key file nonce")?
+			.apply_keystream(&mut suri);
+		String::from_utf8(suri).map_err(|_| "Invalid key file secret".into())
+	}
+}
+
+/// Cipher and MAC keys derived from the password.
+fn derive_keys(password: Option<&str>, salt: &[u8], rounds: u32) -> ([u8; 32], [u8; 32]) {
+	let mut derived = [0u8; 64];
+	pbkdf2::pbkdf2::<Hmac<Sha256>>(password.unwrap_or("").as_bytes(), salt, rounds as usize, &mut derived);
+
+	let (mut cipher_key, mut mac_key) = ([0u8; 32], [0u8; 32]);
+	cipher_key.copy_from_slice(&derived[..32]);
+	mac_key.copy_from_slice(&derived[32..]);
+	(cipher_key, mac_key)
+}
+
+fn mac_of(mac_key: &[u8], nonce: &[u8], ciphertext: &[u8]) -> Hmac<Sha256> {
+	let mut mac = Hmac::<Sha256>::new_varkey(mac_key).expect("HMAC takes keys of any length; qed");
+	mac.input(nonce);
+	mac.input(ciphertext);
+	mac
+}
+
+/// What a key is used for, which determines its crypto scheme.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum KeyRole {
+	/// ed25519 authority key signing blocks and finality votes.
+	Authority,
+	/// sr25519 account key signing transactions.
+	Account,
+	/// ed25519 session key of the branch owner, signing root-port meta transactions.
+	Owner,
+	/// sr25519 root chain account key, relaying root-port meta transactions to the root chain.
+	Relayer,
+}
+
+impl KeyRole {
+	pub fn scheme(&self) -> &'static str {
+		match self {
+			KeyRole::Authority | KeyRole::Owner => "ed25519",
+			KeyRole::Account | KeyRole::Relayer => "sr25519",
+		}
+	}
+
+	fn tag(&self) -> &'static str {
+		match self {
+			KeyRole::Authority => "authority",
+			KeyRole::Account => "account",
+			KeyRole::Owner => "owner",
+			KeyRole::Relayer => "relayer",
+		}
+	}
+
+	fn all() -> [KeyRole; 4] {
+		[KeyRole::Authority, KeyRole::Account, KeyRole::Owner, KeyRole::Relayer]
+	}
+}
+
+impl fmt::Display for KeyRole {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(self.tag())
+	}
+}
+
+impl FromStr for KeyRole {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		KeyRole::all().iter().find(|role| role.tag() == s).cloned()
+			.ok_or_else(|| format!("Unknown key role: {}, expected authority, account, owner or relayer", s))
+	}
+}
+
+/// Keystore directory.
+pub struct Keystore {
+	path: PathBuf,
+}
+
+impl Keystore {
+	pub fn open<P: Into<PathBuf>>(path: P) -> error::Result<Self> {
+		let path = path.into();
+		fs::create_dir_all(&path)?;
+		Ok(Keystore { path })
+	}
+
+	/// Generate a key, returning its public key and mnemonic phrase.
+	pub fn generate(&self, role: KeyRole, password: Option<&str>) -> error::Result<([u8; 32], String)> {
+		let (public, phrase) = match role.scheme() {
+			"ed25519" => {
+				let (pair, phrase) = ed25519::Pair::generate_with_phrase(password);
+				(pair.public().0, phrase)
+			},
+			_ => {
+				let (pair, phrase) = sr25519::Pair::generate_with_phrase(password);
+				(pair.public().0, phrase)
+			},
+		};
+		self.write(role, &public, &phrase, password)?;
+		Ok((public, phrase))
+	}
+
+	/// Insert the key of the secret URI, returning its public key.
+	pub fn insert(&self, role: KeyRole, suri: &str, password: Option<&str>) -> error::Result<[u8; 32]> {
+		let public = public_of(role, suri, password)?;
+		self.write(role, &public, suri, password)?;
+		Ok(public)
+	}
+
+	/// Public keys in the keystore with their role.
+	pub fn list(&self) -> error::Result<Vec<(KeyRole, [u8; 32])>> {
+		let mut keys = Vec::new();
+		for entry in fs::read_dir(&self.path)? {
+			let name = entry?.file_name();
+			if let Some(key) = name.to_str().and_then(parse_file_name) {
+				keys.push(key);
+			}
+		}
+		keys.sort();
+		Ok(keys)
+	}
+
+	/// Remove the key, returning whether it was there.
+	pub fn remove(&self, role: KeyRole, public: &[u8; 32]) -> error::Result<bool> {
+		let file = self.file_path(role, public);
+		if !file.exists() {
+			return Ok(false);
+		}
+		fs::remove_file(file)?;
+		Ok(true)
+	}
+
+	/// Load an ed25519 key, failing if the password does not yield the public key.
+	pub fn load_ed25519(&self, role: KeyRole, public: &[u8; 32], password: Option<&str>) -> error::Result<ed25519::Pair> {
+		let suri = self.read(role, public, password)?;
+		let pair = ed25519::Pair::from_string(&suri, password).map_err(|e| format!("Invalid key: {:?}", e))?;
+		if pair.public().0 != *public {
+			return Err("Invalid password".into());
+		}
+		Ok(pair)
+	}
+
+	/// Load an sr25519 key, failing if the password does not yield the public key.
+	pub fn load_sr25519(&self, role: KeyRole, public: &[u8; 32], password: Option<&str>) -> error::Result<sr25519::Pair> {
+		let suri = self.read(role, public, password)?;
+		let pair = sr25519::Pair::from_string(&suri, password).map_err(|e| format!("Invalid key: {:?}", e))?;
+		if pair.public().0 != *public {
+			return Err("Invalid password".into());
+		}
+		Ok(pair)
+	}
+
+	fn write(&self, role: KeyRole, public: &[u8; 32], suri: &str, password: Option<&str>) -> error::Result<()> {
+		let file = self.file_path(role, public);
+		let content = match role {
+			KeyRole::Authority => serde_json::to_string(suri),
+			_ => serde_json::to_string(&EncryptedSecret::encrypt(suri, password)),
+		};
+		fs::write(&file, content.map_err(|e| format!("{:?}", e))?)?;
+		restrict_permissions(&file)
+	}
+
+	fn read(&self, role: KeyRole, public: &[u8; 32], password: Option<&str>) -> error::Result<String> {
+		let content = fs::read_to_string(self.file_path(role, public))
+			.map_err(|_| format!("No {} key {}", role, HexDisplay::from(public)))?;
+		// authority keys, and role-tagged keys written before they were encrypted, hold the secret URI
+		if let Ok(suri) = serde_json::from_str::<String>(&content) {
+			return Ok(suri);
+		}
+		let encrypted: EncryptedSecret = serde_json::from_str(&content)
+			.map_err(|e| format!("Invalid key file: {:?}", e))?;
+		encrypted.decrypt(password)
+	}
+
+	fn file_path(&self, role: KeyRole, public: &[u8; 32]) -> PathBuf {
+		let hex = format!("{}", HexDisplay::from(public));
+		match role {
+			KeyRole::Authority => self.path.join(hex),
+			_ => self.path.join(format!("{}-{}", role, hex)),
+		}
+	}
+}
+
+/// Public key of the secret URI with the scheme of the role.
+pub fn public_of(role: KeyRole, suri: &str, password: Option<&str>) -> error::Result<[u8; 32]> {
+	Ok(match role.scheme() {
+		"ed25519" => ed25519::Pair::from_string(suri, password).map_err(|e| format!("Invalid secret URI: {:?}", e))?.public().0,
+		_ => sr25519::Pair::from_string(suri, password).map_err(|e| format!("Invalid secret URI: {:?}", e))?.public().0,
+	})
+}
+
+/// Parse a hex encoded public key.
+pub fn parse_public(public: &str) -> error::Result<[u8; 32]> {
+	let public = public.trim_start_matches("0x");
+	H256::from_str(public).map(|h| h.0).map_err(|_| format!("Invalid public key: {}", public).into())
+}
+
+fn parse_file_name(name: &str) -> Option<(KeyRole, [u8; 32])> {
+	let (role, hex) = match name.find('-') {
+		Some(i) => (name[..i].parse().ok()?, &name[i + 1..]),
+		None => (KeyRole::Authority, name),
+	};
+	if hex.len() != 64 {
+		return None;
+	}
+	parse_public(hex).ok().map(|public| (role, public))
+}
+
+#[cfg(unix)]
+fn restrict_permissions(file: &Path) -> error::Result<()> {
+	use std::os::unix::fs::PermissionsExt;
+	fs::set_permissions(file, fs::Permissions::from_mode(0o600))?;
+	Ok(())
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_file: &Path) -> error::Result<()> {
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn secret_is_encrypted_with_the_password() {
+		let suri = "bottom drive obey lake curtain smoke basket hold race lonely fit walk//Alice";
+		let encrypted = EncryptedSecret::encrypt(suri, Some("password"));
+		let content = serde_json::to_string(&encrypted).unwrap();
+		assert!(!content.contains("bottom"));
+
+		let decrypt = |password| serde_json::from_str::<EncryptedSecret>(&content).unwrap().decrypt(password);
+		assert_eq!(decrypt(Some("password")).unwrap(), suri);
+		assert!(decrypt(Some("wrong")).is_err());
+		assert!(decrypt(None).is_err());
+	}
+}
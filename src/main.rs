@@ -8,6 +8,15 @@ mod service;
 mod cli;
 mod custom_param;
 mod dev_param;
+mod root_port;
+mod custom_rpc;
+mod warp_sync;
+mod snapshot;
+mod custom_command;
+mod keystore;
+mod signer;
+mod config_file;
+mod tx_pool;
 
 pub use substrate_cli::{VersionInfo, IntoExit, error};
 
@@ -0,0 +1,423 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Node side of yee-root-port: keeps the embedded root chain in line with the branch runtime,
+//! relays the meta transactions handed over by its offchain worker, finalizes branch
+//! blocks anchored in finalized root chain blocks, and tracks the calls posted for the branch.
+
+use std::sync::{Arc, RwLock};
+use futures::{Future, Stream};
+use log::{info, warn};
+use parking_lot::Mutex;
+use parity_codec::{Encode, Decode};
+use primitives::{
+	Blake2Hasher, ExecutionContext, twox_128, storage::StorageKey,
+	ed25519::Public, offchain,
+};
+use runtime_primitives::traits::{AuthorityIdFor, ProvideRuntimeApi, Header as HeaderT};
+use consensus_common::{BlockImport, ImportBlock, ImportResult};
+use offchain_primitives::OffchainWorkerApi;
+use substrate_client::{self as client, Client, BlockchainEvents, backend::AuxStore};
+use substrate_service::TaskExecutor;
+use yee_branch_runtime::{Hash, opaque::{Block, BlockId}};
+use yee_root_chain::{RootChain, CheckpointHandler, ForcedCallsHandler};
+use yee_root_port::{Checkpoint, ForcedCall, MetaTx, SignedMetaTx};
+use crate::signer::Signer;
+use crate::error;
+
+/// Read the root chain shard the branch attaches to from the branch state.
+pub fn root_shard_num<B, E, RA>(client: &Client<B, E, Block, RA>, at: &BlockId) -> error::Result<u16> where
+	B: client::backend::Backend<Block, Blake2Hasher>,
+	E: client::CallExecutor<Block, Blake2Hasher>,
+{
+	let key = StorageKey(twox_128(yee_root_port::ROOT_SHARD_NUM_KEY).to_vec());
+	let data = client.storage(at, &key)?.ok_or("Root shard number not found in the branch state")?;
+	Ok(Decode::decode(&mut &data.0[..]).ok_or("Invalid root shard number in the branch state")?)
+}
+
+/// Read the index of the next forced call the branch executes from the branch state.
+pub fn next_forced_index<B, E, RA>(client: &Client<B, E, Block, RA>, at: &BlockId) -> error::Result<u64> where
+	B: client::backend::Backend<Block, Blake2Hasher>,
+	E: client::CallExecutor<Block, Blake2Hasher>,
+{
+	let key = StorageKey(twox_128(yee_root_port::NEXT_FORCED_INDEX_KEY).to_vec());
+	match client.storage(at, &key)? {
+		Some(data) => Ok(Decode::decode(&mut &data.0[..]).ok_or("Invalid forced call index in the branch state")?),
+		None => Ok(0),
+	}
+}
+
+/// Re-attach the root chain to another shard whenever the branch runtime re-picks it.
+pub fn follow_root_shard<B, E, RA, OnExit>(
+	client: Arc<Client<B, E, Block, RA>>,
+	root_chain: Arc<Mutex<RootChain>>,
+	on_exit: OnExit,
+	executor: &TaskExecutor,
+) where
+	B: client::backend::Backend<Block, Blake2Hasher> + Send + Sync + 'static,
+	E: client::CallExecutor<Block, Blake2Hasher> + Send + Sync + 'static,
+	RA: Send + Sync + 'static,
+	OnExit: Future<Item=(), Error=()> + Send + 'static,
+{
+	let follow = client.import_notification_stream().for_each(move |notification| {
+
+		if !notification.is_new_best {
+			return Ok(());
+		}
+
+		match root_shard_num(&*client, &BlockId::hash(notification.hash)) {
+			Ok(shard_num) => {
+				let mut root_chain = root_chain.lock();
+				if root_chain.shard_num() != shard_num {
+					if let Err(e) = root_chain.switch_shard(shard_num) {
+						warn!("Failed to switch root chain shard: {:?}", e);
+					}
+				}
+			},
+			Err(e) => warn!("Failed to read the root chain shard: {}", e),
+		}
+
+		Ok(())
+	});
+
+	executor.spawn(follow.select(on_exit).then(|_| Ok(())));
+}
+
+/// Owner session key of the branch, from the branch state.
+pub fn owner_session_key<B, E, RA>(client: &Client<B, E, Block, RA>, at: &BlockId) -> Option<Public> where
+	B: client::backend::Backend<Block, Blake2Hasher>,
+	E: client::CallExecutor<Block, Blake2Hasher>,
+{
+	let key = StorageKey(twox_128(yee_root_port::OWNER_SESSION_KEY_KEY).to_vec());
+	client.storage(at, &key).ok()?
+		.and_then(|data| Decode::decode(&mut &data.0[..]))
+}
+
+/// Offchain externalities collecting what the runtime hands to the node.
+struct HandedOver(Arc<Mutex<Vec<Vec<u8>>>>);
+
+impl offchain::Externalities for HandedOver {
+	fn submit_extrinsic(&mut self, extrinsic: Vec<u8>) {
+		self.0.lock().push(extrinsic);
+	}
+}
+
+/// Aux storage key of the meta transactions signed but not yet relayed in a finalized root
+/// chain block.
+const PENDING_META_TXS_KEY: &[u8] = b"yee_root_port_pending_meta_txs";
+
+/// Aux storage key of the number of the last block whose offchain worker was run.
+const LAST_RELAYED_BLOCK_KEY: &[u8] = b"yee_root_port_last_relayed_block";
+
+/// Run the offchain worker of every block of the best chain, sign the meta transactions it
+/// hands over with the owner session key, and submit them to the root chain.
+///
+/// Blocks where the key of `signer` is not the owner session key are skipped. Signed meta
+/// transactions are kept in the aux storage, across restarts, and submitted again on every
+/// import until the root chain acknowledges them in a finalized block.
+pub fn relay_meta_txs<B, E, RA, OnExit>(
+	client: Arc<Client<B, E, Block, RA>>,
+	root_chain: Arc<Mutex<RootChain>>,
+	signer: Arc<dyn Signer>,
+	on_exit: OnExit,
+	executor: &TaskExecutor,
+) where
+	B: client::backend::Backend<Block, Blake2Hasher> + Send + Sync + 'static,
+	E: client::CallExecutor<Block, Blake2Hasher> + Clone + Send + Sync + 'static,
+	RA: Send + Sync + 'static,
+	Client<B, E, Block, RA>: ProvideRuntimeApi,
+	<Client<B, E, Block, RA> as ProvideRuntimeApi>::Api: OffchainWorkerApi<Block>,
+{
+	let relay = client.import_notification_stream().for_each(move |notification| {
+
+		if !notification.is_new_best {
+			return Ok(());
+		}
+
+		let mut pending: Vec<SignedMetaTx> = load_aux(&*client, PENDING_META_TXS_KEY).unwrap_or_default();
+
+		// blocks of the best chain not run yet, the offchain worker being run on each only once
+		let best_number = *notification.header.number();
+		let from = load_aux::<_, _, _, u64>(&*client, LAST_RELAYED_BLOCK_KEY)
+			.map(|last| last + 1)
+			.unwrap_or(best_number);
+		for number in from..=best_number {
+			let hash = match client.block_hash(number) {
+				Ok(Some(hash)) => hash,
+				_ => {
+					warn!("Block #{} of the best chain not found", number);
+					continue;
+				},
+			};
+			pending.extend(sign_meta_txs(&*client, &*signer, hash, number));
+		}
+
+		let mut unacknowledged = Vec::with_capacity(pending.len());
+		{
+			let mut root_chain = root_chain.lock();
+			for signed in pending {
+				match root_chain.is_meta_tx_relayed(&signed) {
+					Ok(true) => continue,
+					Ok(false) => (),
+					Err(e) => warn!("Failed to read relayed meta transactions from the root chain: {:?}", e),
+				}
+				if let Err(e) = root_chain.submit_meta_tx(&signed) {
+					warn!("Failed to relay meta transaction to the root chain: {:?}", e);
+				}
+				unacknowledged.push(signed);
+			}
+		}
+
+		let pending = unacknowledged.encode();
+		let last = best_number.encode();
+		if let Err(e) = client.insert_aux(&[(PENDING_META_TXS_KEY, &pending[..]), (LAST_RELAYED_BLOCK_KEY, &last[..])], &[]) {
+			warn!("Failed to store pending meta transactions: {:?}", e);
+		}
+
+		Ok(())
+	});
+
+	executor.spawn(relay.select(on_exit).then(|_| Ok(())));
+}
+
+/// Run the offchain worker of the block and sign the meta transactions it hands over, if the
+/// key of `signer` is the owner session key.
+fn sign_meta_txs<B, E, RA>(client: &Client<B, E, Block, RA>, signer: &dyn Signer, hash: Hash, number: u64) -> Vec<SignedMetaTx> where
+	B: client::backend::Backend<Block, Blake2Hasher>,
+	E: client::CallExecutor<Block, Blake2Hasher> + Clone,
+	Client<B, E, Block, RA>: ProvideRuntimeApi,
+	<Client<B, E, Block, RA> as ProvideRuntimeApi>::Api: OffchainWorkerApi<Block>,
+{
+	let at = BlockId::hash(hash);
+	if owner_session_key(client, &at).as_ref() != Some(&signer.public()) {
+		return Vec::new();
+	}
+
+	let handed_over = Arc::new(Mutex::new(Vec::new()));
+	let context = ExecutionContext::OffchainWorker(Box::new(HandedOver(handed_over.clone())));
+	if let Err(e) = client.runtime_api().offchain_worker_with_context(&at, context, number) {
+		warn!("Root port offchain worker failed: {:?}", e);
+		return Vec::new();
+	}
+
+	let meta_txs = std::mem::replace(&mut *handed_over.lock(), Vec::new());
+	let mut signed_meta_txs = Vec::with_capacity(meta_txs.len());
+	for data in meta_txs {
+		let mut meta_tx: MetaTx = match Decode::decode(&mut &data[..]) {
+			Some(meta_tx) => meta_tx,
+			None => {
+				warn!("Invalid meta transaction handed over by the offchain worker");
+				continue;
+			},
+		};
+		if let MetaTx::Checkpoint { ref mut checkpoint, .. } = meta_tx {
+			match client.header(&BlockId::number(checkpoint.number)) {
+				Ok(Some(header)) => checkpoint.state_root = header.state_root().as_ref().to_vec(),
+				_ => {
+					warn!("Checkpoint block #{} not found", checkpoint.number);
+					continue;
+				},
+			}
+			match next_forced_index(client, &BlockId::number(checkpoint.number)) {
+				Ok(index) => checkpoint.next_forced_index = index,
+				Err(e) => {
+					warn!("Failed to read the forced call index of checkpoint block #{}: {}", checkpoint.number, e);
+					continue;
+				},
+			}
+		}
+		let signature = match signer.sign(&meta_tx.encode()) {
+			Ok(signature) => signature,
+			Err(e) => {
+				warn!("Failed to sign meta transaction: {}", e);
+				continue;
+			},
+		};
+		signed_meta_txs.push(SignedMetaTx {
+			signature: signature.as_ref().to_vec(),
+			signer: signer.public().as_ref().to_vec(),
+			meta_tx,
+		});
+	}
+	signed_meta_txs
+}
+
+/// Decode a value of the aux storage, none if missing or invalid.
+fn load_aux<B, E, RA, T: Decode>(client: &Client<B, E, Block, RA>, key: &[u8]) -> Option<T> where
+	B: client::backend::Backend<Block, Blake2Hasher>,
+	E: client::CallExecutor<Block, Blake2Hasher>,
+{
+	client.get_aux(key).ok()?
+		.and_then(|data| Decode::decode(&mut &data[..]))
+}
+
+/// Finalize the branch blocks of the root chain checkpoints given to the returned handler.
+///
+/// Checkpoints of blocks not imported yet, or not in the best chain yet, are retried as blocks
+/// get imported. Light clients use it to verify the headers they follow against the root chain.
+pub fn finalize_on_checkpoints<B, E, RA, OnExit>(
+	client: Arc<Client<B, E, Block, RA>>,
+	on_exit: OnExit,
+	executor: &TaskExecutor,
+) -> CheckpointHandler where
+	B: client::backend::Backend<Block, Blake2Hasher> + Send + Sync + 'static,
+	E: client::CallExecutor<Block, Blake2Hasher> + Send + Sync + 'static,
+	RA: Send + Sync + 'static,
+	OnExit: Future<Item=(), Error=()> + Send + 'static,
+{
+	let pending: Arc<Mutex<Option<Checkpoint>>> = Arc::new(Mutex::new(None));
+
+	let retry = {
+		let client = client.clone();
+		let pending = pending.clone();
+		client.import_notification_stream().for_each(move |_| {
+			let mut pending = pending.lock();
+			if let Some(checkpoint) = pending.take() {
+				if !finalize_checkpoint(&*client, &checkpoint) {
+					*pending = Some(checkpoint);
+				}
+			}
+			Ok(())
+		})
+	};
+	executor.spawn(retry.select(on_exit).then(|_| Ok(())));
+
+	Arc::new(move |checkpoint: Checkpoint| {
+		let mut pending = pending.lock();
+		if finalize_checkpoint(&*client, &checkpoint) {
+			*pending = None;
+		} else {
+			*pending = Some(checkpoint);
+		}
+	})
+}
+
+/// Finalize the block of the checkpoint, returning false if it is not imported yet.
+fn finalize_checkpoint<B, E, RA>(client: &Client<B, E, Block, RA>, checkpoint: &Checkpoint) -> bool where
+	B: client::backend::Backend<Block, Blake2Hasher>,
+	E: client::CallExecutor<Block, Blake2Hasher>,
+{
+	let hash: Hash = match Decode::decode(&mut &checkpoint.hash[..]) {
+		Some(hash) => hash,
+		None => {
+			warn!("Invalid root chain checkpoint hash: {:?}", checkpoint.hash);
+			return true;
+		},
+	};
+
+	match client.info() {
+		Ok(info) if checkpoint.number <= info.chain.finalized_number => return true,
+		Ok(_) => (),
+		Err(e) => {
+			warn!("Failed to get best block information: {:?}", e);
+			return false;
+		},
+	}
+
+	match client.header(&BlockId::hash(hash)) {
+		Ok(Some(_)) => (),
+		Ok(None) => return false,
+		Err(e) => {
+			warn!("Failed to get checkpoint header: {:?}", e);
+			return false;
+		},
+	}
+
+	// the anchored block is only finalized once it is in the best chain,
+	// a header diverging from the root chain is never accepted as final
+	match client.block_hash(checkpoint.number) {
+		Ok(Some(best)) if best == hash => (),
+		Ok(Some(best)) => {
+			warn!("Best block #{} ({}) diverges from #{} ({}) anchored in the root chain", checkpoint.number, best, checkpoint.number, hash);
+			return false;
+		},
+		Ok(None) => return false,
+		Err(e) => {
+			warn!("Failed to get best chain block hash: {:?}", e);
+			return false;
+		},
+	}
+
+	match client.finalize_block(BlockId::hash(hash), None, true) {
+		Ok(()) => info!("Finalized #{} ({}) anchored in the root chain", checkpoint.number, hash),
+		Err(e) => warn!("Failed to finalize #{} ({}) anchored in the root chain: {:?}", checkpoint.number, hash, e),
+	}
+	true
+}
+
+/// Keep the latest checkpoint given to the returned handler in `anchor` before handing it to
+/// `on_checkpoint`.
+pub fn record_anchor(anchor: Arc<RwLock<Option<Checkpoint>>>, on_checkpoint: CheckpointHandler) -> CheckpointHandler {
+	Arc::new(move |checkpoint: Checkpoint| {
+		if let Ok(mut anchor) = anchor.write() {
+			*anchor = Some(checkpoint.clone());
+		}
+		on_checkpoint(checkpoint)
+	})
+}
+
+/// Block import rejecting headers at the height of the latest checkpoint that are not the
+/// block anchored in the root chain, so light clients never follow a diverging chain.
+pub struct AnchoredBlockImport<I> {
+	inner: Arc<I>,
+	anchor: Arc<RwLock<Option<Checkpoint>>>,
+}
+
+impl<I> AnchoredBlockImport<I> {
+	pub fn new(inner: Arc<I>, anchor: Arc<RwLock<Option<Checkpoint>>>) -> Self {
+		Self { inner, anchor }
+	}
+
+	fn diverges(&self, number: u64, hash: &Hash) -> bool {
+		match self.anchor.read() {
+			Ok(anchor) => anchor.as_ref()
+				.map_or(false, |checkpoint| checkpoint.number == number && checkpoint.hash[..] != *hash.as_ref()),
+			Err(_) => false,
+		}
+	}
+}
+
+impl<I: BlockImport<Block>> BlockImport<Block> for AnchoredBlockImport<I> {
+	type Error = I::Error;
+
+	fn check_block(&self, hash: Hash, parent_hash: Hash) -> Result<ImportResult, Self::Error> {
+		self.inner.check_block(hash, parent_hash)
+	}
+
+	fn import_block(
+		&self,
+		block: ImportBlock<Block>,
+		new_authorities: Option<Vec<AuthorityIdFor<Block>>>,
+	) -> Result<ImportResult, Self::Error> {
+		let number = *block.header.number();
+		let hash = block.header.hash();
+		if self.diverges(number, &hash) {
+			warn!("Rejected #{} ({}) diverging from the block anchored in the root chain", number, hash);
+			return Ok(ImportResult::KnownBad);
+		}
+		self.inner.import_block(block, new_authorities)
+	}
+}
+
+/// Keep the calls posted for the branch given to the returned handler in `forced_calls`.
+pub fn track_forced_calls(forced_calls: Arc<RwLock<Vec<ForcedCall>>>) -> ForcedCallsHandler {
+	Arc::new(move |posted: Vec<ForcedCall>| {
+		if let Ok(mut forced_calls) = forced_calls.write() {
+			*forced_calls = posted;
+		}
+	})
+}
@@ -2,28 +2,41 @@
 
 #![warn(unused_extern_crates)]
 
-use std::sync::Arc;
-use log::info;
-use transaction_pool::{self, txpool::{Pool as TransactionPool}};
-use yee_branch_runtime::{self, GenesisConfig, opaque::Block, RuntimeApi};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use log::{info, warn};
+use parking_lot::Mutex;
+use transaction_pool::txpool::{Pool as TransactionPool};
+use yee_branch_runtime::{self, GenesisConfig, opaque::{Block, BlockId, Header}, RuntimeApi, api::StakingApi};
 use substrate_service::{
 	FactoryFullConfiguration, LightComponents, FullComponents, FullBackend,
 	FullClient, LightClient, LightBackend, FullExecutor, LightExecutor,
-	TaskExecutor, DefaultRpcHandlerConstructor
+	TaskExecutor,
 };
 use basic_authorship::ProposerFactory;
 use consensus::{import_queue, start_aura, AuraImportQueue, SlotDuration, NothingExtra};
+use grandpa;
 use substrate_client as client;
-use primitives::{ed25519::Pair, Pair as PairT};
+use primitives::{ed25519::{Pair, Public}, Pair as PairT};
 use inherents::InherentDataProviders;
-use network::{construct_simple_protocol, DefaultIdentifySpecialization};
+use runtime_primitives::traits::{Header as HeaderT, ProvideRuntimeApi};
+use network::DefaultIdentifySpecialization;
 use substrate_executor::native_executor_instance;
 use substrate_service::construct_service_factory;
 
 pub use substrate_executor::NativeExecutor;
 use yee_bootnodes_router::BootnodesRouterConf;
 use yee_root_chain;
+use yee_root_port::Checkpoint;
 use crate::cli::{CliTriggerExit, CliSignal};
+use crate::root_port;
+use crate::warp_sync::{self, NodeProtocol, SyncMode, WarpSync};
+use crate::tx_pool::PolicyChainApi;
+use crate::signer::{Endpoint, Signer, LocalSigner, RemoteSigner, SealCheckedImport, SignerPair};
+use crate::keystore::{Keystore, KeyRole};
+use crate::error;
+use crate::custom_rpc::{FullRpcHandlerConstructor, LightRpcHandlerConstructor};
 use substrate_cli::TriggerExit;
 
 pub const IMPL_NAME : &str = "yee-branch-node";
@@ -38,12 +51,138 @@ native_executor_instance!(
 
 #[derive(Default)]
 pub struct NodeConfig {
+	/// GRANDPA block import and link half, created with the full import queue
+	/// and taken by the authority setup.
+	grandpa_import_setup: Option<(Arc<grandpa::BlockImportForService<Factory>>, grandpa::LinkHalfForService<Factory>)>,
 	inherent_data_providers: InherentDataProviders,
 	pub root_bootnodes_router_conf: Option<BootnodesRouterConf>,
 	pub root_port: Option<u16>,
+	pub root_network: yee_root_chain::NetworkParams,
 	pub version_commit: &'static str,
 	pub version_version: &'static str,
 	pub trigger_exit: Option<Arc<dyn yee_consensus::TriggerExit>>,
+	pub finality: Finality,
+	pub sync_mode: SyncMode,
+	/// Node name of the root light node, derived from the branch node name if not given.
+	pub root_name: Option<String>,
+	/// Whether to report the root light node in the branch telemetry.
+	pub root_telemetry: bool,
+	/// Base of the root chain databases, the branch base path if not given.
+	pub root_base_path: Option<PathBuf>,
+	/// Index of the branch port among the root runtime modules with calls.
+	pub root_branch_port_index: Option<u8>,
+	/// Remote signer of the authority key, the keystore key is used if not given.
+	pub signer: Option<Endpoint>,
+	/// Key of the remote signer to use, its first key if not given.
+	pub signer_key: Option<Public>,
+	/// Token the remote signer requires.
+	pub signer_token: Option<String>,
+	/// Password of the role-tagged keys of the keystore.
+	pub key_password: Option<String>,
+	/// Warp sync state shared with the network protocol.
+	warp_sync: WarpSync,
+	/// Latest checkpoint of light clients, created with the light import queue
+	/// and given the checkpoints by the light service.
+	root_anchor: Arc<RwLock<Option<Checkpoint>>>,
+}
+
+/// How branch blocks get finalized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Finality {
+	/// Run the GRANDPA voter among the branch authorities.
+	Grandpa,
+	/// Finalize blocks whose checkpoints are in finalized root chain blocks.
+	Root,
+}
+
+impl Default for Finality {
+	fn default() -> Self {
+		Finality::Grandpa
+	}
+}
+
+impl std::str::FromStr for Finality {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"grandpa" => Ok(Finality::Grandpa),
+			"root" => Ok(Finality::Root),
+			_ => Err(format!("Unknown finality: {}, expected grandpa or root", s)),
+		}
+	}
+}
+
+/// Base of the root chain databases of the branch node.
+pub fn root_base_path(config: &FactoryFullConfiguration<Factory>) -> PathBuf {
+	config.custom.root_base_path.clone()
+		.unwrap_or_else(|| yee_root_chain::default_root_base_path(&config.database_path))
+}
+
+/// Parameters of the root light node of the branch node, attached to the root chain shard,
+/// not relaying nor handling anything from the root chain.
+fn root_chain_params(config: &FactoryFullConfiguration<Factory>, shard_num: u16, branch_id: Vec<u8>) -> yee_root_chain::Params {
+	yee_root_chain::Params {
+		database_path: config.database_path.clone(),
+		branch_name: config.name.clone(),
+		node_name: root_name(config),
+		root_base_path: root_base_path(config),
+		version_commit: config.custom.version_commit,
+		version_version: config.custom.version_version,
+		trigger_exit: config.custom.trigger_exit.clone().expect("qed"),
+		root_bootnodes_router_conf: config.custom.root_bootnodes_router_conf.clone(),
+		root_port: config.custom.root_port,
+		network: config.custom.root_network.clone(),
+		telemetry: config.custom.root_telemetry,
+		shard_num,
+		sharding_count: Arc::new(RwLock::new(None)),
+		branch_id,
+		on_checkpoint: None,
+		on_forced_calls: None,
+		relayer: None,
+	}
+}
+
+/// Node name of the root light node of the branch node.
+pub fn root_name(config: &FactoryFullConfiguration<Factory>) -> String {
+	yee_root_chain::root_node_name(&config.name, config.custom.root_name.as_ref().map(String::as_str))
+}
+
+/// Owner key of the keystore signing root-port meta transactions, if any.
+fn owner_key(config: &FactoryFullConfiguration<Factory>) -> error::Result<Option<Pair>> {
+	let keystore = Keystore::open(config.keystore_path.clone())?;
+	let owner = keystore.list()?.into_iter().find(|(role, _)| *role == KeyRole::Owner);
+	match owner {
+		Some((role, public)) => {
+			let pair = keystore.load_ed25519(role, &public, config.custom.key_password.as_ref().map(String::as_str))?;
+			info!("Using owner key {}", pair.public());
+			Ok(Some(pair))
+		},
+		None => Ok(None),
+	}
+}
+
+/// Relayer key of the keystore paying the root chain fees of root-port meta transactions, if any.
+fn relayer(config: &FactoryFullConfiguration<Factory>) -> error::Result<Option<yee_root_chain::Relayer>> {
+	let keystore = Keystore::open(config.keystore_path.clone())?;
+	let relayer = keystore.list()?.into_iter().find(|(role, _)| *role == KeyRole::Relayer);
+	let public = match relayer {
+		Some((_, public)) => public,
+		None => return Ok(None),
+	};
+	let branch_port_index = match config.custom.root_branch_port_index {
+		Some(index) => index,
+		None => {
+			warn!("Relayer key found but no --root-branch-port-index, not relaying meta transactions");
+			return Ok(None);
+		},
+	};
+	let key = keystore.load_sr25519(KeyRole::Relayer, &public, config.custom.key_password.as_ref().map(String::as_str))?;
+	info!("Relaying meta transactions from root chain account {}", key.public());
+	Ok(Some(yee_root_chain::Relayer {
+		key: Arc::new(key),
+		branch_port_index,
+	}))
 }
 
 impl yee_consensus::TriggerExit for CliTriggerExit<CliSignal>{
@@ -56,21 +195,16 @@ impl yee_consensus::TriggerExit for CliTriggerExit<CliSignal>{
 	}
 }
 
-construct_simple_protocol! {
-	/// Demo protocol attachment for substrate.
-	pub struct NodeProtocol where Block = Block { }
-}
-
 construct_service_factory! {
 	struct Factory {
 		Block = Block,
 		RuntimeApi = RuntimeApi,
-		NetworkProtocol = NodeProtocol { |config| Ok(NodeProtocol::new()) },
+		NetworkProtocol = NodeProtocol { |config: &FactoryFullConfiguration<Self>| Ok(NodeProtocol::new(config.custom.warp_sync.clone())) },
 		RuntimeDispatch = Executor,
-		FullTransactionPoolApi = transaction_pool::ChainApi<client::Client<FullBackend<Self>, FullExecutor<Self>, Block, RuntimeApi>, Block>
-			{ |config, client| Ok(TransactionPool::new(config, transaction_pool::ChainApi::new(client))) },
-		LightTransactionPoolApi = transaction_pool::ChainApi<client::Client<LightBackend<Self>, LightExecutor<Self>, Block, RuntimeApi>, Block>
-			{ |config, client| Ok(TransactionPool::new(config, transaction_pool::ChainApi::new(client))) },
+		FullTransactionPoolApi = PolicyChainApi<FullBackend<Self>, FullExecutor<Self>, RuntimeApi>
+			{ |config, client| Ok(TransactionPool::new(config, PolicyChainApi::new(client))) },
+		LightTransactionPoolApi = PolicyChainApi<LightBackend<Self>, LightExecutor<Self>, RuntimeApi>
+			{ |config, client| Ok(TransactionPool::new(config, PolicyChainApi::new(client))) },
 		Genesis = GenesisConfig,
 		Configuration = NodeConfig,
 		FullService = FullComponents<Self>
@@ -78,9 +212,41 @@ construct_service_factory! {
 				FullComponents::<Factory>::new(config, executor)
 			},
 		AuthoritySetup = {
-			|service: Self::FullService, executor: TaskExecutor, key: Option<Arc<Pair>>| {
-				if let Some(key) = key {
-					info!("Using authority key {}", key.public());
+			|mut service: Self::FullService, executor: TaskExecutor, key: Option<Arc<Pair>>| {
+				let (block_import, link_half) = service.config.custom.grandpa_import_setup.take()
+					.expect("Link Half and Block Import are present for Full Services or setup failed before. qed");
+
+				let sharding_count = Arc::new(RwLock::new(None));
+				service.config.custom.inherent_data_providers
+					.register_provider(yee_root_port::InherentDataProvider::new(sharding_count.clone()))
+					.map_err(|e| format!("{:?}", e))?;
+				let forced_calls = Arc::new(RwLock::new(Vec::new()));
+				service.config.custom.inherent_data_providers
+					.register_provider(yee_root_port::ForcedCallsDataProvider::new(forced_calls.clone()))
+					.map_err(|e| format!("{:?}", e))?;
+				let offline_client = service.client();
+				service.config.custom.inherent_data_providers
+					.register_provider(yee_staking::OfflineReportDataProvider::new(move || {
+						offline_client.info().ok()
+							.and_then(|info| offline_client.runtime_api()
+								.offline_authorities(&BlockId::hash(info.chain.best_hash)).ok())
+							.unwrap_or_default()
+					}))
+					.map_err(|e| format!("{:?}", e))?;
+
+				let signer: Option<Arc<dyn Signer>> = match service.config.custom.signer.clone() {
+					Some(endpoint) => Some(Arc::new(
+						RemoteSigner::connect(
+							endpoint,
+							service.config.custom.signer_key.clone(),
+							service.config.custom.signer_token.clone(),
+						).map_err(|e| format!("{}", e))?
+					)),
+					None => key.clone().map(|key| Arc::new(LocalSigner::new(key)) as Arc<dyn Signer>),
+				};
+
+				if let Some(ref signer) = signer {
+					info!("Using authority key {}", signer.public());
 					let proposer = Arc::new(ProposerFactory {
 						client: service.client(),
 						transaction_pool: service.transaction_pool(),
@@ -89,9 +255,9 @@ construct_service_factory! {
 					let client = service.client();
 					executor.clone().spawn(start_aura(
 						SlotDuration::get_or_compute(&*client)?,
-						key.clone(),
-						client.clone(),
+						Arc::new(SignerPair::new(signer.clone())),
 						client,
+						Arc::new(SealCheckedImport::new(block_import.clone(), signer.public())),
 						proposer,
 						service.network(),
 						service.on_exit(),
@@ -100,30 +266,147 @@ construct_service_factory! {
 					)?);
 				}
 
-				let root_chain_param = yee_root_chain::Params{
-					database_path: service.config.database_path.clone(),
-					keystore_path: service.config.keystore_path.clone(),
-					version_commit: service.config.custom.version_commit,
-					version_version: service.config.custom.version_version,
-					trigger_exit: service.config.custom.trigger_exit.clone().expect("qed"),
-					root_bootnodes_router_conf: service.config.custom.root_bootnodes_router_conf.clone(),
-					root_port: service.config.custom.root_port,
+				let client = service.client();
+
+				let on_checkpoint = match service.config.custom.finality {
+					Finality::Grandpa => {
+						let grandpa_key = if service.config.disable_grandpa {
+							None
+						} else if service.config.custom.signer.is_some() {
+							// the voter signs with the secret of the key, which a remote signer keeps
+							return Err(
+								"GRANDPA votes can not be signed by a remote signer, use --finality root or --no-grandpa".into()
+							);
+						} else {
+							key.clone()
+						};
+						if let Some(ref key) = grandpa_key {
+							info!("Running Grandpa session as Authority {}", key.public());
+						}
+						executor.spawn(grandpa::run_grandpa(
+							grandpa::Config {
+								local_key: grandpa_key,
+								gossip_duration: Duration::from_millis(333),
+								justification_period: 4096,
+								name: Some(service.config.name.clone()),
+							},
+							link_half,
+							grandpa::NetworkBridge::new(service.network()),
+							service.config.custom.inherent_data_providers.clone(),
+							service.on_exit(),
+						)?);
+						None
+					},
+					Finality::Root => {
+						info!("Finalizing blocks anchored in the root chain");
+						Some(root_port::finalize_on_checkpoints(client.clone(), service.on_exit(), &executor))
+					},
+				};
+
+				let chain_info = client.info()?.chain;
+				let best_hash = chain_info.best_hash;
+				let shard_num = root_port::root_shard_num(&*client, &BlockId::hash(best_hash)).map_err(|e| format!("{}", e))?;
+
+				service.config.custom.warp_sync.serve(client.clone());
+				let on_checkpoint = match service.config.custom.sync_mode {
+					SyncMode::Warp if chain_info.best_number == 0 => {
+						let network = service.network();
+						let warped_client = client.clone();
+						let finality = service.config.custom.finality;
+						let trigger_exit = service.config.custom.trigger_exit.clone();
+						let on_imported = Box::new(move |header: &Header| {
+							// regular sync continues from the warped-to head
+							let hash = header.hash();
+							network.on_block_imported(hash, header.clone());
+							network.on_block_finalized(hash, header.clone());
+
+							// the voter started from the genesis authority set
+							if finality == Finality::Grandpa {
+								match warp_sync::reset_grandpa_authorities(&*warped_client, header) {
+									Ok(()) => if let Some(ref trigger_exit) = trigger_exit {
+										info!("Restarting for the GRANDPA voter to start from the warped-to authority set");
+										trigger_exit.trigger_restart();
+									},
+									Err(e) => warn!("Failed to reset the GRANDPA authority set: {}", e),
+								}
+							}
+						});
+						Some(service.config.custom.warp_sync.start_on_checkpoint(client.clone(), on_checkpoint, on_imported))
+					},
+					_ => on_checkpoint,
+				};
+
+				let relayer = relayer(&service.config).map_err(|e| format!("{}", e))?;
+				let root_chain_param = yee_root_chain::Params {
+					sharding_count,
+					on_checkpoint,
+					on_forced_calls: Some(root_port::track_forced_calls(forced_calls)),
+					relayer: relayer.clone(),
+					..root_chain_params(&service.config, shard_num, chain_info.genesis_hash.as_ref().to_vec())
 				};
 				let root_chain = yee_root_chain::RootChain::new(root_chain_param, &executor).map_err(|e|format!("{:?}", e))?;
 
+				let root_chain = Arc::new(Mutex::new(root_chain));
+
+				let owner_signer = match owner_key(&service.config).map_err(|e| format!("{}", e))? {
+					Some(owner_key) => Some(Arc::new(LocalSigner::new(Arc::new(owner_key))) as Arc<dyn Signer>),
+					None => signer,
+				};
+				if let Some(owner_signer) = owner_signer {
+					if relayer.is_none() {
+						warn!("No relayer key, meta transactions are kept until a relayer key is set");
+					}
+					root_port::relay_meta_txs(client.clone(), root_chain.clone(), owner_signer, service.on_exit(), &executor);
+				}
+
+				root_port::follow_root_shard(client, root_chain, service.on_exit(), &executor);
+
 				Ok(service)
 			}
 		},
 		LightService = LightComponents<Self>
-			{ |config, executor| <LightComponents<Factory>>::new(config, executor) },
+			{ |config, executor: TaskExecutor| {
+				let service = <LightComponents<Factory>>::new(config, executor.clone())?;
+
+				// light clients take branch finality from the root chain anchors,
+				// and reject the headers diverging from them
+				let client = service.client();
+				let on_checkpoint = root_port::record_anchor(
+					service.config.custom.root_anchor.clone(),
+					root_port::finalize_on_checkpoints(client.clone(), service.on_exit(), &executor),
+				);
+
+				let chain_info = client.info()?.chain;
+				let best_hash = chain_info.best_hash;
+				let shard_num = root_port::root_shard_num(&*client, &BlockId::hash(best_hash)).map_err(|e| format!("{}", e))?;
+
+				let root_chain_param = yee_root_chain::Params {
+					on_checkpoint: Some(on_checkpoint),
+					..root_chain_params(&service.config, shard_num, chain_info.genesis_hash.as_ref().to_vec())
+				};
+				let root_chain = yee_root_chain::RootChain::new(root_chain_param, &executor).map_err(|e|format!("{:?}", e))?;
+
+				root_port::follow_root_shard(client, Arc::new(Mutex::new(root_chain)), service.on_exit(), &executor);
+
+				Ok(service)
+			}
+		},
 		FullImportQueue = AuraImportQueue<
 			Self::Block,
 		>
 			{ |config: &mut FactoryFullConfiguration<Self> , client: Arc<FullClient<Self>>| {
+					let (block_import, link_half) = grandpa::block_import::<_, _, _, RuntimeApi, FullClient<Self>>(
+						client.clone(), client.clone()
+					)?;
+					let block_import = Arc::new(block_import);
+					let justification_import = block_import.clone();
+
+					config.custom.grandpa_import_setup = Some((block_import.clone(), link_half));
+
 					import_queue::<_, _, _, Pair>(
 						SlotDuration::get_or_compute(&*client)?,
-						client.clone(),
-						None,
+						block_import,
+						Some(justification_import),
 						client,
 						NothingExtra,
 						config.custom.inherent_data_providers.clone(),
@@ -134,9 +417,13 @@ construct_service_factory! {
 			Self::Block,
 		>
 			{ |config: &mut FactoryFullConfiguration<Self>, client: Arc<LightClient<Self>>| {
+					let block_import = Arc::new(root_port::AnchoredBlockImport::new(
+						client.clone(), config.custom.root_anchor.clone()
+					));
+
 					import_queue::<_, _, _, Pair>(
 						SlotDuration::get_or_compute(&*client)?,
-						client.clone(),
+						block_import,
 						None,
 						client,
 						NothingExtra,
@@ -144,8 +431,8 @@ construct_service_factory! {
 					).map_err(Into::into)
 				}
 			},
-		FullRpcHandlerConstructor = DefaultRpcHandlerConstructor,
-		LightRpcHandlerConstructor = DefaultRpcHandlerConstructor,
+		FullRpcHandlerConstructor = FullRpcHandlerConstructor,
+		LightRpcHandlerConstructor = LightRpcHandlerConstructor,
 		IdentifySpecialization = DefaultIdentifySpecialization { |config| Ok(DefaultIdentifySpecialization{}) },
 	}
 }
@@ -0,0 +1,863 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Signers of the authority and owner keys, so the secrets need not live in the node.
+//!
+//! A remote signer speaks JSON-RPC, over HTTP or as one request per line over a Unix socket,
+//! each request carrying the secret token of the signer as its `token` member:
+//! `signer_publicKeys` returns the hex public keys it holds, and `signer_sign` with a hex public
+//! key and message returns the hex ed25519 signature. `yee-branch signer` is a reference daemon,
+//! which never signs two Aura seals for a slot with a key.
+//!
+//! Signing can not fail for the consensus engines, so a block sealed while the signer fails is
+//! dropped by `SealCheckedImport` instead of imported, which skips the slot.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::{fs::{OpenOptionsExt, PermissionsExt}, net::{UnixListener, UnixStream}};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use std::thread;
+use std::time::Duration;
+use log::{info, warn};
+use parking_lot::Mutex;
+use parity_codec::{Encode, Decode};
+use primitives::{
+	ed25519::{self, Public, Signature}, Pair as PairT, hexdisplay::HexDisplay,
+	crypto::{DeriveJunction, SecretStringError},
+};
+use runtime_primitives::{generic::DigestItem, traits::{AuthorityIdFor, Header as HeaderT}};
+use consensus_common::{BlockImport, BlockOrigin, ImportBlock, ImportResult};
+use serde_json::{json, Value};
+use yee_branch_runtime::{Hash, opaque::Block};
+use crate::error;
+
+/// Time allowed to connect to a remote signer, and for each read and write of a call.
+const CALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time allowed to connect to a remote signer, and for each read and write of a signature,
+/// short for a slow signer to cost the slot rather than hold up the slot worker.
+const SIGN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often a remote signer is checked to hold the key, signatures being refused without
+/// calling it while it does not.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Length of the `(slot, pre_hash)` payload of Aura seals.
+const AURA_SEAL_PAYLOAD_LEN: usize = 40;
+
+/// Largest request body the reference daemon reads.
+const MAX_REQUEST_SIZE: usize = 64 * 1024;
+
+/// Signs with an ed25519 key.
+pub trait Signer: Send + Sync {
+	fn public(&self) -> Public;
+
+	fn sign(&self, message: &[u8]) -> error::Result<Signature>;
+}
+
+/// Signer holding the key in the node.
+pub struct LocalSigner(Arc<ed25519::Pair>);
+
+impl LocalSigner {
+	pub fn new(pair: Arc<ed25519::Pair>) -> Self {
+		LocalSigner(pair)
+	}
+}
+
+impl Signer for LocalSigner {
+	fn public(&self) -> Public {
+		self.0.public()
+	}
+
+	fn sign(&self, message: &[u8]) -> error::Result<Signature> {
+		Ok(self.0.sign(message))
+	}
+}
+
+/// Where a remote signer listens.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+	/// `unix:///path/to/socket`
+	Unix(PathBuf),
+	/// `http://host:port[/path]`
+	Http { address: String, path: String },
+}
+
+impl FromStr for Endpoint {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		if s.starts_with("unix://") {
+			Ok(Endpoint::Unix(PathBuf::from(&s["unix://".len()..])))
+		} else if s.starts_with("http://") {
+			let rest = &s["http://".len()..];
+			let (address, path) = match rest.find('/') {
+				Some(i) => (&rest[..i], &rest[i..]),
+				None => (rest, "/"),
+			};
+			Ok(Endpoint::Http { address: address.to_string(), path: path.to_string() })
+		} else {
+			Err(format!("Unknown signer endpoint: {}, expected unix://<path> or http://<host:port>", s))
+		}
+	}
+}
+
+impl Endpoint {
+	/// Call a JSON-RPC method of the endpoint, returning its result.
+	pub(crate) fn call(&self, method: &str, params: Value) -> error::Result<Value> {
+		self.call_with(method, params, None, CALL_TIMEOUT)
+	}
+
+	/// Call a JSON-RPC method of the endpoint with the token, allowing `timeout` for each read
+	/// and write.
+	fn call_with(&self, method: &str, params: Value, token: Option<&str>, timeout: Duration) -> error::Result<Value> {
+		let mut request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+		if let Some(token) = token {
+			request["token"] = Value::String(token.to_string());
+		}
+		let request = request.to_string();
+
+		let response = match self {
+			Endpoint::Unix(path) => call_unix(path, &request, timeout)?,
+			Endpoint::Http { address, path } => {
+				let mut stream = connect_tcp(address, timeout)?;
+				stream.set_read_timeout(Some(timeout))?;
+				stream.set_write_timeout(Some(timeout))?;
+				write!(stream, "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+					path, address, request.len(), request)?;
+				let mut response = String::new();
+				stream.read_to_string(&mut response)?;
+				match response.find("\r\n\r\n") {
+					Some(i) => response[i + 4..].to_string(),
+					None => return Err("Invalid HTTP response".into()),
+				}
+			},
+		};
+
+		let response: Value = serde_json::from_str(&response).map_err(|e| format!("Invalid JSON-RPC response: {:?}", e))?;
+		if let Some(error) = response.get("error") {
+			return Err(format!("JSON-RPC error: {}", error).into());
+		}
+		response.get("result").cloned().ok_or_else(|| "JSON-RPC response without result".into())
+	}
+}
+
+fn connect_tcp(address: &str, timeout: Duration) -> error::Result<TcpStream> {
+	let mut last_error = None;
+	for address in address.to_socket_addrs()? {
+		match TcpStream::connect_timeout(&address, timeout) {
+			Ok(stream) => return Ok(stream),
+			Err(e) => last_error = Some(e),
+		}
+	}
+	Err(match last_error {
+		Some(e) => e.into(),
+		None => format!("Signer address {} does not resolve", address).into(),
+	})
+}
+
+#[cfg(unix)]
+fn call_unix(path: &Path, request: &str, timeout: Duration) -> error::Result<String> {
+	// connecting to a unix socket does not block
+	let mut stream = UnixStream::connect(path)?;
+	stream.set_read_timeout(Some(timeout))?;
+	stream.set_write_timeout(Some(timeout))?;
+	stream.write_all(request.as_bytes())?;
+	stream.write_all(b"\n")?;
+	let mut line = String::new();
+	BufReader::new(stream).read_line(&mut line)?;
+	Ok(line)
+}
+
+#[cfg(not(unix))]
+fn call_unix(_path: &Path, _request: &str, _timeout: Duration) -> error::Result<String> {
+	Err("Unix socket signers are not supported on this platform".into())
+}
+
+/// Signer asking a remote signer for every signature.
+pub struct RemoteSigner {
+	endpoint: Endpoint,
+	public: Public,
+	token: Option<String>,
+	/// Whether the signer held the key when last called or checked.
+	available: Arc<AtomicBool>,
+}
+
+impl RemoteSigner {
+	/// Connect to the signer with the token, using the key if given or the first key of the
+	/// signer, and keep checking it holds the key.
+	pub fn connect(endpoint: Endpoint, public: Option<Public>, token: Option<String>) -> error::Result<Self> {
+		let keys = public_keys(&endpoint, token.as_ref().map(String::as_str))?;
+		let public = match public {
+			Some(public) if keys.contains(&public) => public,
+			Some(public) => return Err(format!("Signer does not hold key {}", public).into()),
+			None => keys.into_iter().next().ok_or("Signer holds no key")?,
+		};
+
+		info!("Using remote signer {:?} with key {}", endpoint, public);
+		let signer = RemoteSigner { endpoint, public, token, available: Arc::new(AtomicBool::new(true)) };
+		signer.check_availability();
+		Ok(signer)
+	}
+
+	/// Check the signer in the background until the signer is dropped.
+	fn check_availability(&self) {
+		let (endpoint, public, token) = (self.endpoint.clone(), self.public.clone(), self.token.clone());
+		let available = Arc::downgrade(&self.available);
+		let spawned = thread::Builder::new().name("remote-signer-check".into()).spawn(move || {
+			loop {
+				thread::sleep(CHECK_INTERVAL);
+				let holds_key = public_keys(&endpoint, token.as_ref().map(String::as_str))
+					.map_or(false, |keys| keys.contains(&public));
+				let available = match available.upgrade() {
+					Some(available) => available,
+					None => return,
+				};
+				if available.swap(holds_key, Ordering::SeqCst) != holds_key {
+					if holds_key {
+						info!("Remote signer {:?} holds key {} again", endpoint, public);
+					} else {
+						warn!("Remote signer {:?} unavailable for key {}, slots are skipped", endpoint, public);
+					}
+				}
+			}
+		});
+		if let Err(e) = spawned {
+			warn!("Failed to start checking the remote signer: {}", e);
+		}
+	}
+}
+
+/// Public keys a remote signer holds.
+fn public_keys(endpoint: &Endpoint, token: Option<&str>) -> error::Result<Vec<Public>> {
+	let keys = endpoint.call_with("signer_publicKeys", json!([]), token, CALL_TIMEOUT)?;
+	Ok(keys.as_array().ok_or("Invalid signer public keys")?
+		.iter()
+		.filter_map(|key| key.as_str().and_then(|key| parse_hex(key).ok()))
+		.filter(|key| key.len() == 32)
+		.map(|key| Public::from_slice(&key))
+		.collect())
+}
+
+impl Signer for RemoteSigner {
+	fn public(&self) -> Public {
+		self.public.clone()
+	}
+
+	fn sign(&self, message: &[u8]) -> error::Result<Signature> {
+		if !self.available.load(Ordering::SeqCst) {
+			return Err("Remote signer unavailable".into());
+		}
+		let params = json!([to_hex(self.public.as_ref()), to_hex(message)]);
+		let signature = self.endpoint.call_with("signer_sign", params, self.token.as_ref().map(String::as_str), SIGN_TIMEOUT)
+			.and_then(|signature| parse_hex(signature.as_str().ok_or("Invalid signature")?))
+			.map_err(|e| {
+				// the next check tells when it is back
+				self.available.store(false, Ordering::SeqCst);
+				e
+			})?;
+		if signature.len() != 64 {
+			return Err("Invalid signature length".into());
+		}
+		let signature = Signature::from_slice(&signature);
+		if !ed25519::Pair::verify(&signature, message, &self.public) {
+			return Err("Signer returned a signature not matching the key".into());
+		}
+		Ok(signature)
+	}
+}
+
+/// Keys can not be derived through a signer, the secret may not be here to derive from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CannotDerive;
+
+/// ed25519 pair whose signatures come from a signer, for the consensus engines which sign
+/// with a pair. Built from a secret, it signs locally like an `ed25519::Pair`.
+#[derive(Clone)]
+pub struct SignerPair(Arc<dyn Signer>);
+
+impl SignerPair {
+	pub fn new(signer: Arc<dyn Signer>) -> Self {
+		SignerPair(signer)
+	}
+
+	pub fn signer(&self) -> Arc<dyn Signer> {
+		self.0.clone()
+	}
+
+	fn local(pair: ed25519::Pair) -> Self {
+		SignerPair(Arc::new(LocalSigner::new(Arc::new(pair))))
+	}
+}
+
+impl PairT for SignerPair {
+	type Public = Public;
+	type Seed = <ed25519::Pair as PairT>::Seed;
+	type Signature = Signature;
+	type DeriveError = CannotDerive;
+
+	fn generate() -> Self {
+		Self::local(ed25519::Pair::generate())
+	}
+
+	fn generate_with_phrase(password: Option<&str>) -> (Self, String) {
+		let (pair, phrase) = ed25519::Pair::generate_with_phrase(password);
+		(Self::local(pair), phrase)
+	}
+
+	fn from_phrase(phrase: &str, password: Option<&str>) -> Result<Self, SecretStringError> {
+		ed25519::Pair::from_phrase(phrase, password).map(Self::local)
+	}
+
+	fn derive<Iter: Iterator<Item=DeriveJunction>>(&self, _path: Iter) -> Result<Self, Self::DeriveError> {
+		Err(CannotDerive)
+	}
+
+	fn from_seed(seed: Self::Seed) -> Self {
+		Self::local(ed25519::Pair::from_seed(seed))
+	}
+
+	fn from_seed_slice(seed: &[u8]) -> Result<Self, SecretStringError> {
+		ed25519::Pair::from_seed_slice(seed).map(Self::local)
+	}
+
+	fn from_standard_components<I: Iterator<Item=DeriveJunction>>(
+		seed: &str,
+		password: Option<&str>,
+		path: I,
+	) -> Result<Self, SecretStringError> {
+		ed25519::Pair::from_standard_components(seed, password, path).map(Self::local)
+	}
+
+	fn sign(&self, message: &[u8]) -> Self::Signature {
+		match self.0.sign(message) {
+			Ok(signature) => signature,
+			Err(e) => {
+				// the consensus engines expect a signature: an invalid one makes `SealCheckedImport`
+				// drop the block sealed with it
+				warn!("Signing failed: {}", e);
+				Signature::default()
+			},
+		}
+	}
+
+	fn verify<P: AsRef<Self::Public>, M: AsRef<[u8]>>(sig: &Self::Signature, message: M, pubkey: P) -> bool {
+		ed25519::Pair::verify(sig, message, pubkey)
+	}
+
+	fn verify_weak<P: AsRef<[u8]>, M: AsRef<[u8]>>(sig: &[u8], message: M, pubkey: P) -> bool {
+		ed25519::Pair::verify_weak(sig, message, pubkey)
+	}
+
+	fn public(&self) -> Self::Public {
+		self.0.public()
+	}
+}
+
+/// Block import dropping the blocks the node sealed without a valid seal of the key, the
+/// signer having failed, so that the slot is skipped rather than built on a block every peer
+/// rejects.
+pub struct SealCheckedImport<I> {
+	inner: Arc<I>,
+	public: Public,
+}
+
+impl<I> SealCheckedImport<I> {
+	pub fn new(inner: Arc<I>, public: Public) -> Self {
+		Self { inner, public }
+	}
+
+	/// Whether the block has an Aura seal of the key.
+	fn is_sealed(&self, block: &ImportBlock<Block>) -> bool {
+		let pre_hash = block.header.hash();
+		block.post_digests.iter().any(|item| match item {
+			DigestItem::Seal(slot, signature) => ed25519::Pair::verify(signature, (slot, pre_hash).encode(), &self.public),
+			_ => false,
+		})
+	}
+}
+
+impl<I: BlockImport<Block>> BlockImport<Block> for SealCheckedImport<I> {
+	type Error = I::Error;
+
+	fn check_block(&self, hash: Hash, parent_hash: Hash) -> Result<ImportResult, Self::Error> {
+		self.inner.check_block(hash, parent_hash)
+	}
+
+	fn import_block(
+		&self,
+		block: ImportBlock<Block>,
+		new_authorities: Option<Vec<AuthorityIdFor<Block>>>,
+	) -> Result<ImportResult, Self::Error> {
+		if block.origin == BlockOrigin::Own && !self.is_sealed(&block) {
+			warn!("Dropped #{} built on {}, its seal could not be signed", block.header.number(), block.header.parent_hash());
+			return Ok(ImportResult::KnownBad);
+		}
+		self.inner.import_block(block, new_authorities)
+	}
+}
+
+/// Reference remote signer: the keys it serves, the token its clients must present, and the
+/// Aura slots signed.
+pub struct Daemon {
+	keys: Vec<ed25519::Pair>,
+	token: String,
+	slots: Mutex<SignedSlots>,
+}
+
+impl Daemon {
+	/// Daemon of the keys, keeping the slots signed in the file at `slots_path`.
+	pub fn new(keys: Vec<ed25519::Pair>, token: String, slots_path: PathBuf) -> error::Result<Self> {
+		for pair in &keys {
+			info!("Signer holds key {}", pair.public());
+		}
+		Ok(Daemon { keys, token, slots: Mutex::new(SignedSlots::open(slots_path)?) })
+	}
+}
+
+/// Last Aura slot and pre-seal hash signed with each key, kept in a file so that a restarted
+/// daemon does not sign a second block for a slot either.
+///
+/// Aura seals are recognized by the length of their payload, so messages of other kinds
+/// signed with authority keys must not be of that length.
+struct SignedSlots {
+	path: PathBuf,
+	signed: HashMap<Vec<u8>, (u64, Vec<u8>)>,
+}
+
+impl SignedSlots {
+	fn open(path: PathBuf) -> error::Result<Self> {
+		let signed = match fs::read(&path) {
+			Ok(data) => Vec::<(Vec<u8>, u64, Vec<u8>)>::decode(&mut &data[..])
+				.ok_or_else(|| format!("Invalid signed slots file {}", path.display()))?
+				.into_iter()
+				.map(|(public, slot, pre_hash)| (public, (slot, pre_hash)))
+				.collect(),
+			Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+			Err(e) => return Err(e.into()),
+		};
+		Ok(SignedSlots { path, signed })
+	}
+
+	/// Record that the key signs the message, refusing a second Aura seal for a slot, or a
+	/// seal for a slot before the last one sealed.
+	fn sign(&mut self, public: &[u8], message: &[u8]) -> Result<(), String> {
+		if message.len() != AURA_SEAL_PAYLOAD_LEN {
+			return Ok(());
+		}
+		let slot = u64::decode(&mut &message[..8]).ok_or("Invalid slot")?;
+		let pre_hash = &message[8..];
+		match self.signed.get(public) {
+			Some((last, signed)) if slot == *last && signed[..] == *pre_hash => return Ok(()),
+			Some((last, _)) if slot <= *last => {
+				return Err(format!("Refused to seal another block for slot {}, slot {} is sealed", slot, last));
+			},
+			_ => (),
+		}
+
+		self.signed.insert(public.to_vec(), (slot, pre_hash.to_vec()));
+		self.persist().map_err(|e| format!("Failed to record the signed slot: {}", e))
+	}
+
+	fn persist(&self) -> std::io::Result<()> {
+		let signed = self.signed.iter()
+			.map(|(public, (slot, pre_hash))| (public.clone(), *slot, pre_hash.clone()))
+			.collect::<Vec<_>>();
+		let temporary = self.path.with_extension("tmp");
+		let mut file = fs::File::create(&temporary)?;
+		file.write_all(&signed.encode())?;
+		file.sync_all()?;
+		fs::rename(&temporary, &self.path)
+	}
+}
+
+/// Token of the daemon in the file, created with a random one readable only by the user if
+/// the file does not exist.
+pub fn daemon_token(path: &Path) -> error::Result<String> {
+	if path.exists() {
+		return load_token(path);
+	}
+	if let Some(parent) = path.parent() {
+		fs::create_dir_all(parent)?;
+	}
+	let token = format!("{}", HexDisplay::from(&rand::random::<[u8; 32]>()));
+	let mut options = fs::OpenOptions::new();
+	options.write(true).create_new(true);
+	#[cfg(unix)]
+	options.mode(0o600);
+	options.open(path)?.write_all(token.as_bytes())?;
+	info!("Signer token written to {}", path.display());
+	Ok(token)
+}
+
+/// Token of a signer in the file.
+pub fn load_token(path: &Path) -> error::Result<String> {
+	let token = fs::read_to_string(path)?.trim().to_string();
+	if token.is_empty() {
+		return Err(format!("Empty signer token file {}", path.display()).into());
+	}
+	Ok(token)
+}
+
+/// Serve the keys to remote signer clients until the process exits.
+pub fn serve(endpoint: &Endpoint, daemon: Daemon) -> error::Result<()> {
+	let daemon = Arc::new(daemon);
+
+	match endpoint {
+		Endpoint::Unix(path) => serve_unix(path, daemon)?,
+		Endpoint::Http { address, .. } => {
+			let listener = TcpListener::bind(address.as_str())?;
+			info!("Signer listening on http://{}", address);
+			for stream in listener.incoming() {
+				let stream = stream?;
+				let daemon = daemon.clone();
+				thread::spawn(move || {
+					if let Err(e) = serve_http(&daemon, stream) {
+						warn!("Signer request failed: {:?}", e);
+					}
+				});
+			}
+		},
+	}
+	Ok(())
+}
+
+#[cfg(unix)]
+fn serve_unix(path: &Path, daemon: Arc<Daemon>) -> error::Result<()> {
+	let _ = fs::remove_file(path);
+	let listener = UnixListener::bind(path)?;
+	// only the user may connect
+	fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+	info!("Signer listening on {:?}", path);
+	for stream in listener.incoming() {
+		let stream = stream?;
+		let daemon = daemon.clone();
+		thread::spawn(move || {
+			let mut reader = BufReader::new(match stream.try_clone() {
+				Ok(stream) => stream,
+				Err(_) => return,
+			});
+			let mut writer = stream;
+			let mut line = String::new();
+			while reader.read_line(&mut line).map(|n| n > 0).unwrap_or(false) {
+				let response = handle(&daemon, &line);
+				if writer.write_all(response.as_bytes()).and_then(|_| writer.write_all(b"\n")).is_err() {
+					break;
+				}
+				line.clear();
+			}
+		});
+	}
+	Ok(())
+}
+
+#[cfg(not(unix))]
+fn serve_unix(_path: &Path, _daemon: Arc<Daemon>) -> error::Result<()> {
+	Err("Unix socket signers are not supported on this platform".into())
+}
+
+fn serve_http(daemon: &Daemon, stream: TcpStream) -> std::io::Result<()> {
+	let mut reader = BufReader::new(stream.try_clone()?);
+	let mut content_length = 0;
+	let mut line = String::new();
+	loop {
+		line.clear();
+		if reader.read_line(&mut line)? == 0 || line == "\r\n" {
+			break;
+		}
+		let lower = line.to_lowercase();
+		if lower.starts_with("content-length:") {
+			content_length = lower["content-length:".len()..].trim().parse().unwrap_or(0);
+		}
+	}
+	if content_length > MAX_REQUEST_SIZE {
+		return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Signer request too large"));
+	}
+	let mut body = vec![0u8; content_length];
+	reader.read_exact(&mut body)?;
+
+	let response = handle(daemon, &String::from_utf8_lossy(&body));
+	let mut stream = stream;
+	write!(stream, "HTTP/1.0 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", response.len(), response)
+}
+
+fn handle(daemon: &Daemon, request: &str) -> String {
+	let request: Value = match serde_json::from_str(request) {
+		Ok(request) => request,
+		Err(_) => return json!({ "jsonrpc": "2.0", "id": Value::Null, "error": "Invalid request" }).to_string(),
+	};
+	let id = request.get("id").cloned().unwrap_or(Value::Null);
+	let params = request.get("params").and_then(Value::as_array).cloned().unwrap_or_default();
+	let token = request.get("token").and_then(Value::as_str).unwrap_or_default();
+
+	let result = if !token_matches(&daemon.token, token) {
+		Err("Unauthorized".to_string())
+	} else {
+		match request.get("method").and_then(Value::as_str) {
+			Some("signer_publicKeys") => Ok(Value::Array(daemon.keys.iter().map(|pair| Value::String(to_hex(pair.public().as_ref()))).collect())),
+			Some("signer_sign") => sign_request(daemon, &params),
+			_ => Err("Unknown method".to_string()),
+		}
+	};
+
+	match result {
+		Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+		Err(e) => json!({ "jsonrpc": "2.0", "id": id, "error": e }),
+	}.to_string()
+}
+
+/// Compare the tokens in a time not depending on where they differ.
+fn token_matches(expected: &str, token: &str) -> bool {
+	expected.len() == token.len() && expected.bytes().zip(token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn sign_request(daemon: &Daemon, params: &[Value]) -> Result<Value, String> {
+	let public = params.get(0).and_then(Value::as_str).ok_or("Missing public key")?;
+	let message = params.get(1).and_then(Value::as_str).ok_or("Missing message")?;
+	let public = parse_hex(public).map_err(|e| format!("{}", e))?;
+	let message = parse_hex(message).map_err(|e| format!("{}", e))?;
+
+	let pair = daemon.keys.iter().find(|pair| pair.public().as_ref() == &public[..]).ok_or("Unknown key")?;
+	daemon.slots.lock().sign(&public, &message)?;
+	Ok(Value::String(to_hex(pair.sign(&message).as_ref())))
+}
+
+fn to_hex(data: &[u8]) -> String {
+	format!("0x{}", HexDisplay::from(&data))
+}
+
+pub(crate) fn parse_hex(s: &str) -> error::Result<Vec<u8>> {
+	let s = s.trim_start_matches("0x");
+	if s.len() % 2 != 0 {
+		return Err(format!("Invalid hex: {}", s).into());
+	}
+	(0..s.len()).step_by(2)
+		.map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| format!("Invalid hex: {}", s).into()))
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const TOKEN: &str = "secret";
+
+	fn pair() -> ed25519::Pair {
+		ed25519::Pair::from_seed([1u8; 32])
+	}
+
+	/// Path under the system temporary directory, removed first.
+	fn temp_path(name: &str) -> PathBuf {
+		let path = std::env::temp_dir().join(format!("yee-signer-{}-{}", std::process::id(), name));
+		let _ = fs::remove_file(&path);
+		path
+	}
+
+	fn daemon(name: &str) -> Daemon {
+		Daemon::new(vec![pair()], TOKEN.to_string(), temp_path(name)).unwrap()
+	}
+
+	fn call_as(daemon: &Daemon, token: &str, method: &str, params: Value) -> Value {
+		let request = json!({ "jsonrpc": "2.0", "id": 7, "method": method, "params": params, "token": token }).to_string();
+		serde_json::from_str(&handle(daemon, &request)).unwrap()
+	}
+
+	fn call(daemon: &Daemon, method: &str, params: Value) -> Value {
+		call_as(daemon, TOKEN, method, params)
+	}
+
+	fn seal(slot: u64, pre_hash: u8) -> Vec<u8> {
+		(slot, Hash::repeat_byte(pre_hash)).encode()
+	}
+
+	fn sign(daemon: &Daemon, message: &[u8]) -> Value {
+		call(daemon, "signer_sign", json!([to_hex(pair().public().as_ref()), to_hex(message)]))
+	}
+
+	fn serve_once(name: &str) -> (Endpoint, thread::JoinHandle<std::io::Result<()>>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let address = listener.local_addr().unwrap().to_string();
+		let daemon = daemon(name);
+		let server = thread::spawn(move || {
+			let (stream, _) = listener.accept()?;
+			serve_http(&daemon, stream)
+		});
+		(Endpoint::Http { address, path: "/".to_string() }, server)
+	}
+
+	#[test]
+	fn endpoints_are_parsed() {
+		assert_eq!("unix:///tmp/signer.sock".parse(), Ok(Endpoint::Unix(PathBuf::from("/tmp/signer.sock"))));
+		assert_eq!("http://127.0.0.1:8000".parse(), Ok(Endpoint::Http { address: "127.0.0.1:8000".into(), path: "/".into() }));
+		assert_eq!("http://127.0.0.1:8000/sign".parse(), Ok(Endpoint::Http { address: "127.0.0.1:8000".into(), path: "/sign".into() }));
+		assert!("ftp://host".parse::<Endpoint>().is_err());
+	}
+
+	#[test]
+	fn daemon_lists_and_signs_with_its_keys() {
+		let daemon = daemon("lists");
+		let response = call(&daemon, "signer_publicKeys", json!([]));
+		assert_eq!(response["id"], json!(7));
+		assert_eq!(response["result"], json!([to_hex(pair().public().as_ref())]));
+
+		let response = sign(&daemon, b"message");
+		let signature = parse_hex(response["result"].as_str().unwrap()).unwrap();
+		assert!(ed25519::Pair::verify(&Signature::from_slice(&signature), b"message", &pair().public()));
+	}
+
+	#[test]
+	fn daemon_rejects_invalid_requests() {
+		let daemon = daemon("invalid");
+		let other = ed25519::Pair::from_seed([2u8; 32]);
+		assert!(call(&daemon, "signer_sign", json!([to_hex(other.public().as_ref()), "0x00"])).get("error").is_some());
+		assert!(call(&daemon, "signer_sign", json!([to_hex(pair().public().as_ref())])).get("error").is_some());
+		assert!(call(&daemon, "signer_sign", json!([to_hex(pair().public().as_ref()), "0xzz"])).get("error").is_some());
+		assert!(call(&daemon, "signer_unknown", json!([])).get("error").is_some());
+		let response: Value = serde_json::from_str(&handle(&daemon, "not json")).unwrap();
+		assert!(response.get("error").is_some());
+	}
+
+	#[test]
+	fn daemon_requires_its_token() {
+		let daemon = daemon("token");
+		assert_eq!(call_as(&daemon, "secreT", "signer_publicKeys", json!([]))["error"], json!("Unauthorized"));
+		assert_eq!(call_as(&daemon, "", "signer_publicKeys", json!([]))["error"], json!("Unauthorized"));
+		let request = json!({ "jsonrpc": "2.0", "id": 7, "method": "signer_sign", "params": [to_hex(pair().public().as_ref()), "0x00"] });
+		let response: Value = serde_json::from_str(&handle(&daemon, &request.to_string())).unwrap();
+		assert_eq!(response["error"], json!("Unauthorized"));
+	}
+
+	#[test]
+	fn daemon_never_seals_two_blocks_for_a_slot() {
+		let path = temp_path("slots");
+		let daemon = Daemon::new(vec![pair()], TOKEN.to_string(), path.clone()).unwrap();
+
+		assert!(sign(&daemon, &seal(10, 1)).get("result").is_some());
+		// the same block may be sealed again, not another one, nor one of an earlier slot
+		assert!(sign(&daemon, &seal(10, 1)).get("result").is_some());
+		assert!(sign(&daemon, &seal(10, 2)).get("error").is_some());
+		assert!(sign(&daemon, &seal(9, 3)).get("error").is_some());
+		assert!(sign(&daemon, &seal(11, 2)).get("result").is_some());
+		// other messages are not seals
+		assert!(sign(&daemon, b"meta transaction").get("result").is_some());
+
+		// the slots signed survive a restart
+		let restarted = Daemon::new(vec![pair()], TOKEN.to_string(), path.clone()).unwrap();
+		assert!(sign(&restarted, &seal(11, 3)).get("error").is_some());
+		assert!(sign(&restarted, &seal(12, 3)).get("result").is_some());
+		let _ = fs::remove_file(&path);
+	}
+
+	#[test]
+	fn tokens_are_created_private_and_loaded() {
+		let path = temp_path("token-file");
+		let token = daemon_token(&path).unwrap();
+		assert_eq!(token.len(), 64);
+		assert_eq!(daemon_token(&path).unwrap(), token);
+		assert_eq!(load_token(&path).unwrap(), token);
+		#[cfg(unix)]
+		assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+		let _ = fs::remove_file(&path);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn unix_socket_is_private() {
+		let path = temp_path("socket");
+		let daemon = Arc::new(daemon("socket-slots"));
+		let socket = path.clone();
+		thread::spawn(move || serve_unix(&socket, daemon));
+		for _ in 0..100 {
+			if path.exists() {
+				break;
+			}
+			thread::sleep(Duration::from_millis(10));
+		}
+		// permissions are set right after the socket is bound
+		thread::sleep(Duration::from_millis(50));
+		assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+		let response = call_unix(&path, &json!({ "jsonrpc": "2.0", "id": 1, "method": "signer_publicKeys", "params": [], "token": TOKEN }).to_string(), CALL_TIMEOUT).unwrap();
+		assert!(serde_json::from_str::<Value>(&response).unwrap().get("result").is_some());
+		let _ = fs::remove_file(&path);
+	}
+
+	#[test]
+	fn remote_signer_signs_over_http() {
+		let (endpoint, server) = serve_once("http-keys");
+		let keys = public_keys(&endpoint, Some(TOKEN)).unwrap();
+		server.join().unwrap().unwrap();
+		assert_eq!(keys, vec![pair().public()]);
+
+		let (endpoint, server) = serve_once("http-sign");
+		let signer = RemoteSigner {
+			endpoint,
+			public: pair().public(),
+			token: Some(TOKEN.to_string()),
+			available: Arc::new(AtomicBool::new(true)),
+		};
+		let signature = signer.sign(b"message").unwrap();
+		server.join().unwrap().unwrap();
+		assert!(ed25519::Pair::verify(&signature, b"message", &pair().public()));
+	}
+
+	#[test]
+	fn remote_signer_fails_fast_while_unavailable() {
+		let signer = RemoteSigner {
+			endpoint: Endpoint::Unix(temp_path("missing-socket")),
+			public: pair().public(),
+			token: None,
+			available: Arc::new(AtomicBool::new(true)),
+		};
+		assert!(signer.sign(b"message").is_err());
+		assert!(!signer.available.load(Ordering::SeqCst));
+
+		// a signer not answering is not called until checked again
+		let signer = RemoteSigner {
+			endpoint: Endpoint::Http { address: "10.255.255.1:9".into(), path: "/".into() },
+			public: pair().public(),
+			token: None,
+			available: Arc::new(AtomicBool::new(false)),
+		};
+		let started = std::time::Instant::now();
+		assert!(signer.sign(b"message").is_err());
+		assert!(started.elapsed() < SIGN_TIMEOUT);
+	}
+
+	#[test]
+	fn daemon_refuses_large_requests() {
+		let (endpoint, server) = serve_once("large");
+		let address = match endpoint {
+			Endpoint::Http { address, .. } => address,
+			_ => unreachable!(),
+		};
+		let mut stream = TcpStream::connect(address.as_str()).unwrap();
+		write!(stream, "POST / HTTP/1.0\r\nContent-Length: {}\r\n\r\n", MAX_REQUEST_SIZE + 1).unwrap();
+		assert!(server.join().unwrap().is_err());
+	}
+
+	#[test]
+	fn keys_are_not_derived_through_a_signer() {
+		let pair = SignerPair::new(Arc::new(LocalSigner::new(Arc::new(pair()))));
+		assert_eq!(pair.derive(vec![DeriveJunction::hard(1u32)].into_iter()).err(), Some(CannotDerive));
+	}
+}
@@ -0,0 +1,411 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! State snapshots of branch databases: the branch storage and header at a block, with the
+//! root chain databases of the branch, for backups and node cloning.
+
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use log::info;
+use parity_codec::{Encode, Decode};
+use primitives::Blake2Hasher;
+use runtime_primitives::traits::{Block as BlockT, Header as HeaderT};
+use state_machine::{TrieBackend, TrieBackendStorage};
+use substrate_client::backend::StateBackend;
+use substrate_service::{FactoryFullConfiguration, new_client};
+use yee_branch_runtime::{Hash, opaque::{Block, BlockId}};
+use crate::error;
+use crate::service::Factory;
+use crate::warp_sync::{prove_chunk, StateChunk, StateImporter, StateSink, StateVerifier};
+
+/// Leading bytes of snapshot files.
+const SNAPSHOT_MAGIC: &[u8; 8] = b"yeesnap\0";
+
+/// Version of the snapshot format, bumped on incompatible changes.
+const SNAPSHOT_VERSION: u32 = 4;
+
+type Header = <Block as BlockT>::Header;
+
+/// Records of a snapshot file, written and read one at a time so that neither side holds the
+/// whole snapshot: `Start`, the `Ancestor`s interleaved with the `State` chunks, the
+/// `RootFile`s, then `End`.
+#[derive(Encode, Decode)]
+enum Record {
+	/// Version and chain of the snapshot, and encoded header of the block of the state.
+	Start {
+		version: u32,
+		genesis_hash: Hash,
+		header: Vec<u8>,
+	},
+	/// Encoded header of an ancestor of the block, in ascending order from block 1.
+	Ancestor(Vec<u8>),
+	/// Chunk of the storage proven against the state root of the block, in key order across
+	/// records.
+	State(StateChunk),
+	/// File of the root chain databases by path relative to their base, its `len` bytes
+	/// following the record.
+	RootFile {
+		path: String,
+		len: u64,
+	},
+	End,
+}
+
+/// Write the snapshot of the state at the block to the file.
+///
+/// The block must be in the best chain, its ancestors being read by number.
+pub fn export_state(config: FactoryFullConfiguration<Factory>, root_base_path: &Path, at: Option<BlockId>, output: &Path) -> error::Result<()> {
+	let client = new_client::<Factory>(&config).map_err(|e| format!("{:?}", e))?;
+	let info = client.info().map_err(|e| format!("{:?}", e))?.chain;
+	let at = at.unwrap_or_else(|| BlockId::hash(info.best_hash));
+
+	let header = client.header(&at).map_err(|e| format!("{:?}", e))?
+		.ok_or_else(|| format!("Block {} not found", at))?;
+	if client.block_hash(*header.number()).map_err(|e| format!("{:?}", e))? != Some(header.hash()) {
+		return Err(format!("#{} ({}) is not in the best chain", header.number(), header.hash()).into());
+	}
+	let state = client.state_at(&at).map_err(|e| format!("{:?}", e))?
+		.try_into_trie_backend()
+		.ok_or("State of the block can not be read as a trie")?;
+
+	info!("Exporting state of #{} ({})", header.number(), header.hash());
+
+	let root_base = yee_root_chain::root_database_path(root_base_path, info.genesis_hash.as_ref());
+	let ancestor = |number: u64| -> error::Result<Header> {
+		Ok(client.header(&BlockId::number(number)).map_err(|e| format!("{:?}", e))?
+			.ok_or_else(|| format!("Ancestor #{} not found", number))?)
+	};
+
+	let mut file = BufWriter::new(fs::File::create(output)?);
+	let (entry_count, root_file_count) = write_snapshot(&mut file, info.genesis_hash, &header, ancestor, &state, &root_base)?;
+	file.flush()?;
+
+	info!("Exported {} entries, {} root chain files", entry_count, root_file_count);
+	Ok(())
+}
+
+/// Read the snapshot from the file into the databases of the configuration.
+///
+/// The root chain databases of the branch must not exist yet. Each state chunk is checked
+/// against the state root of the snapshot header, then written along with the next ancestor.
+pub fn import_state_snapshot(config: FactoryFullConfiguration<Factory>, root_base_path: &Path, input: &Path) -> error::Result<()> {
+	let mut file = BufReader::new(fs::File::open(input)?);
+	let (snapshot_genesis_hash, header) = read_start(&mut file)?;
+
+	let client = new_client::<Factory>(&config).map_err(|e| format!("{:?}", e))?;
+	let genesis_hash = client.info().map_err(|e| format!("{:?}", e))?.chain.genesis_hash;
+	if snapshot_genesis_hash != genesis_hash {
+		return Err(format!("Snapshot of chain {} does not match chain spec genesis {}", snapshot_genesis_hash, genesis_hash).into());
+	}
+
+	// files of the snapshot never overwrite those of running root chain databases
+	let root_base = yee_root_chain::root_database_path(root_base_path, genesis_hash.as_ref());
+	if root_base.exists() && fs::read_dir(&root_base)?.next().is_some() {
+		return Err(format!("Root chain databases already exist in {}, remove them first", root_base.display()).into());
+	}
+
+	info!("Importing state of #{} ({})", header.number(), header.hash());
+
+	let mut importer = StateImporter::new(&*client, header, genesis_hash)?;
+	let (record, entry_count) = read_state(&mut file, &mut importer)?;
+	importer.finish()?;
+	let root_file_count = read_root_files(&mut file, record, &root_base)?;
+
+	info!("Imported {} entries, {} root chain files", entry_count, root_file_count);
+	Ok(())
+}
+
+/// Write the snapshot of the state of the block of the header, with the root chain databases
+/// under `root_base`, returning the number of entries and files written.
+///
+/// `ancestor` reads the header of an ancestor by number. Each is written after a state chunk
+/// while there are some, so that the importer writes the state along with the ancestors.
+fn write_snapshot<W, S, A>(
+	output: &mut W,
+	genesis_hash: Hash,
+	header: &Header,
+	ancestor: A,
+	state: &TrieBackend<S, Blake2Hasher>,
+	root_base: &Path,
+) -> error::Result<(usize, usize)> where
+	W: Write,
+	S: TrieBackendStorage<Blake2Hasher>,
+	A: Fn(u64) -> error::Result<Header>,
+{
+	output.write_all(SNAPSHOT_MAGIC)?;
+	write_record(output, &Record::Start {
+		version: SNAPSHOT_VERSION,
+		genesis_hash,
+		header: header.encode(),
+	})?;
+
+	// chunks are checked as they would be on import, which also gives the key the next one
+	// starts after
+	let mut verifier = StateVerifier::new(*state.root());
+	let mut entry_count = 0;
+	let mut number = 1;
+	while !verifier.is_complete() || number < *header.number() {
+		if !verifier.is_complete() {
+			let chunk = prove_chunk(state, verifier.start_key())?;
+			verifier.verify(&chunk)?;
+			entry_count += chunk.count as usize;
+			write_record(output, &Record::State(chunk))?;
+		}
+		if number < *header.number() {
+			write_record(output, &Record::Ancestor(ancestor(number)?.encode()))?;
+			number += 1;
+		}
+	}
+
+	let mut root_files = Vec::new();
+	if root_base.exists() {
+		list_files(root_base, &mut root_files)?;
+	}
+	for path in &root_files {
+		let relative = path.strip_prefix(root_base).map_err(|e| format!("{:?}", e))?;
+		let mut root_file = fs::File::open(path)?;
+		let len = root_file.metadata()?.len();
+		write_record(output, &Record::RootFile {
+			path: relative.to_string_lossy().into_owned(),
+			len,
+		})?;
+		if io::copy(&mut root_file.by_ref().take(len), output)? != len {
+			return Err(format!("Root chain file {} changed while exported", path.display()).into());
+		}
+	}
+
+	write_record(output, &Record::End)?;
+	Ok((entry_count, root_files.len()))
+}
+
+/// Read the start of a snapshot: its chain genesis hash and the header of its block.
+fn read_start<R: Read>(input: &mut R) -> error::Result<(Hash, Header)> {
+	let mut magic = [0u8; 8];
+	if input.read_exact(&mut magic).is_err() || magic != *SNAPSHOT_MAGIC {
+		return Err("Not a state snapshot".into());
+	}
+	let (version, genesis_hash, header) = match read_record(input)? {
+		Record::Start { version, genesis_hash, header } => (version, genesis_hash, header),
+		_ => return Err("Invalid state snapshot".into()),
+	};
+	if version != SNAPSHOT_VERSION {
+		return Err(format!("Unsupported snapshot version {}, expected {}", version, SNAPSHOT_VERSION).into());
+	}
+	let header = Header::decode(&mut &header[..]).ok_or("Invalid snapshot header")?;
+	Ok((genesis_hash, header))
+}
+
+/// Read the ancestors and state chunks of a snapshot into the sink as they come, returning the
+/// record following them and the number of entries read.
+fn read_state<R: Read>(input: &mut R, sink: &mut dyn StateSink) -> error::Result<(Record, usize)> {
+	let mut entry_count = 0;
+	loop {
+		match read_record(input)? {
+			Record::Ancestor(ancestor) => {
+				sink.import_ancestor(Header::decode(&mut &ancestor[..]).ok_or("Invalid snapshot ancestor header")?)?;
+			},
+			Record::State(chunk) => {
+				sink.import_chunk(&chunk)?;
+				entry_count += chunk.count as usize;
+			},
+			record => return Ok((record, entry_count)),
+		}
+	}
+}
+
+/// Read the root chain files of a snapshot under `root_base`, from the record, returning their
+/// number.
+fn read_root_files<R: Read>(input: &mut R, mut record: Record, root_base: &Path) -> error::Result<usize> {
+	let mut root_file_count = 0;
+	loop {
+		match record {
+			Record::RootFile { path, len } => {
+				let path = root_file_path(root_base, &path)
+					.ok_or_else(|| format!("Root chain file path {} outside of the root chain databases", path))?;
+				if let Some(parent) = path.parent() {
+					fs::create_dir_all(parent)?;
+				}
+				let mut root_file = fs::OpenOptions::new().write(true).create_new(true).open(&path)?;
+				let copied = io::copy(&mut input.by_ref().take(len), &mut root_file)?;
+				if copied != len {
+					return Err(format!("Truncated root chain file {}", path.display()).into());
+				}
+				root_file_count += 1;
+			},
+			Record::End => return Ok(root_file_count),
+			_ => return Err("Invalid state snapshot".into()),
+		}
+		record = read_record(input)?;
+	}
+}
+
+/// Path of a root chain file under the base, none unless the snapshot path only has plain
+/// components, so that a snapshot can not write files elsewhere.
+fn root_file_path(root_base: &Path, path: &str) -> Option<PathBuf> {
+	let path = Path::new(path);
+	let plain = path.components().all(|component| match component {
+		Component::Normal(_) => true,
+		_ => false,
+	});
+	if !plain || path.components().next().is_none() {
+		return None;
+	}
+	Some(root_base.join(path))
+}
+
+fn write_record<W: Write>(output: &mut W, record: &Record) -> error::Result<()> {
+	output.write_all(&record.encode())?;
+	Ok(())
+}
+
+fn read_record<R: Read>(input: &mut R) -> error::Result<Record> {
+	Ok(Record::decode(input).ok_or("Truncated or invalid state snapshot")?)
+}
+
+/// Collect the paths of the files under the directory.
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) -> error::Result<()> {
+	for entry in fs::read_dir(dir)? {
+		let path = entry?.path();
+		if path.is_dir() {
+			list_files(&path, files)?;
+		} else {
+			files.push(path);
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::collections::HashMap;
+	use hash_db::HashDB;
+	use state_machine::{Backend, InMemory};
+	use trie::MemoryDB;
+
+	/// Directory under the system temporary one, removed when dropped.
+	struct TempDir(PathBuf);
+
+	impl TempDir {
+		fn new(name: &str) -> Self {
+			let path = std::env::temp_dir().join(format!("yee-snapshot-{}-{}", std::process::id(), name));
+			let _ = fs::remove_dir_all(&path);
+			fs::create_dir_all(&path).unwrap();
+			TempDir(path)
+		}
+	}
+
+	impl Drop for TempDir {
+		fn drop(&mut self) {
+			let _ = fs::remove_dir_all(&self.0);
+		}
+	}
+
+	/// Collects what is read, checking the chunks against the state root.
+	struct Collected {
+		state: StateVerifier,
+		nodes: MemoryDB<Blake2Hasher>,
+		ancestors: Vec<Header>,
+	}
+
+	impl StateSink for Collected {
+		fn import_ancestor(&mut self, ancestor: Header) -> Result<(), String> {
+			self.ancestors.push(ancestor);
+			Ok(())
+		}
+
+		fn import_chunk(&mut self, chunk: &StateChunk) -> Result<(), String> {
+			for node in self.state.verify(chunk)? {
+				self.nodes.insert(&node);
+			}
+			Ok(())
+		}
+	}
+
+	fn header(number: u64, parent_hash: Hash, state_root: Hash) -> Header {
+		Header::new(number, Default::default(), state_root, parent_hash, Default::default())
+	}
+
+	#[test]
+	fn snapshots_round_trip() {
+		let entries = (0u32..10_000).map(|i| (i.encode(), vec![i as u8; 4])).collect::<HashMap<_, _>>();
+		let state = InMemory::<Blake2Hasher>::from(entries.clone()).try_into_trie_backend().unwrap();
+		let mut headers = vec![header(0, Default::default(), Default::default())];
+		for number in 1..=5 {
+			let parent = headers.last().unwrap().hash();
+			headers.push(header(number, parent, if number == 5 { *state.root() } else { Default::default() }));
+		}
+
+		let exported = TempDir::new("round-trip-exported");
+		fs::create_dir_all(exported.0.join("db")).unwrap();
+		fs::write(exported.0.join("db").join("000001.log"), b"root chain").unwrap();
+		fs::write(exported.0.join("LOCK"), b"").unwrap();
+
+		let mut snapshot = Vec::new();
+		let ancestor = |number: u64| Ok(headers[number as usize].clone());
+		let counts = write_snapshot(&mut snapshot, headers[0].hash(), &headers[5], ancestor, &state, &exported.0).unwrap();
+		assert_eq!(counts, (10_000, 2));
+
+		let imported = TempDir::new("round-trip-imported");
+		let mut input = &snapshot[..];
+		let (genesis_hash, header) = read_start(&mut input).unwrap();
+		assert_eq!((genesis_hash, header.clone()), (headers[0].hash(), headers[5].clone()));
+
+		let mut collected = Collected {
+			state: StateVerifier::new(*header.state_root()),
+			nodes: MemoryDB::default(),
+			ancestors: Vec::new(),
+		};
+		let (record, entry_count) = read_state(&mut input, &mut collected).unwrap();
+		assert_eq!(read_root_files(&mut input, record, &imported.0).unwrap(), 2);
+		assert!(input.is_empty());
+
+		assert_eq!(entry_count, 10_000);
+		assert!(collected.state.is_complete());
+		assert_eq!(collected.ancestors, headers[1..5].to_vec());
+		let mut read = TrieBackend::new(collected.nodes, *header.state_root()).pairs();
+		let mut expected = entries.into_iter().collect::<Vec<_>>();
+		read.sort();
+		expected.sort();
+		assert_eq!(read, expected);
+
+		assert_eq!(fs::read(imported.0.join("db").join("000001.log")).unwrap(), b"root chain".to_vec());
+		assert_eq!(fs::read(imported.0.join("LOCK")).unwrap(), Vec::<u8>::new());
+	}
+
+	#[test]
+	fn root_files_are_only_written_under_the_root_base() {
+		let base = TempDir::new("paths");
+		let root_base = base.0.join("root");
+
+		for path in &["../evil", "db/../../evil", "/tmp/yee-snapshot-evil", ""] {
+			let mut snapshot = Vec::new();
+			write_record(&mut snapshot, &Record::RootFile { path: path.to_string(), len: 4 }).unwrap();
+			snapshot.extend_from_slice(b"evil");
+			write_record(&mut snapshot, &Record::End).unwrap();
+
+			let mut input = &snapshot[..];
+			let record = read_record(&mut input).unwrap();
+			assert!(read_root_files(&mut input, record, &root_base).is_err(), "{} accepted", path);
+		}
+		assert!(!base.0.join("evil").exists());
+		assert!(!Path::new("/tmp/yee-snapshot-evil").exists());
+
+		assert_eq!(root_file_path(&root_base, "db/000001.log"), Some(root_base.join("db").join("000001.log")));
+	}
+}
@@ -0,0 +1,294 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Transaction pool policies on top of the runtime validation: a cap on the transactions of a
+//! sender ahead of its account nonce, and banning of senders of repeatedly invalid transactions.
+//!
+//! The runtime sets fee-based priorities, so the pool replaces a transaction by a same-nonce
+//! one paying more, and puts checkpoint challenges first.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use log::debug;
+use parking_lot::{Mutex, RwLock};
+use parity_codec::{Encode, Decode};
+use primitives::Blake2Hasher;
+use runtime_primitives::ApplyError;
+use runtime_primitives::traits::{NumberFor, ProvideRuntimeApi};
+use runtime_primitives::transaction_validity::TransactionValidity;
+use serde::Serialize;
+use substrate_client::{self as client, Client};
+use transaction_pool::{self, txpool::{self, ExtrinsicFor}};
+use yee_branch_runtime::{
+	AccountId, Hash, Nonce, UncheckedExtrinsic, UNKNOWN_ERROR, api::AccountNonceApi,
+	opaque::{Block, BlockId, UncheckedExtrinsic as OpaqueExtrinsic},
+};
+
+/// Validity code of transactions of a banned sender.
+const BANNED: i8 = -64;
+/// Validity code of transactions beyond the per-sender cap.
+const SENDER_LIMIT_REACHED: i8 = -65;
+
+/// Maximum number of senders whose invalid transactions are counted.
+const MAX_COUNTED_SENDERS: usize = 4096;
+
+/// Policies of the transaction pool, set from the command line.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolPolicy {
+	/// Maximum number of ready transactions.
+	pub ready_limit: usize,
+	/// Maximum total size of ready transactions, in bytes.
+	pub ready_bytes: usize,
+	/// Maximum number of future transactions.
+	pub future_limit: usize,
+	/// Maximum total size of future transactions, in bytes.
+	pub future_bytes: usize,
+	/// Maximum number of transactions of a sender ahead of its account nonce.
+	pub sender_limit: u64,
+	/// Number of invalid transactions after which their sender is banned, 0 to never ban.
+	pub ban_threshold: u32,
+	/// How long a sender stays banned, in seconds.
+	pub ban_duration: u64,
+}
+
+impl Default for PoolPolicy {
+	fn default() -> Self {
+		let options = txpool::Options::default();
+		PoolPolicy {
+			ready_limit: options.ready.count,
+			ready_bytes: options.ready.total_bytes,
+			future_limit: options.future.count,
+			future_bytes: options.future.total_bytes,
+			sender_limit: 64,
+			ban_threshold: 8,
+			ban_duration: 30 * 60,
+		}
+	}
+}
+
+lazy_static! {
+	// the pool is built by the service factory, which only gets the pool options
+	static ref POLICY: RwLock<PoolPolicy> = RwLock::new(PoolPolicy::default());
+	static ref BANS: Mutex<Bans> = Mutex::new(Bans::default());
+}
+
+/// Set the policies of the transaction pools built afterwards.
+pub fn set_policy(policy: PoolPolicy) {
+	*POLICY.write() = policy;
+}
+
+/// Current policies of the transaction pool.
+pub fn policy() -> PoolPolicy {
+	POLICY.read().clone()
+}
+
+/// Number of currently banned senders.
+pub fn banned_count() -> usize {
+	BANS.lock().banned(Instant::now())
+}
+
+/// Invalid transaction counts with the time of the last one, and bans, by encoded sender address.
+#[derive(Default)]
+struct Bans {
+	invalid: HashMap<Vec<u8>, (u32, Instant)>,
+	banned: HashMap<Vec<u8>, Instant>,
+}
+
+impl Bans {
+	fn is_banned(&mut self, sender: &[u8], now: Instant) -> bool {
+		match self.banned.get(sender) {
+			Some(until) if *until > now => true,
+			Some(_) => {
+				self.banned.remove(sender);
+				false
+			},
+			None => false,
+		}
+	}
+
+	fn note_invalid(&mut self, sender: Vec<u8>, policy: &PoolPolicy, now: Instant) {
+		if policy.ban_threshold == 0 {
+			return;
+		}
+		if self.invalid.len() >= MAX_COUNTED_SENDERS && !self.invalid.contains_key(&sender) {
+			// forget the sender whose last invalid transaction is the oldest
+			let oldest = self.invalid.iter().min_by_key(|(_, (_, last))| *last).map(|(sender, _)| sender.clone());
+			if let Some(oldest) = oldest {
+				self.invalid.remove(&oldest);
+			}
+		}
+		let count = {
+			let entry = self.invalid.entry(sender.clone()).or_insert((0, now));
+			*entry = (entry.0 + 1, now);
+			entry.0
+		};
+		if count >= policy.ban_threshold {
+			debug!(target: "txpool", "Banning sender {:?} after {} invalid transactions", sender, count);
+			self.invalid.remove(&sender);
+			self.banned.insert(sender, now + Duration::from_secs(policy.ban_duration));
+		}
+	}
+
+	fn banned(&mut self, now: Instant) -> usize {
+		self.banned.retain(|_, until| *until > now);
+		self.banned.len()
+	}
+}
+
+/// Pool chain API applying the pool policies to the validation of the client.
+pub struct PolicyChainApi<B, E, RA> {
+	inner: transaction_pool::ChainApi<Client<B, E, Block, RA>, Block>,
+	client: Arc<Client<B, E, Block, RA>>,
+}
+
+impl<B, E, RA> PolicyChainApi<B, E, RA> {
+	pub fn new(client: Arc<Client<B, E, Block, RA>>) -> Self {
+		PolicyChainApi {
+			inner: transaction_pool::ChainApi::new(client.clone()),
+			client,
+		}
+	}
+}
+
+impl<B, E, RA> PolicyChainApi<B, E, RA> where
+	B: client::backend::Backend<Block, Blake2Hasher>,
+	E: client::CallExecutor<Block, Blake2Hasher>,
+	Client<B, E, Block, RA>: ProvideRuntimeApi,
+	<Client<B, E, Block, RA> as ProvideRuntimeApi>::Api: AccountNonceApi<Block>,
+{
+	fn account_nonce(&self, at: &BlockId, account: &AccountId) -> Result<Nonce, transaction_pool::error::Error> {
+		Ok(self.client.runtime_api().account_nonce(at, account.clone())?)
+	}
+}
+
+impl<B, E, RA> txpool::ChainApi for PolicyChainApi<B, E, RA> where
+	B: client::backend::Backend<Block, Blake2Hasher> + Send + Sync + 'static,
+	E: client::CallExecutor<Block, Blake2Hasher> + Send + Sync + 'static,
+	RA: Send + Sync + 'static,
+	Client<B, E, Block, RA>: ProvideRuntimeApi,
+	<Client<B, E, Block, RA> as ProvideRuntimeApi>::Api: AccountNonceApi<Block>,
+	transaction_pool::ChainApi<Client<B, E, Block, RA>, Block>:
+		txpool::ChainApi<Block=Block, Hash=Hash, Error=transaction_pool::error::Error>,
+{
+	type Block = Block;
+	type Hash = Hash;
+	type Error = transaction_pool::error::Error;
+
+	fn validate_transaction(&self, at: &BlockId, uxt: ExtrinsicFor<Self>) -> Result<TransactionValidity, Self::Error> {
+		let policy = policy();
+		let sender = sender_address(&uxt);
+		if let Some(ref sender) = sender {
+			if BANS.lock().is_banned(sender, Instant::now()) {
+				return Ok(TransactionValidity::Invalid(BANNED));
+			}
+		}
+
+		let validity = self.inner.validate_transaction(at, uxt)?;
+		match validity {
+			// a transaction failing before its signature is checked may not be from its sender
+			TransactionValidity::Invalid(code) if code != ApplyError::BadSignature as i8 && code != UNKNOWN_ERROR => {
+				if let Some(sender) = sender {
+					BANS.lock().note_invalid(sender, &policy, Instant::now());
+				}
+			},
+			TransactionValidity::Valid { ref provides, .. } => {
+				// signed transactions provide the tag of their sender and nonce
+				let tag = provides.first().and_then(|tag| <(AccountId, Nonce)>::decode(&mut &tag[..]));
+				if let Some((account, nonce)) = tag {
+					if nonce >= self.account_nonce(at, &account)?.saturating_add(policy.sender_limit) {
+						return Ok(TransactionValidity::Invalid(SENDER_LIMIT_REACHED));
+					}
+				}
+			},
+			TransactionValidity::Invalid(_) | TransactionValidity::Unknown(_) => (),
+		}
+		Ok(validity)
+	}
+
+	fn block_id_to_number(&self, at: &BlockId) -> Result<Option<NumberFor<Block>>, Self::Error> {
+		self.inner.block_id_to_number(at)
+	}
+
+	fn block_id_to_hash(&self, at: &BlockId) -> Result<Option<Hash>, Self::Error> {
+		self.inner.block_id_to_hash(at)
+	}
+
+	fn hash_and_length(&self, uxt: &ExtrinsicFor<Self>) -> (Self::Hash, usize) {
+		self.inner.hash_and_length(uxt)
+	}
+}
+
+/// Encoded address of the signer of the transaction, if signed.
+fn sender_address(uxt: &OpaqueExtrinsic) -> Option<Vec<u8>> {
+	// the opaque extrinsic encodes as the runtime one
+	let xt = UncheckedExtrinsic::decode(&mut &uxt.encode()[..])?;
+	xt.signature.map(|(address, ..)| address.encode())
+}
+
+/// Pool status with the policies, for `author_poolStatus`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolStatus {
+	pub ready: usize,
+	pub future: usize,
+	pub banned: usize,
+	pub policy: PoolPolicy,
+}
+
+/// Status of the pool.
+pub fn status<A: txpool::ChainApi>(pool: &txpool::Pool<A>) -> PoolStatus {
+	let status = pool.status();
+	PoolStatus {
+		ready: status.ready,
+		future: status.future,
+		banned: banned_count(),
+		policy: policy(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn senders_are_banned_at_the_threshold() {
+		let policy = PoolPolicy { ban_threshold: 2, ..PoolPolicy::default() };
+		let now = Instant::now();
+		let mut bans = Bans::default();
+		bans.note_invalid(vec![1], &policy, now);
+		assert!(!bans.is_banned(&[1], now));
+		bans.note_invalid(vec![1], &policy, now);
+		assert!(bans.is_banned(&[1], now));
+		assert!(!bans.is_banned(&[1], now + Duration::from_secs(policy.ban_duration)));
+	}
+
+	#[test]
+	fn counted_senders_are_capped() {
+		let policy = PoolPolicy::default();
+		let now = Instant::now();
+		let mut bans = Bans::default();
+		for i in 0..MAX_COUNTED_SENDERS + 10 {
+			bans.note_invalid((i as u32).encode(), &policy, now + Duration::from_millis(i as u64));
+		}
+		assert_eq!(bans.invalid.len(), MAX_COUNTED_SENDERS);
+		assert!(!bans.invalid.contains_key(&0u32.encode()));
+		assert!(bans.invalid.contains_key(&((MAX_COUNTED_SENDERS + 9) as u32).encode()));
+	}
+}
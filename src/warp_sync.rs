@@ -0,0 +1,1442 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Warp sync: bootstraps a branch full node from the latest branch checkpoint anchored in the
+//! root chain.
+//!
+//! Once the root chain stops delivering newer checkpoints, the state at the checkpoint block is
+//! downloaded from one branch peer at a time in chunks, each proven against the anchored state
+//! root as it arrives. Meanwhile the headers of its ancestors down to the local finalized block
+//! are downloaded in ranges from all the other peers, each range checked against the hash the
+//! range above links to. Peers sending data that does not check out are reported. What checks
+//! out is staged in the aux storage, then imported: the ancestors are finalized in ascending
+//! order, each committed with the trie nodes of the state staged so far, and the checkpoint
+//! block last, with the rest. The imported head is handed to regular sync, which continues from
+//! there.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{info, warn};
+use parking_lot::Mutex;
+use parity_codec::{Encode, Decode};
+use primitives::Blake2Hasher;
+use hash_db::{HashDB, HashDBRef, Hasher};
+use trie::{Trie, TrieDB, DBValue};
+use state_machine::{TrieBackend, TrieBackendStorage};
+use runtime_primitives::traits::{Block as BlockT, Header as HeaderT, ProvideRuntimeApi};
+use network::{specialization::NetworkSpecialization, Context, PeerId, Severity, message::{Message, Status}};
+use network::message::generic::Message as GenericMessage;
+use substrate_client::{self as client, Client, backend::{AuxStore, Backend, BlockImportOperation, NewBlockState, StateBackend}};
+use fg_primitives::GrandpaApi;
+use yee_branch_runtime::{AuthorityId, Hash, opaque::{Block, BlockId}};
+use yee_root_chain::CheckpointHandler;
+use yee_root_port::Checkpoint;
+
+type Header = <Block as BlockT>::Header;
+
+/// Number of state entries in a chunk, but for the last one.
+pub const STATE_CHUNK_SIZE: usize = 4096;
+
+/// Maximum number of headers in a range.
+const HEADERS_RANGE_SIZE: u64 = 1024;
+
+/// Most header ranges downloaded below the lowest header linked to the anchored block.
+const MAX_UNLINKED_RANGES: u64 = 16;
+
+/// How long the latest checkpoint must stay the latest before warping to it, so that a root
+/// chain catching up does not start the download from an outdated one.
+const CHECKPOINT_SETTLE: Duration = Duration::from_secs(30);
+
+/// How long a peer has to answer a request before it is asked of another one.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Aux storage prefix of the staged headers, by number.
+const STAGED_HEADER_PREFIX: &[u8] = b"yee_warp_sync_header";
+
+/// Aux storage prefix of the staged state chunks, by index.
+const STAGED_STATE_PREFIX: &[u8] = b"yee_warp_sync_state";
+
+/// Aux storage key of the GRANDPA authority set of substrate-finality-grandpa.
+const GRANDPA_AUTHORITY_SET_KEY: &[u8] = b"grandpa_voters";
+
+/// Aux storage key of the GRANDPA voter set state of substrate-finality-grandpa.
+const GRANDPA_SET_STATE_KEY: &[u8] = b"grandpa_completed_round";
+
+/// How a new branch full node gets the chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncMode {
+	/// Import every block from genesis.
+	Full,
+	/// Start from the state of the latest checkpoint anchored in the root chain.
+	Warp,
+}
+
+impl Default for SyncMode {
+	fn default() -> Self {
+		SyncMode::Full
+	}
+}
+
+impl std::str::FromStr for SyncMode {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"full" => Ok(SyncMode::Full),
+			"warp" => Ok(SyncMode::Warp),
+			_ => Err(format!("Unknown sync mode: {}, expected full or warp", s)),
+		}
+	}
+}
+
+/// Chunk of a state, with the trie nodes proving it against the state root.
+#[derive(Encode, Decode, Clone, Debug, PartialEq, Eq)]
+pub struct StateChunk {
+	/// Number of entries of the chunk, following the last entry of the previous chunk.
+	pub count: u32,
+	/// Whether the chunk ends the state.
+	pub complete: bool,
+	/// Trie nodes read walking the entries of the chunk from the state root.
+	pub proof: Vec<Vec<u8>>,
+}
+
+/// Messages exchanged between branch peers as chain specific network messages.
+#[derive(Encode, Decode)]
+enum WarpMessage {
+	/// Request the chunk of the state at the block with keys after `start_key`.
+	StateRequest {
+		block: Hash,
+		start_key: Vec<u8>,
+	},
+	/// Encoded header of the block and the chunk, none if the peer lacks the state.
+	StateResponse {
+		block: Hash,
+		chunk: Option<(Vec<u8>, StateChunk)>,
+	},
+	/// Request at most `count` headers of the best chain from the number `start` up.
+	HeadersRequest {
+		start: u64,
+		count: u32,
+	},
+	/// Encoded headers of the best chain from the number `start` up, empty if the peer lacks them.
+	HeadersResponse {
+		start: u64,
+		headers: Vec<Vec<u8>>,
+	},
+}
+
+/// Proves the chunk of the state at a block after a key, with the encoded header of the block.
+type StateSource = Box<dyn Fn(&Hash, &[u8]) -> Option<(Vec<u8>, StateChunk)> + Send>;
+
+/// Reads the encoded headers of the best chain from a number up, at most a count.
+type HeaderSource = Box<dyn Fn(u64, u64) -> Vec<Vec<u8>> + Send>;
+
+/// Local chain warp sync downloads into.
+pub trait WarpStore: Send {
+	/// Number and hash of the last finalized block, which the ancestors are downloaded down to.
+	fn finalized(&self) -> Result<(u64, Hash), String>;
+
+	/// Keep verified headers until the import.
+	fn stage_headers(&mut self, headers: &[Header]) -> Result<(), String>;
+
+	/// Keep the verified state chunk with the index until the import.
+	fn stage_state(&mut self, index: u32, chunk: &StateChunk) -> Result<(), String>;
+
+	/// Import the staged ancestors above `base`, then the block of the header with the `chunks`
+	/// staged state chunks.
+	fn import(self: Box<Self>, header: Header, base: (u64, Hash), chunks: u32) -> Result<(), String>;
+}
+
+/// What the protocol has to do after handling an event.
+enum Action {
+	Send(PeerId, WarpMessage),
+	/// Disconnect the peer, which sent data that does not check out.
+	Report(PeerId, String),
+}
+
+/// Import of a downloaded target, run apart from the network.
+struct ImportJob {
+	store: Box<dyn WarpStore>,
+	header: Header,
+	base: (u64, Hash),
+	chunks: u32,
+}
+
+impl ImportJob {
+	fn run(self) -> Result<(), String> {
+		let number = *self.header.number();
+		let hash = self.header.hash();
+		self.store.import(self.header, self.base, self.chunks)?;
+		info!("Warp synced to #{} ({})", number, hash);
+		Ok(())
+	}
+}
+
+/// Download in progress of the checkpoint block, its state and the headers of its ancestors.
+struct Target {
+	number: u64,
+	hash: Hash,
+	state_root: Hash,
+	/// Header of the block, set by the first state response.
+	header: Option<Header>,
+	state: StateVerifier,
+	/// Number of state chunks verified and staged.
+	chunks: u32,
+	state_request: Option<(PeerId, Instant)>,
+	/// Peers which do not have the state.
+	state_lacking: HashSet<PeerId>,
+	/// Number and hash of the local finalized block the ancestors are downloaded down to.
+	base: (u64, Hash),
+	/// Lowest block linked down from the anchored block, and its parent hash once known.
+	linked: (u64, Option<Hash>),
+	/// Header ranges received but not linked yet, by the number of their last header, with
+	/// the peer they came from.
+	ranges: BTreeMap<u64, (PeerId, Vec<Header>)>,
+	/// Header ranges requested, by the number of their last header.
+	range_requests: HashMap<PeerId, (u64, Instant)>,
+	/// Peers which do not have the headers.
+	headers_lacking: HashSet<PeerId>,
+}
+
+impl Target {
+	fn new(number: u64, hash: Hash, state_root: Hash, base: (u64, Hash)) -> Self {
+		Target {
+			number,
+			hash,
+			state_root,
+			header: None,
+			state: StateVerifier::new(state_root),
+			chunks: 0,
+			state_request: None,
+			state_lacking: HashSet::new(),
+			base,
+			linked: (number, None),
+			ranges: BTreeMap::new(),
+			range_requests: HashMap::new(),
+			headers_lacking: HashSet::new(),
+		}
+	}
+
+	/// Number of the first header of the range ending with the number.
+	fn range_start(&self, end: u64) -> u64 {
+		(self.base.0 + 1).max((end + 1).saturating_sub(HEADERS_RANGE_SIZE))
+	}
+
+	/// Ranges not received nor requested yet in the window below the lowest linked header,
+	/// by the number of their last header.
+	fn missing_ranges(&self) -> Vec<u64> {
+		let mut missing = Vec::new();
+		let mut end = self.linked.0.saturating_sub(1);
+		for _ in 0..MAX_UNLINKED_RANGES {
+			if end <= self.base.0 {
+				break;
+			}
+			let requested = self.range_requests.values().any(|(requested, _)| *requested == end);
+			if !requested && !self.ranges.contains_key(&end) {
+				missing.push(end);
+			}
+			end = self.range_start(end) - 1;
+		}
+		missing
+	}
+
+	fn headers_linked(&self) -> bool {
+		self.linked.0 == self.base.0 + 1 && self.linked.1.is_some()
+	}
+}
+
+/// Warp sync state machine, apart from the network and the client.
+struct Inner {
+	state_source: Option<StateSource>,
+	header_source: Option<HeaderSource>,
+	store: Option<Box<dyn WarpStore>>,
+	/// Latest checkpoint anchored in the root chain, and when it was seen.
+	latest: Option<(Checkpoint, Instant)>,
+	settle: Duration,
+	target: Option<Target>,
+	peers: Vec<PeerId>,
+	done: bool,
+}
+
+impl Default for Inner {
+	fn default() -> Self {
+		Inner {
+			state_source: None,
+			header_source: None,
+			store: None,
+			latest: None,
+			settle: CHECKPOINT_SETTLE,
+			target: None,
+			peers: Vec::new(),
+			done: false,
+		}
+	}
+}
+
+impl Inner {
+	fn on_checkpoint(&mut self, checkpoint: Checkpoint, now: Instant) {
+		if self.done || self.target.is_some() {
+			return;
+		}
+		if self.latest.as_ref().map_or(true, |(latest, _)| latest.number < checkpoint.number) {
+			self.latest = Some((checkpoint, now));
+		}
+	}
+
+	/// Start the download of the latest checkpoint once no newer one came for `settle`.
+	fn start(&mut self, now: Instant) {
+		if self.done || self.target.is_some() {
+			return;
+		}
+		let checkpoint = match self.latest {
+			Some((ref checkpoint, seen)) if now.duration_since(seen) >= self.settle => checkpoint.clone(),
+			_ => return,
+		};
+		let base = match self.store.as_ref().map(|store| store.finalized()) {
+			Some(Ok(base)) => base,
+			Some(Err(e)) => {
+				warn!("Warp sync failed to read the finalized block: {}", e);
+				return;
+			},
+			None => return,
+		};
+
+		match (Decode::decode(&mut &checkpoint.hash[..]), Decode::decode(&mut &checkpoint.state_root[..])) {
+			(Some(hash), Some(state_root)) => {
+				if checkpoint.number <= base.0 {
+					info!("Finalized #{} is past the latest checkpoint, no warp sync", base.0);
+					self.done = true;
+					return;
+				}
+				info!("Warp sync to #{} ({}) anchored in the root chain", checkpoint.number, hash);
+				self.target = Some(Target::new(checkpoint.number, hash, state_root, base));
+			},
+			_ => {
+				warn!("Invalid root chain checkpoint: {:?}", checkpoint);
+				self.latest = None;
+			},
+		}
+	}
+
+	/// Give up the target, continuing with full sync.
+	fn abort(&mut self, reason: &str) {
+		if let Some(target) = self.target.take() {
+			warn!("Warp sync to #{} failed, continuing with full sync: {}", target.number, reason);
+		}
+		self.done = true;
+	}
+
+	fn on_connect(&mut self, who: PeerId, now: Instant) -> Vec<Action> {
+		self.peers.push(who);
+		self.request(now)
+	}
+
+	fn on_disconnect(&mut self, who: &PeerId, now: Instant) -> Vec<Action> {
+		self.peers.retain(|peer| peer != who);
+		if let Some(ref mut target) = self.target {
+			if target.state_request.as_ref().map_or(false, |(peer, _)| peer == who) {
+				target.state_request = None;
+			}
+			target.range_requests.remove(who);
+		}
+		self.request(now)
+	}
+
+	/// Start the target, retry the requests left unanswered or refused and send the next ones.
+	fn maintain(&mut self, now: Instant) -> Vec<Action> {
+		self.start(now);
+		if let Some(ref mut target) = self.target {
+			if let Some((peer, _)) = target.state_request.clone().filter(|(_, at)| now.duration_since(*at) >= REQUEST_TIMEOUT) {
+				target.state_lacking.insert(peer);
+				target.state_request = None;
+			}
+			let expired = target.range_requests.iter()
+				.filter(|(_, (_, at))| now.duration_since(*at) >= REQUEST_TIMEOUT)
+				.map(|(peer, _)| peer.clone())
+				.collect::<Vec<_>>();
+			for peer in expired {
+				target.range_requests.remove(&peer);
+				target.headers_lacking.insert(peer);
+			}
+
+			// peers lacking the data may have got it since
+			if self.peers.iter().all(|peer| target.state_lacking.contains(peer)) {
+				target.state_lacking.clear();
+			}
+			if self.peers.iter().all(|peer| target.headers_lacking.contains(peer)) {
+				target.headers_lacking.clear();
+			}
+		}
+		self.request(now)
+	}
+
+	/// Ask a peer for the next state chunk, and the other idle peers for the missing header ranges.
+	fn request(&mut self, now: Instant) -> Vec<Action> {
+		let mut actions = Vec::new();
+		let target = match self.target {
+			Some(ref mut target) => target,
+			None => return actions,
+		};
+
+		if !target.state.is_complete() && target.state_request.is_none() {
+			let peer = self.peers.iter()
+				.filter(|peer| !target.state_lacking.contains(*peer))
+				.find(|peer| !target.range_requests.contains_key(*peer))
+				.or_else(|| self.peers.iter().find(|peer| !target.state_lacking.contains(*peer)));
+			if let Some(peer) = peer.cloned() {
+				target.state_request = Some((peer.clone(), now));
+				actions.push(Action::Send(peer, WarpMessage::StateRequest {
+					block: target.hash,
+					start_key: target.state.start_key().to_vec(),
+				}));
+			}
+		}
+
+		let mut missing = target.missing_ranges().into_iter();
+		for peer in &self.peers {
+			let busy = target.range_requests.contains_key(peer)
+				|| target.state_request.as_ref().map_or(false, |(requested, _)| requested == peer);
+			if busy || target.headers_lacking.contains(peer) {
+				continue;
+			}
+			let end = match missing.next() {
+				Some(end) => end,
+				None => break,
+			};
+			let start = target.range_start(end);
+			target.range_requests.insert(peer.clone(), (end, now));
+			actions.push(Action::Send(peer.clone(), WarpMessage::HeadersRequest { start, count: (end + 1 - start) as u32 }));
+		}
+
+		actions
+	}
+
+	fn on_message(&mut self, who: PeerId, message: WarpMessage, now: Instant) -> Vec<Action> {
+		match message {
+			WarpMessage::StateRequest { block, start_key } => {
+				let chunk = self.state_source.as_ref().and_then(|source| source(&block, &start_key));
+				vec![Action::Send(who, WarpMessage::StateResponse { block, chunk })]
+			},
+			WarpMessage::HeadersRequest { start, count } => {
+				let count = (count as u64).min(HEADERS_RANGE_SIZE);
+				let headers = self.header_source.as_ref().map_or_else(Vec::new, |source| source(start, count));
+				vec![Action::Send(who, WarpMessage::HeadersResponse { start, headers })]
+			},
+			WarpMessage::StateResponse { block, chunk } => {
+				let mut actions = self.on_state_response(who, block, chunk);
+				actions.extend(self.request(now));
+				actions
+			},
+			WarpMessage::HeadersResponse { start, headers } => {
+				let mut actions = self.on_headers_response(who, start, headers);
+				actions.extend(self.request(now));
+				actions
+			},
+		}
+	}
+
+	fn on_state_response(&mut self, who: PeerId, block: Hash, chunk: Option<(Vec<u8>, StateChunk)>) -> Vec<Action> {
+		let (store, target) = match (self.store.as_mut(), self.target.as_mut()) {
+			(Some(store), Some(target)) => (store, target),
+			_ => return Vec::new(),
+		};
+		if target.hash != block || target.state_request.as_ref().map_or(true, |(peer, _)| *peer != who) {
+			return Vec::new();
+		}
+		target.state_request = None;
+
+		let (header, chunk) = match chunk {
+			Some(chunk) => chunk,
+			None => {
+				target.state_lacking.insert(who);
+				return Vec::new();
+			},
+		};
+
+		if target.header.is_none() {
+			match Header::decode(&mut &header[..]) {
+				Some(ref header) if header.hash() == target.hash && *header.state_root() == target.state_root => {
+					target.linked.1 = Some(*header.parent_hash());
+					target.header = Some(header.clone());
+				},
+				_ => return vec![Action::Report(who, "Warp sync header not anchored in the root chain".into())],
+			}
+		}
+
+		if let Err(e) = target.state.verify(&chunk) {
+			return vec![Action::Report(who, format!("Invalid warp sync state chunk: {}", e))];
+		}
+		if let Err(e) = store.stage_state(target.chunks, &chunk) {
+			self.abort(&e);
+			return Vec::new();
+		}
+		target.chunks += 1;
+
+		self.link()
+	}
+
+	fn on_headers_response(&mut self, who: PeerId, start: u64, headers: Vec<Vec<u8>>) -> Vec<Action> {
+		let target = match self.target {
+			Some(ref mut target) => target,
+			None => return Vec::new(),
+		};
+		let end = match target.range_requests.remove(&who) {
+			Some((end, _)) => end,
+			None => return Vec::new(),
+		};
+		if headers.is_empty() {
+			target.headers_lacking.insert(who);
+			return Vec::new();
+		}
+
+		match decode_range(target.range_start(end), end, start, &headers) {
+			Ok(range) => {
+				target.ranges.insert(end, (who, range));
+				self.link()
+			},
+			Err(e) => vec![Action::Report(who, e)],
+		}
+	}
+
+	/// Link the received ranges down from the anchored block, staging them, and reporting the
+	/// peers of those not linking.
+	fn link(&mut self) -> Vec<Action> {
+		let mut actions = Vec::new();
+		let mut error = None;
+		{
+			let (store, target) = match (self.store.as_mut(), self.target.as_mut()) {
+				(Some(store), Some(target)) => (store, target),
+				_ => return actions,
+			};
+
+			while let (Some(parent), true) = (target.linked.1, target.linked.0 > target.base.0 + 1) {
+				let (peer, range) = match target.ranges.remove(&(target.linked.0 - 1)) {
+					Some(range) => range,
+					None => break,
+				};
+				if range.last().map(|header| header.hash()) != Some(parent) {
+					actions.push(Action::Report(peer, "Warp sync headers not linked to the anchored block".into()));
+					break;
+				}
+				if let Err(e) = store.stage_headers(&range) {
+					error = Some(e);
+					break;
+				}
+				target.linked = (*range[0].number(), Some(*range[0].parent_hash()));
+			}
+
+			if target.linked.0 == target.base.0 + 1 && target.linked.1.map_or(false, |parent| parent != target.base.1) {
+				error = Some(format!("Anchored block does not descend from finalized #{} ({})", target.base.0, target.base.1));
+			}
+		}
+
+		if let Some(e) = error {
+			self.abort(&e);
+		}
+		actions
+	}
+
+	/// Import job of the target once its state and the headers of its ancestors are staged.
+	fn take_import(&mut self) -> Option<ImportJob> {
+		match self.target {
+			Some(ref target) if target.state.is_complete() && target.headers_linked() && self.store.is_some() => (),
+			_ => return None,
+		}
+		let target = self.target.take().expect("Target matched above; qed");
+		Some(ImportJob {
+			store: self.store.take().expect("Store matched above; qed"),
+			header: target.header.expect("Header is set before the first state chunk is verified; qed"),
+			base: target.base,
+			chunks: target.chunks,
+		})
+	}
+}
+
+/// Checks headers of the range from `start` to `end` sent as from `sent_start`, decoded, in
+/// ascending order and linked to one another.
+fn decode_range(start: u64, end: u64, sent_start: u64, headers: &[Vec<u8>]) -> Result<Vec<Header>, String> {
+	if sent_start != start || headers.len() as u64 != end + 1 - start {
+		return Err("Warp sync headers not of the requested range".into());
+	}
+	let mut range: Vec<Header> = Vec::with_capacity(headers.len());
+	for (number, encoded) in (start..).zip(headers) {
+		let header = Header::decode(&mut &encoded[..]).ok_or("Invalid warp sync header")?;
+		if *header.number() != number || range.last().map_or(false, |parent| parent.hash() != *header.parent_hash()) {
+			return Err("Warp sync headers not linked to one another".into());
+		}
+		range.push(header);
+	}
+	Ok(range)
+}
+
+/// Handle shared between the network protocol and the service setting up warp sync.
+#[derive(Clone, Default)]
+pub struct WarpSync {
+	inner: Arc<Mutex<Inner>>,
+}
+
+impl WarpSync {
+	/// Serve the state and headers of the blocks of the client to warping peers.
+	pub fn serve<B, E, RA>(&self, client: Arc<Client<B, E, Block, RA>>) where
+		B: client::backend::Backend<Block, Blake2Hasher> + Send + Sync + 'static,
+		E: client::CallExecutor<Block, Blake2Hasher> + Send + Sync + 'static,
+		RA: Send + Sync + 'static,
+	{
+		let mut inner = self.inner.lock();
+
+		let state_client = client.clone();
+		inner.state_source = Some(Box::new(move |block, start_key| {
+			let id = BlockId::hash(*block);
+			let header = state_client.header(&id).ok()??;
+			let state = state_client.state_at(&id).ok()?.try_into_trie_backend()?;
+
+			match prove_chunk(&state, start_key) {
+				Ok(chunk) => Some((header.encode(), chunk)),
+				Err(e) => {
+					warn!("Failed to read the state of {}: {}", block, e);
+					None
+				},
+			}
+		}));
+
+		inner.header_source = Some(Box::new(move |start, count| {
+			(start..start + count)
+				.map(|number| client.header(&BlockId::number(number)))
+				.take_while(|header| match header {
+					Ok(Some(_)) => true,
+					_ => false,
+				})
+				.filter_map(|header| header.ok().and_then(|header| header.map(|header| header.encode())))
+				.collect()
+		}));
+	}
+
+	/// Wrap the checkpoint handler so that the latest checkpoint starts the download, once the
+	/// root chain stops delivering newer ones. `on_imported` is given the imported head.
+	pub fn start_on_checkpoint<B, E, RA>(
+		&self,
+		client: Arc<Client<B, E, Block, RA>>,
+		on_checkpoint: Option<CheckpointHandler>,
+		on_imported: Box<dyn Fn(&Header) + Send>,
+	) -> CheckpointHandler where
+		B: client::backend::Backend<Block, Blake2Hasher> + Send + Sync + 'static,
+		E: client::CallExecutor<Block, Blake2Hasher> + Send + Sync + 'static,
+		RA: Send + Sync + 'static,
+		<B::State as StateBackend<Blake2Hasher>>::Transaction: HashDB<Blake2Hasher, DBValue> + Default,
+	{
+		self.inner.lock().store = Some(Box::new(ClientStore { client, on_imported }));
+
+		let inner = self.inner.clone();
+		Arc::new(move |checkpoint: Checkpoint| {
+			inner.lock().on_checkpoint(checkpoint.clone(), Instant::now());
+			if let Some(ref on_checkpoint) = on_checkpoint {
+				on_checkpoint(checkpoint);
+			}
+		})
+	}
+}
+
+/// Network protocol of the branch, serving and downloading warp sync state.
+pub struct NodeProtocol {
+	warp_sync: WarpSync,
+}
+
+impl NodeProtocol {
+	pub fn new(warp_sync: WarpSync) -> Self {
+		NodeProtocol {
+			warp_sync,
+		}
+	}
+
+	/// Carry out the actions, then start the import once everything is downloaded.
+	fn apply(&self, ctx: &mut dyn Context<Block>, actions: Vec<Action>) {
+		for action in actions {
+			match action {
+				Action::Send(who, message) => ctx.send_message(who, GenericMessage::ChainSpecific(message.encode())),
+				Action::Report(who, reason) => {
+					warn!("Reporting peer {}: {}", who, reason);
+					ctx.report_peer(who, Severity::Bad(reason));
+				},
+			}
+		}
+
+		let job = match self.warp_sync.inner.lock().take_import() {
+			Some(job) => job,
+			None => return,
+		};
+		let inner = self.warp_sync.inner.clone();
+		let spawned = thread::Builder::new().name("warp-sync-import".into()).spawn(move || {
+			let result = job.run();
+			let mut inner = inner.lock();
+			inner.done = true;
+			if let Err(e) = result {
+				warn!("Warp sync import failed, continuing with full sync: {}", e);
+			}
+		});
+		if let Err(e) = spawned {
+			warn!("Failed to start the warp sync import: {}", e);
+		}
+	}
+}
+
+impl NetworkSpecialization<Block> for NodeProtocol {
+	fn status(&self) -> Vec<u8> {
+		Vec::new()
+	}
+
+	fn on_connect(&mut self, ctx: &mut dyn Context<Block>, who: PeerId, _status: Status<Block>) {
+		let actions = self.warp_sync.inner.lock().on_connect(who, Instant::now());
+		self.apply(ctx, actions);
+	}
+
+	fn on_disconnect(&mut self, ctx: &mut dyn Context<Block>, who: PeerId) {
+		let actions = self.warp_sync.inner.lock().on_disconnect(&who, Instant::now());
+		self.apply(ctx, actions);
+	}
+
+	fn on_message(&mut self, ctx: &mut dyn Context<Block>, who: PeerId, message: &mut Option<Message<Block>>) {
+		let data = match message.take() {
+			Some(GenericMessage::ChainSpecific(data)) => data,
+			other => {
+				*message = other;
+				return;
+			},
+		};
+
+		let actions = match WarpMessage::decode(&mut &data[..]) {
+			Some(message) => self.warp_sync.inner.lock().on_message(who, message, Instant::now()),
+			None => vec![Action::Report(who, "Invalid warp sync message".into())],
+		};
+		self.apply(ctx, actions);
+	}
+
+	fn maintain_peers(&mut self, ctx: &mut dyn Context<Block>) {
+		let actions = self.warp_sync.inner.lock().maintain(Instant::now());
+		self.apply(ctx, actions);
+	}
+}
+
+/// Trie nodes of a state backend, recording those read.
+struct RecordingNodes<'a, S: 'a> {
+	storage: &'a S,
+	read: RefCell<HashMap<Hash, DBValue>>,
+}
+
+impl<'a, S: TrieBackendStorage<Blake2Hasher>> HashDBRef<Blake2Hasher, DBValue> for RecordingNodes<'a, S> {
+	fn get(&self, key: &Hash) -> Option<DBValue> {
+		let value = self.storage.get(key).ok()??;
+		self.read.borrow_mut().insert(*key, value.clone());
+		Some(value)
+	}
+
+	fn contains(&self, key: &Hash) -> bool {
+		HashDBRef::get(self, key).is_some()
+	}
+}
+
+/// Trie nodes of a proof, recording those read.
+struct ProofNodes {
+	nodes: HashMap<Hash, DBValue>,
+	read: RefCell<HashSet<Hash>>,
+}
+
+impl ProofNodes {
+	fn new(proof: &[Vec<u8>]) -> Self {
+		ProofNodes {
+			nodes: proof.iter().map(|node| (Blake2Hasher::hash(node), DBValue::from_slice(node))).collect(),
+			read: RefCell::new(HashSet::new()),
+		}
+	}
+
+	/// The nodes read, leaving out those the proof does not need.
+	fn into_read(self) -> Vec<Vec<u8>> {
+		let read = self.read.into_inner();
+		self.nodes.into_iter()
+			.filter(|(hash, _)| read.contains(hash))
+			.map(|(_, node)| node.to_vec())
+			.collect()
+	}
+}
+
+impl HashDBRef<Blake2Hasher, DBValue> for ProofNodes {
+	fn get(&self, key: &Hash) -> Option<DBValue> {
+		let value = self.nodes.get(key)?.clone();
+		self.read.borrow_mut().insert(*key);
+		Some(value)
+	}
+
+	fn contains(&self, key: &Hash) -> bool {
+		self.nodes.contains_key(key)
+	}
+}
+
+/// At most `limit` entries with keys after `start_key` in key order of the trie with the root,
+/// and whether they are the last ones.
+///
+/// The keys are walked from `start_key` in the trie, so a chunk does not read the whole state.
+fn walk_entries(
+	nodes: &dyn HashDBRef<Blake2Hasher, DBValue>,
+	root: &Hash,
+	start_key: &[u8],
+	limit: usize,
+) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, bool), String> {
+	let trie = TrieDB::<Blake2Hasher>::new(nodes, root).map_err(|e| format!("{:?}", e))?;
+	let mut iter = trie.iter().map_err(|e| format!("{:?}", e))?;
+	iter.seek(start_key).map_err(|e| format!("{:?}", e))?;
+
+	let mut entries = Vec::new();
+	for item in iter {
+		let (key, value) = item.map_err(|e| format!("{:?}", e))?;
+		if key.as_slice() <= start_key {
+			continue;
+		}
+		if entries.len() == limit {
+			return Ok((entries, false));
+		}
+		entries.push((key, value.to_vec()));
+	}
+	Ok((entries, true))
+}
+
+/// Chunk of the state with keys after `start_key`, proven by the trie nodes read.
+pub fn prove_chunk<S: TrieBackendStorage<Blake2Hasher>>(
+	state: &TrieBackend<S, Blake2Hasher>,
+	start_key: &[u8],
+) -> Result<StateChunk, String> {
+	let nodes = RecordingNodes { storage: state.backend_storage(), read: RefCell::new(HashMap::new()) };
+	let (entries, complete) = walk_entries(&nodes, state.root(), start_key, STATE_CHUNK_SIZE)?;
+	Ok(StateChunk {
+		count: entries.len() as u32,
+		complete,
+		proof: nodes.read.into_inner().into_iter().map(|(_, node)| node.to_vec()).collect(),
+	})
+}
+
+/// Checks the chunks of a state, in order, against its state root.
+pub struct StateVerifier {
+	root: Hash,
+	/// Key of the last entry verified.
+	start_key: Vec<u8>,
+	complete: bool,
+}
+
+impl StateVerifier {
+	pub fn new(root: Hash) -> Self {
+		StateVerifier { root, start_key: Vec::new(), complete: false }
+	}
+
+	/// Whether the chunk ending the state is verified.
+	pub fn is_complete(&self) -> bool {
+		self.complete
+	}
+
+	/// Key the next chunk starts after.
+	pub fn start_key(&self) -> &[u8] {
+		&self.start_key
+	}
+
+	/// Check the next chunk, returning the trie nodes it proves for writing them.
+	///
+	/// A chunk must have `STATE_CHUNK_SIZE` entries unless it ends the state, and its proof
+	/// must yield exactly them walking the trie from the state root.
+	pub fn verify(&mut self, chunk: &StateChunk) -> Result<Vec<Vec<u8>>, String> {
+		if self.complete {
+			return Err("State already complete".into());
+		}
+		let count = chunk.count as usize;
+		if count > STATE_CHUNK_SIZE || (!chunk.complete && count != STATE_CHUNK_SIZE) {
+			return Err(format!("State chunk of {} entries", count));
+		}
+
+		let nodes = ProofNodes::new(&chunk.proof);
+		let (entries, complete) = walk_entries(&nodes, &self.root, &self.start_key, count)
+			.map_err(|e| format!("State chunk not proven: {}", e))?;
+		if entries.len() != count || complete != chunk.complete {
+			return Err("State chunk does not match its proof".into());
+		}
+
+		if let Some((key, _)) = entries.last() {
+			self.start_key = key.clone();
+		}
+		self.complete = complete;
+		Ok(nodes.into_read())
+	}
+}
+
+/// Receives a state and the headers of the ancestors of its block as they are read.
+pub trait StateSink {
+	/// Import the header of the next ancestor, in ascending order.
+	fn import_ancestor(&mut self, ancestor: Header) -> Result<(), String>;
+
+	/// Import the next chunk of the state, in key order.
+	fn import_chunk(&mut self, chunk: &StateChunk) -> Result<(), String>;
+}
+
+/// Writes a state and the headers of the ancestors of its block into the client database,
+/// finalized.
+///
+/// Ancestors come in ascending order from the child of a finalized block. Each is committed
+/// with the trie nodes of the state verified so far, the backend only taking trie nodes along
+/// with a block, and the block of the state last, with the rest. Blocks regular sync imported
+/// meanwhile are only finalized. Child tries are not written: the branch runtime has none.
+pub struct StateImporter<'a, B, E, RA> {
+	client: &'a Client<B, E, Block, RA>,
+	header: Header,
+	state: StateVerifier,
+	/// Trie nodes verified but not written yet.
+	nodes: Vec<Vec<u8>>,
+	/// Number and hash of the last ancestor imported.
+	last: (u64, Hash),
+	finalized_number: u64,
+}
+
+impl<'a, B, E, RA> StateImporter<'a, B, E, RA> where
+	B: client::backend::Backend<Block, Blake2Hasher>,
+	E: client::CallExecutor<Block, Blake2Hasher>,
+	<B::State as StateBackend<Blake2Hasher>>::Transaction: HashDB<Blake2Hasher, DBValue> + Default,
+{
+	/// Import the state of the block of the header, whose ancestors descend from the block `base`.
+	pub fn new(client: &'a Client<B, E, Block, RA>, header: Header, base: Hash) -> Result<Self, String> {
+		let base_number = client.header(&BlockId::hash(base)).map_err(|e| format!("{:?}", e))?
+			.map(|base| *base.number())
+			.ok_or_else(|| format!("Ancestor {} of #{} not found", base, header.number()))?;
+
+		let finalized_number = client.info().map_err(|e| format!("{:?}", e))?.chain.finalized_number;
+		if base_number <= finalized_number {
+			if client.block_hash(base_number).map_err(|e| format!("{:?}", e))? != Some(base) {
+				return Err(format!("#{} ({}) is not in the finalized chain", base_number, base));
+			}
+		} else {
+			finalize(client, base)?;
+		}
+
+		Ok(StateImporter {
+			client,
+			state: StateVerifier::new(*header.state_root()),
+			header,
+			nodes: Vec::new(),
+			last: (base_number, base),
+			finalized_number,
+		})
+	}
+
+	/// Commit the block of the header, finalized, with the trie nodes verified so far.
+	fn commit(&mut self, header: Header) -> Result<(), String> {
+		let mut transaction = <B::State as StateBackend<Blake2Hasher>>::Transaction::default();
+		for node in self.nodes.drain(..) {
+			transaction.insert(&node);
+		}
+
+		let backend = self.client.backend();
+		let mut op = backend.begin_operation().map_err(|e| format!("{:?}", e))?;
+		op.set_block_data(header, None, None, NewBlockState::Final).map_err(|e| format!("{:?}", e))?;
+		op.update_db_storage(transaction).map_err(|e| format!("{:?}", e))?;
+		backend.commit_operation(op).map_err(|e| format!("{:?}", e))
+	}
+
+	/// Import the block of the header, which becomes the finalized head, with the rest of the
+	/// state, which must be complete.
+	pub fn finish(mut self) -> Result<(), String> {
+		if !self.state.is_complete() {
+			return Err("State incomplete".into());
+		}
+		if *self.header.parent_hash() != self.last.1 {
+			return Err(format!("#{} does not descend from the imported ancestors", self.header.number()));
+		}
+
+		// regular sync may have got there first
+		let hash = self.header.hash();
+		if is_known(self.client, &hash)? {
+			return finalize(self.client, hash);
+		}
+		let header = self.header.clone();
+		self.commit(header)
+	}
+}
+
+impl<'a, B, E, RA> StateSink for StateImporter<'a, B, E, RA> where
+	B: client::backend::Backend<Block, Blake2Hasher>,
+	E: client::CallExecutor<Block, Blake2Hasher>,
+	<B::State as StateBackend<Blake2Hasher>>::Transaction: HashDB<Blake2Hasher, DBValue> + Default,
+{
+	fn import_ancestor(&mut self, ancestor: Header) -> Result<(), String> {
+		let number = *ancestor.number();
+		let hash = ancestor.hash();
+		if number != self.last.0 + 1 || *ancestor.parent_hash() != self.last.1 || number >= *self.header.number() {
+			return Err(format!("Header #{} is not the next ancestor of #{}", number, self.header.number()));
+		}
+
+		if number <= self.finalized_number {
+			if self.client.block_hash(number).map_err(|e| format!("{:?}", e))? != Some(hash) {
+				return Err(format!("#{} ({}) is not in the finalized chain", number, hash));
+			}
+		} else if is_known(self.client, &hash)? {
+			finalize(self.client, hash)?;
+		} else {
+			self.commit(ancestor)?;
+		}
+
+		self.last = (number, hash);
+		Ok(())
+	}
+
+	fn import_chunk(&mut self, chunk: &StateChunk) -> Result<(), String> {
+		let nodes = self.state.verify(chunk)?;
+		self.nodes.extend(nodes);
+		Ok(())
+	}
+}
+
+fn is_known<B, E, RA>(client: &Client<B, E, Block, RA>, hash: &Hash) -> Result<bool, String> where
+	B: client::backend::Backend<Block, Blake2Hasher>,
+	E: client::CallExecutor<Block, Blake2Hasher>,
+{
+	client.header(&BlockId::hash(*hash)).map(|header| header.is_some()).map_err(|e| format!("{:?}", e))
+}
+
+fn finalize<B, E, RA>(client: &Client<B, E, Block, RA>, hash: Hash) -> Result<(), String> where
+	B: client::backend::Backend<Block, Blake2Hasher>,
+	E: client::CallExecutor<Block, Blake2Hasher>,
+{
+	client.finalize_block(BlockId::hash(hash), None, false).map_err(|e| format!("{:?}", e))
+}
+
+fn staged_key(prefix: &[u8], index: u64) -> Vec<u8> {
+	let mut key = prefix.to_vec();
+	index.encode_to(&mut key);
+	key
+}
+
+/// Warp store of a client, staging in its aux storage.
+struct ClientStore<B, E, RA> {
+	client: Arc<Client<B, E, Block, RA>>,
+	on_imported: Box<dyn Fn(&Header) + Send>,
+}
+
+impl<B, E, RA> ClientStore<B, E, RA> where
+	B: client::backend::Backend<Block, Blake2Hasher>,
+	E: client::CallExecutor<Block, Blake2Hasher>,
+{
+	fn stage(&self, key: Vec<u8>, value: Vec<u8>) -> Result<(), String> {
+		self.client.insert_aux(&[(&key[..], &value[..])], &[]).map_err(|e| format!("{:?}", e))
+	}
+
+	fn staged<T: Decode>(&self, key: &[u8]) -> Result<T, String> {
+		self.client.get_aux(key).map_err(|e| format!("{:?}", e))?
+			.and_then(|data| Decode::decode(&mut &data[..]))
+			.ok_or_else(|| "Staged warp sync data missing".to_string())
+	}
+
+	fn unstage(&self, keys: Vec<Vec<u8>>) {
+		for keys in keys.chunks(HEADERS_RANGE_SIZE as usize) {
+			let keys = keys.iter().map(|key| &key[..]).collect::<Vec<_>>();
+			if let Err(e) = self.client.insert_aux(&[], &keys) {
+				warn!("Failed to remove staged warp sync data: {:?}", e);
+			}
+		}
+	}
+}
+
+impl<B, E, RA> WarpStore for ClientStore<B, E, RA> where
+	B: client::backend::Backend<Block, Blake2Hasher> + Send + Sync,
+	E: client::CallExecutor<Block, Blake2Hasher> + Send + Sync,
+	RA: Send + Sync,
+	<B::State as StateBackend<Blake2Hasher>>::Transaction: HashDB<Blake2Hasher, DBValue> + Default,
+{
+	fn finalized(&self) -> Result<(u64, Hash), String> {
+		let info = self.client.info().map_err(|e| format!("{:?}", e))?.chain;
+		Ok((info.finalized_number, info.finalized_hash))
+	}
+
+	fn stage_headers(&mut self, headers: &[Header]) -> Result<(), String> {
+		for header in headers {
+			self.stage(staged_key(STAGED_HEADER_PREFIX, *header.number()), header.encode())?;
+		}
+		Ok(())
+	}
+
+	fn stage_state(&mut self, index: u32, chunk: &StateChunk) -> Result<(), String> {
+		self.stage(staged_key(STAGED_STATE_PREFIX, index as u64), chunk.encode())
+	}
+
+	fn import(self: Box<Self>, header: Header, base: (u64, Hash), chunks: u32) -> Result<(), String> {
+		let mut importer = StateImporter::new(&*self.client, header.clone(), base.1)?;
+
+		let mut next_chunk = 0;
+		for number in base.0 + 1..*header.number() {
+			if next_chunk < chunks {
+				importer.import_chunk(&self.staged::<StateChunk>(&staged_key(STAGED_STATE_PREFIX, next_chunk as u64))?)?;
+				next_chunk += 1;
+			}
+			importer.import_ancestor(self.staged(&staged_key(STAGED_HEADER_PREFIX, number))?)?;
+		}
+		for index in next_chunk..chunks {
+			importer.import_chunk(&self.staged::<StateChunk>(&staged_key(STAGED_STATE_PREFIX, index as u64))?)?;
+		}
+		importer.finish()?;
+
+		let staged = (base.0 + 1..*header.number()).map(|number| staged_key(STAGED_HEADER_PREFIX, number))
+			.chain((0..chunks).map(|index| staged_key(STAGED_STATE_PREFIX, index as u64)))
+			.collect();
+		self.unstage(staged);
+
+		(self.on_imported)(&header);
+		Ok(())
+	}
+}
+
+/// GRANDPA authority set as persisted by substrate-finality-grandpa, without pending changes.
+#[derive(Encode)]
+struct GrandpaAuthoritySet {
+	current_authorities: Vec<(AuthorityId, u64)>,
+	set_id: u64,
+	/// Empty fork tree of pending standard changes: no roots and no best finalized number.
+	pending_standard_changes: (Vec<()>, Option<u64>),
+	pending_forced_changes: Vec<()>,
+}
+
+/// Persist the GRANDPA authority set of the warped-to block for the voter to start from, the
+/// blocks signalling the changes leading to it having been skipped.
+///
+/// The authorities are those of the block state, and the set ID counts the changes signalled
+/// up to it: the runtime schedules them without delay, so they are all enacted. The voter
+/// only reads the set when it starts.
+pub fn reset_grandpa_authorities<B, E, RA>(client: &Client<B, E, Block, RA>, header: &Header) -> Result<(), String> where
+	B: client::backend::Backend<Block, Blake2Hasher>,
+	E: client::CallExecutor<Block, Blake2Hasher>,
+	Client<B, E, Block, RA>: ProvideRuntimeApi,
+	<Client<B, E, Block, RA> as ProvideRuntimeApi>::Api: GrandpaApi<Block>,
+{
+	let at = BlockId::hash(header.hash());
+	let api = client.runtime_api();
+	let current_authorities = api.grandpa_authorities(&at).map_err(|e| format!("{:?}", e))?;
+
+	let mut set_id = 0;
+	for number in 1..=*header.number() {
+		let digest = client.header(&BlockId::number(number)).map_err(|e| format!("{:?}", e))?
+			.ok_or_else(|| format!("Header #{} not found", number))?
+			.digest().clone();
+		if api.grandpa_pending_change(&at, &digest).map_err(|e| format!("{:?}", e))?.is_some() {
+			set_id += 1;
+		}
+		if api.grandpa_forced_change(&at, &digest).map_err(|e| format!("{:?}", e))?.is_some() {
+			set_id += 1;
+		}
+	}
+
+	let set = GrandpaAuthoritySet {
+		current_authorities,
+		set_id,
+		pending_standard_changes: (Vec::new(), None),
+		pending_forced_changes: Vec::new(),
+	};
+	info!("GRANDPA authority set #{} of {} authorities at #{}", set_id, set.current_authorities.len(), header.number());
+	client.insert_aux(&[(GRANDPA_AUTHORITY_SET_KEY, &set.encode()[..])], &[GRANDPA_SET_STATE_KEY])
+		.map_err(|e| format!("{:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use state_machine::InMemory;
+	use trie::MemoryDB;
+
+	fn header(number: u64, parent_hash: Hash, state_root: Hash) -> Header {
+		Header::new(number, Default::default(), state_root, parent_hash, Default::default())
+	}
+
+	fn state(entries: u32) -> (TrieBackend<MemoryDB<Blake2Hasher>, Blake2Hasher>, Vec<(Vec<u8>, Vec<u8>)>) {
+		let mut entries = (0..entries).map(|i| (i.encode(), vec![i as u8; 8])).collect::<Vec<_>>();
+		entries.sort();
+		let state = InMemory::<Blake2Hasher>::from(entries.iter().cloned().collect::<HashMap<_, _>>())
+			.try_into_trie_backend()
+			.unwrap();
+		(state, entries)
+	}
+
+	/// Chain from genesis up to the block of the state root, number `number`.
+	fn chain(number: u64, state_root: Hash) -> Vec<Header> {
+		let mut headers = vec![header(0, Default::default(), Default::default())];
+		for n in 1..=number {
+			let parent = headers.last().unwrap().hash();
+			headers.push(header(n, parent, if n == number { state_root } else { Default::default() }));
+		}
+		headers
+	}
+
+	/// Entries of the state proven by the chunks.
+	fn entries_of(root: Hash, chunks: &[StateChunk]) -> Vec<(Vec<u8>, Vec<u8>)> {
+		let mut verifier = StateVerifier::new(root);
+		let mut nodes = Vec::new();
+		for chunk in chunks {
+			nodes.extend(verifier.verify(chunk).unwrap());
+		}
+		assert!(verifier.is_complete());
+		let nodes = ProofNodes::new(&nodes);
+		walk_entries(&nodes, &root, &[], usize::max_value()).unwrap().0
+	}
+
+	#[derive(Default)]
+	struct Staged {
+		headers: BTreeMap<u64, Header>,
+		chunks: BTreeMap<u32, StateChunk>,
+		imported: Option<(Header, (u64, Hash), u32)>,
+	}
+
+	struct MemoryStore {
+		genesis: Hash,
+		staged: Arc<Mutex<Staged>>,
+	}
+
+	impl WarpStore for MemoryStore {
+		fn finalized(&self) -> Result<(u64, Hash), String> {
+			Ok((0, self.genesis))
+		}
+
+		fn stage_headers(&mut self, headers: &[Header]) -> Result<(), String> {
+			self.staged.lock().headers.extend(headers.iter().map(|header| (*header.number(), header.clone())));
+			Ok(())
+		}
+
+		fn stage_state(&mut self, index: u32, chunk: &StateChunk) -> Result<(), String> {
+			self.staged.lock().chunks.insert(index, chunk.clone());
+			Ok(())
+		}
+
+		fn import(self: Box<Self>, header: Header, base: (u64, Hash), chunks: u32) -> Result<(), String> {
+			self.staged.lock().imported = Some((header, base, chunks));
+			Ok(())
+		}
+	}
+
+	fn checkpoint(header: &Header) -> Checkpoint {
+		Checkpoint {
+			number: *header.number(),
+			hash: header.hash().as_ref().to_vec(),
+			state_root: header.state_root().as_ref().to_vec(),
+			next_forced_index: 0,
+		}
+	}
+
+	/// Peer serving the chain, with the state at its last block.
+	fn server(headers: Vec<Header>, entries: u32) -> Inner {
+		let (state, _) = state(entries);
+		let top = headers.last().unwrap().clone();
+		let mut inner = Inner::default();
+		inner.state_source = Some(Box::new(move |block, start_key| {
+			if *block != top.hash() {
+				return None;
+			}
+			Some((top.encode(), prove_chunk(&state, start_key).unwrap()))
+		}));
+		inner.header_source = Some(Box::new(move |start, count| {
+			headers.iter().skip(start as usize).take(count as usize).map(|header| header.encode()).collect()
+		}));
+		inner
+	}
+
+	/// Warping node, with the checkpoint of the block seen long enough.
+	fn client(genesis: Hash, checkpoint: Checkpoint) -> (Inner, Arc<Mutex<Staged>>) {
+		let staged = Arc::new(Mutex::new(Staged::default()));
+		let mut inner = Inner::default();
+		inner.settle = Duration::from_secs(0);
+		inner.store = Some(Box::new(MemoryStore { genesis, staged: staged.clone() }));
+		inner.on_checkpoint(checkpoint, Instant::now());
+		(inner, staged)
+	}
+
+	/// Deliver the messages between the client and the servers until none is left, returning
+	/// the peers reported and the number of requests each server got.
+	fn run(client: &mut Inner, servers: &mut [(PeerId, Inner)]) -> (Vec<PeerId>, HashMap<PeerId, usize>) {
+		let now = Instant::now();
+		let mut pending = Vec::new();
+		for (peer, _) in servers.iter() {
+			pending.extend(client.on_connect(peer.clone(), now));
+		}
+		pending.extend(client.maintain(now));
+
+		let mut reported = Vec::new();
+		let mut requests = HashMap::new();
+		while !pending.is_empty() {
+			let mut next = Vec::new();
+			for action in pending {
+				match action {
+					Action::Send(peer, message) => {
+						*requests.entry(peer.clone()).or_insert(0) += 1;
+						let server = &mut servers.iter_mut().find(|(id, _)| *id == peer).unwrap().1;
+						for response in server.on_message(PeerId::random(), message, now) {
+							if let Action::Send(_, response) = response {
+								next.extend(client.on_message(peer.clone(), response, now));
+							}
+						}
+					},
+					Action::Report(peer, _) => {
+						reported.push(peer.clone());
+						next.extend(client.on_disconnect(&peer, now));
+					},
+				}
+			}
+			pending = next;
+		}
+		(reported, requests)
+	}
+
+	#[test]
+	fn state_is_served_in_proven_chunks() {
+		let (state, entries) = state(10_000);
+		let mut chunks = vec![prove_chunk(&state, &[]).unwrap()];
+		let mut verifier = StateVerifier::new(*state.root());
+		while !chunks.last().unwrap().complete {
+			verifier.verify(chunks.last().unwrap()).unwrap();
+			chunks.push(prove_chunk(&state, verifier.start_key()).unwrap());
+		}
+
+		assert_eq!(chunks.iter().map(|chunk| chunk.count).collect::<Vec<_>>(), vec![4096, 4096, 1808]);
+		assert_eq!(entries_of(*state.root(), &chunks), entries);
+	}
+
+	#[test]
+	fn chunks_not_matching_their_proof_are_rejected() {
+		let (state, _) = state(5000);
+		let chunk = prove_chunk(&state, &[]).unwrap();
+
+		let mut truncated = chunk.clone();
+		truncated.proof.pop();
+		assert!(StateVerifier::new(*state.root()).verify(&truncated).is_err());
+
+		let mut ending = chunk.clone();
+		ending.complete = true;
+		assert!(StateVerifier::new(*state.root()).verify(&ending).is_err());
+
+		let mut short = chunk.clone();
+		short.count -= 1;
+		assert!(StateVerifier::new(*state.root()).verify(&short).is_err());
+
+		assert!(StateVerifier::new(Hash::repeat_byte(1)).verify(&chunk).is_err());
+
+		let mut verifier = StateVerifier::new(*state.root());
+		let mut padded = chunk.clone();
+		padded.proof.push(vec![1, 2, 3]);
+		let nodes = verifier.verify(&padded).unwrap();
+		assert_eq!(nodes.len(), chunk.proof.len());
+		assert!(verifier.verify(&chunk).is_err());
+	}
+
+	#[test]
+	fn ranges_must_be_linked_and_as_requested() {
+		let headers = chain(5, Default::default());
+		let encoded = headers.iter().map(|header| header.encode()).collect::<Vec<_>>();
+
+		assert_eq!(decode_range(1, 4, 1, &encoded[1..5]).unwrap(), headers[1..5].to_vec());
+		assert!(decode_range(1, 4, 2, &encoded[2..6]).is_err());
+		assert!(decode_range(1, 4, 1, &encoded[1..4]).is_err());
+
+		let mut forked = encoded[1..5].to_vec();
+		forked[2] = header(3, Hash::repeat_byte(1), Default::default()).encode();
+		assert!(decode_range(1, 4, 1, &forked).is_err());
+	}
+
+	#[test]
+	fn warps_to_the_latest_checkpoint_downloading_headers_in_parallel() {
+		let (state, entries) = state(9000);
+		let headers = chain(3000, *state.root());
+		let top = headers.last().unwrap().clone();
+
+		let (mut client, staged) = client(headers[0].hash(), checkpoint(&headers[100]));
+		client.on_checkpoint(checkpoint(&top), Instant::now());
+		client.on_checkpoint(checkpoint(&headers[200]), Instant::now());
+
+		let mut servers = (0..3).map(|_| (PeerId::random(), server(headers.clone(), 9000))).collect::<Vec<_>>();
+		let (reported, requests) = run(&mut client, &mut servers);
+
+		assert!(reported.is_empty());
+		assert_eq!(requests.len(), 3);
+		let job = client.take_import().unwrap();
+		assert_eq!((job.header.clone(), job.base, job.chunks), (top.clone(), (0, headers[0].hash()), 3));
+		job.run().unwrap();
+
+		let staged = staged.lock();
+		assert_eq!(staged.imported.as_ref().map(|(header, _, _)| header.clone()), Some(top));
+		assert_eq!(staged.headers.values().cloned().collect::<Vec<_>>(), headers[1..3000].to_vec());
+		assert_eq!(entries_of(*state.root(), &staged.chunks.values().cloned().collect::<Vec<_>>()), entries);
+	}
+
+	#[test]
+	fn checkpoints_keep_the_download_waiting_until_they_settle() {
+		let headers = chain(2, Default::default());
+		let (mut client, _) = client(headers[0].hash(), checkpoint(&headers[1]));
+		client.settle = Duration::from_secs(60);
+
+		let now = Instant::now();
+		client.on_checkpoint(checkpoint(&headers[2]), now);
+		client.maintain(now);
+		assert!(client.target.is_none());
+
+		client.maintain(now + Duration::from_secs(61));
+		assert_eq!(client.target.as_ref().map(|target| target.number), Some(2));
+	}
+
+	#[test]
+	fn peers_sending_bad_data_are_reported_and_the_download_goes_on() {
+		let (state, entries) = state(5000);
+		let headers = chain(1500, *state.root());
+		let top = headers.last().unwrap().clone();
+		let (mut client, staged) = client(headers[0].hash(), checkpoint(&top));
+
+		// a peer of another state, and a peer of a fork below the anchored block
+		let bad_state = server(headers.clone(), 4000);
+		let mut forked = vec![headers[0].clone()];
+		for n in 1..=1500 {
+			let parent = forked.last().unwrap().hash();
+			forked.push(header(n, parent, Hash::repeat_byte(1)));
+		}
+		let bad_headers = server(forked, 5000);
+
+		let (bad_state_peer, bad_headers_peer) = (PeerId::random(), PeerId::random());
+		let mut servers = vec![
+			(bad_state_peer.clone(), bad_state),
+			(bad_headers_peer.clone(), bad_headers),
+			(PeerId::random(), server(headers.clone(), 5000)),
+		];
+		let (reported, _) = run(&mut client, &mut servers);
+
+		assert!(reported.contains(&bad_state_peer));
+		assert!(reported.contains(&bad_headers_peer));
+		client.take_import().unwrap().run().unwrap();
+
+		let staged = staged.lock();
+		assert_eq!(staged.headers.values().cloned().collect::<Vec<_>>(), headers[1..1500].to_vec());
+		assert_eq!(entries_of(*state.root(), &staged.chunks.values().cloned().collect::<Vec<_>>()), entries);
+	}
+
+	#[test]
+	fn anchored_block_must_descend_from_the_finalized_block() {
+		let (state, _) = state(10);
+		let headers = chain(10, *state.root());
+		let (mut client, _) = client(Hash::repeat_byte(9), checkpoint(headers.last().unwrap()));
+
+		let mut servers = vec![(PeerId::random(), server(headers, 10))];
+		run(&mut client, &mut servers);
+
+		assert!(client.done);
+		assert!(client.target.is_none());
+		assert!(client.take_import().is_none());
+	}
+}
@@ -0,0 +1,412 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Tokens issued on the branch, optionally bridged to root chain tokens.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use rstd::prelude::*;
+use parity_codec::{Encode, Decode, Codec};
+use support::{
+	Parameter, decl_storage, decl_module, decl_event, StorageValue, StorageMap,
+	dispatch::Result, ensure,
+};
+use runtime_primitives::traits::{Member, SimpleArithmetic, StaticLookup, Zero, CheckedAdd, CheckedSub};
+use system::{ensure_signed, ensure_root};
+
+/// Identifier of an asset.
+pub type AssetId = u32;
+
+/// Max length of an asset symbol.
+pub const MAX_SYMBOL_LENGTH: usize = 16;
+
+/// Ownership and supply of an asset.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct AssetDetails<AccountId, Balance> {
+	pub owner: AccountId,
+	pub supply: Balance,
+}
+
+/// Display metadata of an asset.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct AssetMetadata {
+	pub symbol: Vec<u8>,
+	pub decimals: u8,
+}
+
+/// Moves assets to the root chain.
+pub trait RootBridge<Balance> {
+	/// Queue a transfer of `amount` of the root chain token `root_token` to `recipient` on the root chain.
+	fn bridge_out(root_token: Vec<u8>, recipient: Vec<u8>, amount: Balance) -> Result;
+}
+
+impl<Balance> RootBridge<Balance> for () {
+	fn bridge_out(_root_token: Vec<u8>, _recipient: Vec<u8>, _amount: Balance) -> Result {
+		Err("Root chain bridge not available")
+	}
+}
+
+pub trait Trait: system::Trait {
+	/// The balance of an asset account.
+	type Balance: Parameter + Member + SimpleArithmetic + Codec + Default + Copy;
+
+	/// Moves bridged assets to the root chain.
+	type RootBridge: RootBridge<Self::Balance>;
+
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as Assets {
+		/// Id of the next issued asset.
+		pub NextAssetId get(next_asset_id): AssetId;
+
+		/// Owner and supply of every asset.
+		pub Details get(details): map AssetId => Option<AssetDetails<T::AccountId, T::Balance>>;
+
+		/// Symbol and decimals of every asset.
+		pub Metadata get(metadata): map AssetId => AssetMetadata;
+
+		/// Balance of an account in an asset.
+		pub Balances get(balance): map (AssetId, T::AccountId) => T::Balance;
+
+		/// Whether an account is frozen in an asset.
+		pub Frozen get(frozen): map (AssetId, T::AccountId) => bool;
+
+		/// Root chain token an asset is bridged to.
+		pub RootToken get(root_token): map AssetId => Option<Vec<u8>>;
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Issue a new asset, crediting the whole initial supply to the sender.
+		fn issue(origin, #[compact] total: T::Balance, symbol: Vec<u8>, decimals: u8) -> Result {
+			let owner = ensure_signed(origin)?;
+			ensure_symbol(&symbol)?;
+
+			let id = Self::next_asset_id();
+			<NextAssetId<T>>::put(id + 1);
+
+			<Details<T>>::insert(id, AssetDetails { owner: owner.clone(), supply: total });
+			<Metadata<T>>::insert(id, AssetMetadata { symbol, decimals });
+			<Balances<T>>::insert((id, owner.clone()), total);
+
+			Self::deposit_event(RawEvent::Issued(id, owner, total));
+			Ok(())
+		}
+
+		/// Transfer `amount` of an asset to `target`.
+		fn transfer(origin, #[compact] id: AssetId, target: <T::Lookup as StaticLookup>::Source, #[compact] amount: T::Balance) -> Result {
+			let origin = ensure_signed(origin)?;
+			let target = T::Lookup::lookup(target)?;
+
+			Self::do_transfer(id, &origin, &target, amount)?;
+
+			Self::deposit_event(RawEvent::Transferred(id, origin, target, amount));
+			Ok(())
+		}
+
+		/// Mint `amount` of an asset to `beneficiary`. Asset owner only.
+		fn mint(origin, #[compact] id: AssetId, beneficiary: <T::Lookup as StaticLookup>::Source, #[compact] amount: T::Balance) -> Result {
+			let origin = ensure_signed(origin)?;
+			let beneficiary = T::Lookup::lookup(beneficiary)?;
+			Self::ensure_owner(id, &origin)?;
+
+			Self::do_mint(id, &beneficiary, amount)?;
+
+			Self::deposit_event(RawEvent::Minted(id, beneficiary, amount));
+			Ok(())
+		}
+
+		/// Burn `amount` of an asset from the sender.
+		fn burn(origin, #[compact] id: AssetId, #[compact] amount: T::Balance) -> Result {
+			let origin = ensure_signed(origin)?;
+
+			Self::do_burn(id, &origin, amount)?;
+
+			Self::deposit_event(RawEvent::Burned(id, origin, amount));
+			Ok(())
+		}
+
+		/// Stop `who` from moving an asset. Asset owner only.
+		fn freeze(origin, #[compact] id: AssetId, who: <T::Lookup as StaticLookup>::Source) -> Result {
+			let origin = ensure_signed(origin)?;
+			let who = T::Lookup::lookup(who)?;
+			Self::ensure_owner(id, &origin)?;
+
+			<Frozen<T>>::insert((id, who.clone()), true);
+
+			Self::deposit_event(RawEvent::Frozen(id, who));
+			Ok(())
+		}
+
+		/// Allow `who` to move an asset again. Asset owner only.
+		fn thaw(origin, #[compact] id: AssetId, who: <T::Lookup as StaticLookup>::Source) -> Result {
+			let origin = ensure_signed(origin)?;
+			let who = T::Lookup::lookup(who)?;
+			Self::ensure_owner(id, &origin)?;
+
+			<Frozen<T>>::remove((id, who.clone()));
+
+			Self::deposit_event(RawEvent::Thawed(id, who));
+			Ok(())
+		}
+
+		/// Set the symbol and decimals of an asset. Asset owner only.
+		fn set_metadata(origin, #[compact] id: AssetId, symbol: Vec<u8>, decimals: u8) -> Result {
+			let origin = ensure_signed(origin)?;
+			Self::ensure_owner(id, &origin)?;
+			ensure_symbol(&symbol)?;
+
+			<Metadata<T>>::insert(id, AssetMetadata { symbol, decimals });
+
+			Self::deposit_event(RawEvent::MetadataSet(id));
+			Ok(())
+		}
+
+		/// Bridge an asset to a root chain token.
+		///
+		/// Burning a bridged asset releases root chain tokens, so only governance binds them.
+		fn set_root_token(origin, #[compact] id: AssetId, root_token: Vec<u8>) -> Result {
+			ensure_root(origin)?;
+			ensure!(<Details<T>>::exists(id), "Unknown asset");
+
+			<RootToken<T>>::insert(id, root_token);
+
+			Self::deposit_event(RawEvent::RootTokenSet(id));
+			Ok(())
+		}
+
+		/// Burn `amount` of a bridged asset and queue its transfer to `recipient` on the root chain.
+		fn bridge_out(origin, #[compact] id: AssetId, recipient: Vec<u8>, #[compact] amount: T::Balance) -> Result {
+			let origin = ensure_signed(origin)?;
+			let root_token = Self::root_token(id).ok_or("Asset is not bridged")?;
+
+			Self::ensure_can_burn(id, &origin, amount)?;
+			T::RootBridge::bridge_out(root_token, recipient, amount)?;
+			Self::do_burn(id, &origin, amount)?;
+
+			Self::deposit_event(RawEvent::BridgedOut(id, origin, amount));
+			Ok(())
+		}
+
+		/// Mint `amount` of a bridged asset received from the root chain.
+		fn bridge_in(origin, #[compact] id: AssetId, beneficiary: T::AccountId, #[compact] amount: T::Balance) -> Result {
+			ensure_root(origin)?;
+			ensure!(<RootToken<T>>::exists(id), "Asset is not bridged");
+
+			Self::do_mint(id, &beneficiary, amount)?;
+
+			Self::deposit_event(RawEvent::BridgedIn(id, beneficiary, amount));
+			Ok(())
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where
+		<T as system::Trait>::AccountId,
+		Balance = <T as Trait>::Balance,
+	{
+		/// Asset issued: id, owner, total supply.
+		Issued(AssetId, AccountId, Balance),
+		/// Asset transferred: id, from, to, amount.
+		Transferred(AssetId, AccountId, AccountId, Balance),
+		/// Asset minted: id, beneficiary, amount.
+		Minted(AssetId, AccountId, Balance),
+		/// Asset burned: id, owner, amount.
+		Burned(AssetId, AccountId, Balance),
+		/// Account frozen in an asset.
+		Frozen(AssetId, AccountId),
+		/// Account thawed in an asset.
+		Thawed(AssetId, AccountId),
+		/// Asset metadata changed.
+		MetadataSet(AssetId),
+		/// Asset bridged to a root chain token.
+		RootTokenSet(AssetId),
+		/// Asset burned and queued for the root chain: id, owner, amount.
+		BridgedOut(AssetId, AccountId, Balance),
+		/// Asset received from the root chain: id, beneficiary, amount.
+		BridgedIn(AssetId, AccountId, Balance),
+	}
+);
+
+impl<T: Trait> Module<T> {
+
+	fn ensure_owner(id: AssetId, who: &T::AccountId) -> Result {
+		let details = Self::details(id).ok_or("Unknown asset")?;
+		ensure!(&details.owner == who, "Only the asset owner can do this");
+		Ok(())
+	}
+
+	fn ensure_not_frozen(id: AssetId, who: &T::AccountId) -> Result {
+		ensure!(!Self::frozen((id, who.clone())), "Account is frozen");
+		Ok(())
+	}
+
+	fn do_transfer(id: AssetId, from: &T::AccountId, to: &T::AccountId, amount: T::Balance) -> Result {
+		ensure!(<Details<T>>::exists(id), "Unknown asset");
+		ensure!(!amount.is_zero(), "Transfer amount should be non-zero");
+		Self::ensure_not_frozen(id, from)?;
+
+		let from_balance = Self::balance((id, from.clone()));
+		let new_from_balance = from_balance.checked_sub(&amount).ok_or("Balance too low to send value")?;
+		if from == to {
+			return Ok(());
+		}
+		let to_balance = Self::balance((id, to.clone()));
+		let new_to_balance = to_balance.checked_add(&amount).ok_or("Balance overflow")?;
+
+		// both sides are checked before writing either
+		<Balances<T>>::insert((id, from.clone()), new_from_balance);
+		<Balances<T>>::insert((id, to.clone()), new_to_balance);
+		Ok(())
+	}
+
+	fn do_mint(id: AssetId, who: &T::AccountId, amount: T::Balance) -> Result {
+		let mut details = Self::details(id).ok_or("Unknown asset")?;
+		details.supply = details.supply.checked_add(&amount).ok_or("Supply overflow")?;
+
+		let balance = Self::balance((id, who.clone()));
+		<Balances<T>>::insert((id, who.clone()), balance + amount);
+		<Details<T>>::insert(id, details);
+		Ok(())
+	}
+
+	fn ensure_can_burn(id: AssetId, who: &T::AccountId, amount: T::Balance) -> Result {
+		ensure!(<Details<T>>::exists(id), "Unknown asset");
+		Self::ensure_not_frozen(id, who)?;
+		ensure!(Self::balance((id, who.clone())) >= amount, "Balance too low to burn");
+		Ok(())
+	}
+
+	fn do_burn(id: AssetId, who: &T::AccountId, amount: T::Balance) -> Result {
+		Self::ensure_can_burn(id, who, amount)?;
+
+		let mut details = Self::details(id).ok_or("Unknown asset")?;
+		let new_balance = Self::balance((id, who.clone())) - amount;
+		details.supply = details.supply - amount;
+
+		<Balances<T>>::insert((id, who.clone()), new_balance);
+		<Details<T>>::insert(id, details);
+		Ok(())
+	}
+}
+
+fn ensure_symbol(symbol: &[u8]) -> Result {
+	ensure!(!symbol.is_empty() && symbol.len() <= MAX_SYMBOL_LENGTH, "Invalid asset symbol");
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use runtime_io::with_externalities;
+	use primitives::{H256, Blake2Hasher};
+	use support::{impl_outer_origin, assert_ok, assert_noop};
+	use runtime_primitives::{
+		BuildStorage,
+		traits::{BlakeTwo256, IdentityLookup},
+		testing::{Digest, DigestItem, Header}
+	};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+	impl Trait for Test {
+		type Balance = u64;
+		type RootBridge = ();
+		type Event = ();
+	}
+	type Assets = Module<Test>;
+
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		system::GenesisConfig::<Test>::default().build_storage().unwrap().0.into()
+	}
+
+	#[test]
+	fn issue_and_transfer() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Assets::issue(Origin::signed(1), 100, b"LOYAL".to_vec(), 2));
+			assert_eq!(Assets::metadata(0), AssetMetadata { symbol: b"LOYAL".to_vec(), decimals: 2 });
+			assert_ok!(Assets::transfer(Origin::signed(1), 0, 2, 40));
+			assert_eq!(Assets::balance((0, 1)), 60);
+			assert_eq!(Assets::balance((0, 2)), 40);
+			assert_noop!(Assets::transfer(Origin::signed(2), 0, 1, 41), "Balance too low to send value");
+			assert_ok!(Assets::transfer(Origin::signed(2), 0, 2, 40));
+			assert_eq!(Assets::balance((0, 2)), 40);
+		});
+	}
+
+	#[test]
+	fn mint_and_burn_track_supply() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Assets::issue(Origin::signed(1), 100, b"LOYAL".to_vec(), 2));
+			assert_noop!(Assets::mint(Origin::signed(2), 0, 2, 10), "Only the asset owner can do this");
+			assert_ok!(Assets::mint(Origin::signed(1), 0, 2, 10));
+			assert_ok!(Assets::burn(Origin::signed(1), 0, 30));
+			assert_eq!(Assets::details(0).unwrap().supply, 80);
+		});
+	}
+
+	#[test]
+	fn frozen_accounts_can_not_transfer() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Assets::issue(Origin::signed(1), 100, b"LOYAL".to_vec(), 2));
+			assert_ok!(Assets::transfer(Origin::signed(1), 0, 2, 50));
+			assert_ok!(Assets::freeze(Origin::signed(1), 0, 2));
+			assert_noop!(Assets::transfer(Origin::signed(2), 0, 1, 10), "Account is frozen");
+			assert_ok!(Assets::thaw(Origin::signed(1), 0, 2));
+			assert_ok!(Assets::transfer(Origin::signed(2), 0, 1, 10));
+		});
+	}
+
+	#[test]
+	fn unbridged_assets_can_not_bridge_out() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Assets::issue(Origin::signed(1), 100, b"LOYAL".to_vec(), 2));
+			assert_noop!(Assets::bridge_out(Origin::signed(1), 0, vec![1], 10), "Asset is not bridged");
+			assert_noop!(Assets::set_root_token(Origin::signed(1), 0, b"ROOT".to_vec()), "bad origin: expected to be a root origin");
+			assert_noop!(Assets::set_root_token(Origin::ROOT, 1, b"ROOT".to_vec()), "Unknown asset");
+			assert_ok!(Assets::set_root_token(Origin::ROOT, 0, b"ROOT".to_vec()));
+			assert_noop!(Assets::bridge_out(Origin::signed(1), 0, vec![1], 10), "Root chain bridge not available");
+		});
+	}
+}
@@ -0,0 +1,455 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Root chain side of yee-root-port, for the root chain runtime, declared as `BranchPort`:
+//!
+//! ```ignore
+//! BranchPort: yee_branch_port::{Module, Call, Storage, Event<T>},
+//! ```
+//!
+//! Branches are registered with the session key of their owner. Meta transactions the owner
+//! signs are relayed by any root chain account: checkpoints anchor branch blocks, read by branch
+//! nodes under `yee_root_port::ROOT_CHECKPOINTS_PREFIX`, and token transfers are handed to the
+//! root chain token module. Calls posted for a branch, which it must execute, are read by branch
+//! nodes under `yee_root_port::ROOT_FORCED_CALLS_PREFIX`, one per index. Each call holds a deposit
+//! of its poster until a checkpoint shows the branch executed it, when it is pruned.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use rstd::prelude::*;
+use parity_codec::{Encode, Decode};
+use support::{
+	Parameter, decl_storage, decl_module, decl_event, StorageMap,
+	dispatch::Result, ensure,
+	traits::{Currency, ReservableCurrency},
+};
+use runtime_primitives::traits::{Hash, Verify};
+use system::{ensure_signed, ensure_root};
+use yee_root_port::{Checkpoint, ForcedCall, MetaTx, SignedMetaTx};
+
+/// Index of `submit_meta_tx` among the calls of the module, for nodes encoding it.
+pub const SUBMIT_META_TX_CALL_INDEX: u8 = 1;
+
+/// Most calls posted for a branch and not pruned yet.
+pub const MAX_PENDING_CALLS: u64 = 1024;
+
+/// Most calls pruned by a checkpoint or a `prune_calls` call.
+pub const MAX_PRUNED_CALLS: u64 = 64;
+
+type BalanceOf<T> = <<T as Trait>::Currency as Currency<<T as system::Trait>::AccountId>>::Balance;
+
+/// Root chain token module crediting the tokens transferred out of branches.
+pub trait OnTokenTransfer {
+	fn on_token_transfer(branch: &[u8], token: &[u8], recipient: &[u8], amount: u128) -> Result;
+}
+
+impl OnTokenTransfer for () {
+	fn on_token_transfer(_: &[u8], _: &[u8], _: &[u8], _: u128) -> Result {
+		Err("Token transfers are not supported")
+	}
+}
+
+pub trait Trait: system::Trait {
+	/// Session key of branch owners.
+	type SessionKey: Parameter + Default;
+
+	/// Signature of branch owners.
+	type Signature: Parameter + Verify<Signer=Self::SessionKey>;
+
+	/// Where tokens transferred out of branches go.
+	type OnTokenTransfer: OnTokenTransfer;
+
+	/// Currency of the deposits of posted calls.
+	type Currency: ReservableCurrency<Self::AccountId>;
+
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as BranchPort {
+		/// Owner session key of a branch, by branch ID (the branch genesis hash).
+		pub Owners get(owner): map Vec<u8> => Option<T::SessionKey>;
+
+		/// Latest checkpoint of a branch, by branch ID.
+		pub Checkpoints get(checkpoint): map Vec<u8> => Option<Checkpoint>;
+
+		/// Deposit reserved from the poster of a call until the call is pruned.
+		pub ForcedCallDeposit get(forced_call_deposit) config(): BalanceOf<T>;
+
+		/// Call posted for a branch, by branch ID and index.
+		pub ForcedCalls get(forced_call): map (Vec<u8>, u64) => Option<ForcedCall>;
+
+		/// Poster of a call and the deposit reserved from it, by branch ID and index.
+		pub ForcedCallPosters get(forced_call_poster): map (Vec<u8>, u64) => Option<(T::AccountId, BalanceOf<T>)>;
+
+		/// Index of the first call of a branch not pruned yet, by branch ID.
+		pub FirstCallIndex get(first_call_index): map Vec<u8> => u64;
+
+		/// Index of the next call posted for a branch, by branch ID.
+		pub NextCallIndex get(next_call_index): map Vec<u8> => u64;
+
+		/// Meta transactions already relayed, by hash of the encoded branch ID and signed meta
+		/// transaction.
+		pub Relayed get(relayed): map T::Hash => bool;
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Register a branch with the session key of its owner.
+		fn register_branch(origin, branch: Vec<u8>, owner: T::SessionKey) -> Result {
+			ensure_root(origin)?;
+			ensure!(Self::owner(&branch).is_none(), "Branch already registered");
+
+			<Owners<T>>::insert(&branch, owner);
+			Self::deposit_event(RawEvent::BranchRegistered(branch));
+			Ok(())
+		}
+
+		/// Relay a meta transaction signed by the owner of the branch.
+		fn submit_meta_tx(origin, branch: Vec<u8>, signed: SignedMetaTx) -> Result {
+			ensure_signed(origin)?;
+			let owner = Self::owner(&branch).ok_or("Branch not registered")?;
+			ensure!(signed.signer == owner.encode(), "Not signed by the branch owner");
+			let signature = T::Signature::decode(&mut &signed.signature[..]).ok_or("Invalid signature")?;
+			ensure!(signature.verify(&signed.meta_tx.encode()[..], &owner), "Invalid owner signature");
+
+			let hash = T::Hashing::hash_of(&(&branch, &signed));
+			ensure!(!Self::relayed(hash), "Meta transaction already relayed");
+
+			match signed.meta_tx {
+				MetaTx::Checkpoint { branch: checkpoint_branch, checkpoint } => {
+					ensure!(checkpoint_branch == branch, "Checkpoint of another branch");
+					ensure!(
+						Self::checkpoint(&branch).map_or(true, |last| last.number < checkpoint.number),
+						"Checkpoint not after the latest one"
+					);
+					ensure!(
+						checkpoint.next_forced_index <= Self::next_call_index(&branch),
+						"Checkpoint executes calls not posted"
+					);
+					let number = checkpoint.number;
+					<Checkpoints<T>>::insert(&branch, checkpoint);
+					Self::prune_executed_calls(&branch);
+					Self::deposit_event(RawEvent::CheckpointAnchored(branch, number));
+				},
+				MetaTx::TokenTransfer { id, token, recipient, amount } => {
+					T::OnTokenTransfer::on_token_transfer(&branch, &token, &recipient, amount)?;
+					Self::deposit_event(RawEvent::TokenTransferred(branch, id));
+				},
+			}
+
+			<Relayed<T>>::insert(hash, true);
+			Ok(())
+		}
+
+		/// Post a call signed by a branch account, which the branch must execute within its
+		/// inclusion period of the latest checkpoint of the branch.
+		///
+		/// `ForcedCallDeposit` is reserved from the poster until the call is pruned.
+		fn post_call(origin, branch: Vec<u8>, sender: Vec<u8>, #[compact] nonce: u64, call: Vec<u8>, signature: Vec<u8>) -> Result {
+			let who = ensure_signed(origin)?;
+			ensure!(Self::owner(&branch).is_some(), "Branch not registered");
+
+			let index = Self::next_call_index(&branch);
+			ensure!(index - Self::first_call_index(&branch) < MAX_PENDING_CALLS, "Too many calls pending for the branch");
+
+			let deposit = Self::forced_call_deposit();
+			T::Currency::reserve(&who, deposit)?;
+
+			let anchor = Self::checkpoint(&branch).map_or(0, |checkpoint| checkpoint.number);
+			let key = (branch.clone(), index);
+			<ForcedCalls<T>>::insert(&key, ForcedCall { index, sender, nonce, call, signature, anchor });
+			<ForcedCallPosters<T>>::insert(&key, (who.clone(), deposit));
+			<NextCallIndex<T>>::insert(&branch, index + 1);
+
+			Self::deposit_event(RawEvent::CallPosted(branch, index, who));
+			Ok(())
+		}
+
+		/// Prune the next calls of the branch executed as of its latest checkpoint, once a
+		/// checkpoint left more than `MAX_PRUNED_CALLS` of them.
+		fn prune_calls(origin, branch: Vec<u8>) -> Result {
+			ensure_signed(origin)?;
+			ensure!(Self::prune_executed_calls(&branch) > 0, "No executed calls to prune");
+			Ok(())
+		}
+	}
+}
+
+impl<T: Trait> Module<T> {
+
+	/// Prune up to `MAX_PRUNED_CALLS` calls of the branch before the next forced index of its
+	/// latest checkpoint, returning the deposits to their posters, and return how many.
+	///
+	/// A call is executed once the branch consumed its index, whether it succeeded or not.
+	fn prune_executed_calls(branch: &[u8]) -> u64 {
+		let branch = branch.to_vec();
+		let executed = Self::checkpoint(&branch).map_or(0, |checkpoint| checkpoint.next_forced_index);
+		let first = Self::first_call_index(&branch);
+		let end = executed.min(first + MAX_PRUNED_CALLS);
+		if end <= first {
+			return 0;
+		}
+
+		for index in first..end {
+			let key = (branch.clone(), index);
+			<ForcedCalls<T>>::remove(&key);
+			if let Some((poster, deposit)) = <ForcedCallPosters<T>>::take(&key) {
+				T::Currency::unreserve(&poster, deposit);
+			}
+		}
+		<FirstCallIndex<T>>::insert(&branch, end);
+
+		Self::deposit_event(RawEvent::CallsPruned(branch, end));
+		end - first
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where <T as system::Trait>::AccountId {
+		/// Branch registered.
+		BranchRegistered(Vec<u8>),
+		/// Block of the branch with the number anchored.
+		CheckpointAnchored(Vec<u8>, u64),
+		/// Token transfer with the ID out of the branch credited.
+		TokenTransferred(Vec<u8>, u64),
+		/// Call with the index posted for the branch by the account.
+		CallPosted(Vec<u8>, u64, AccountId),
+		/// Calls of the branch executed before the index pruned.
+		CallsPruned(Vec<u8>, u64),
+	}
+);
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use runtime_io::with_externalities;
+	use primitives::{H256, Blake2Hasher};
+	use support::{impl_outer_origin, assert_ok, assert_noop};
+	use runtime_primitives::{
+		BuildStorage,
+		traits::{BlakeTwo256, IdentityLookup, Lazy},
+		testing::{Digest, DigestItem, Header, UintAuthorityId}
+	};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+
+	/// Signature of `msg` by the key, valid if it carries both.
+	#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+	pub struct TestSignature(u64, Vec<u8>);
+	impl Verify for TestSignature {
+		type Signer = UintAuthorityId;
+		fn verify<L: Lazy<[u8]>>(&self, mut msg: L, signer: &UintAuthorityId) -> bool {
+			signer.0 == self.0 && msg.get() == &self.1[..]
+		}
+	}
+
+	pub struct TestTokens;
+	impl OnTokenTransfer for TestTokens {
+		fn on_token_transfer(_: &[u8], token: &[u8], _: &[u8], _: u128) -> Result {
+			ensure!(token == b"YEE", "Unknown token");
+			Ok(())
+		}
+	}
+
+	impl balances::Trait for Test {
+		type Balance = u64;
+		type OnFreeBalanceZero = ();
+		type OnNewAccount = ();
+		type Event = ();
+		type TransactionPayment = ();
+		type TransferPayment = ();
+		type DustRemoval = ();
+	}
+
+	impl Trait for Test {
+		type SessionKey = UintAuthorityId;
+		type Signature = TestSignature;
+		type OnTokenTransfer = TestTokens;
+		type Currency = Balances;
+		type Event = ();
+	}
+	type Balances = balances::Module<Test>;
+	type BranchPort = Module<Test>;
+
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		t.extend(balances::GenesisConfig::<Test> {
+			balances: vec![(1, 100), (2, 15)],
+			existential_deposit: 0,
+			transaction_base_fee: 0,
+			transaction_byte_fee: 0,
+			transfer_fee: 0,
+			creation_fee: 0,
+			vesting: vec![],
+		}.build_storage().unwrap().0);
+		t.extend(GenesisConfig::<Test> {
+			forced_call_deposit: 10,
+		}.build_storage().unwrap().0);
+		t.into()
+	}
+
+	fn branch() -> Vec<u8> {
+		vec![7; 32]
+	}
+
+	fn signed(key: u64, meta_tx: MetaTx) -> SignedMetaTx {
+		SignedMetaTx {
+			signer: UintAuthorityId(key).encode(),
+			signature: TestSignature(key, meta_tx.encode()).encode(),
+			meta_tx,
+		}
+	}
+
+	fn checkpoint(number: u64) -> MetaTx {
+		checkpoint_executing(number, 0)
+	}
+
+	fn checkpoint_executing(number: u64, next_forced_index: u64) -> MetaTx {
+		MetaTx::Checkpoint {
+			branch: branch(),
+			checkpoint: Checkpoint { number, hash: vec![1; 32], state_root: vec![2; 32], next_forced_index },
+		}
+	}
+
+	fn post(who: u64, nonce: u64) -> Result {
+		BranchPort::post_call(Origin::signed(who), branch(), vec![1], nonce, vec![2], vec![3])
+	}
+
+	#[test]
+	fn owner_checkpoints_are_anchored_in_order() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_noop!(BranchPort::submit_meta_tx(Origin::signed(1), branch(), signed(5, checkpoint(10))), "Branch not registered");
+			assert_ok!(BranchPort::register_branch(Origin::ROOT, branch(), UintAuthorityId(5)));
+
+			assert_noop!(
+				BranchPort::submit_meta_tx(Origin::signed(1), branch(), signed(6, checkpoint(10))),
+				"Not signed by the branch owner"
+			);
+			assert_ok!(BranchPort::submit_meta_tx(Origin::signed(1), branch(), signed(5, checkpoint(10))));
+			assert_eq!(BranchPort::checkpoint(branch()).map(|checkpoint| checkpoint.number), Some(10));
+			assert_noop!(
+				BranchPort::submit_meta_tx(Origin::signed(1), branch(), signed(5, checkpoint(10))),
+				"Meta transaction already relayed"
+			);
+			assert_noop!(
+				BranchPort::submit_meta_tx(Origin::signed(1), branch(), signed(5, checkpoint(9))),
+				"Checkpoint not after the latest one"
+			);
+		});
+	}
+
+	#[test]
+	fn token_transfers_are_credited_once() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(BranchPort::register_branch(Origin::ROOT, branch(), UintAuthorityId(5)));
+
+			let transfer = |id, token: &[u8]| MetaTx::TokenTransfer { id, token: token.to_vec(), recipient: vec![3], amount: 100 };
+			assert_noop!(BranchPort::submit_meta_tx(Origin::signed(1), branch(), signed(5, transfer(0, b"BTC"))), "Unknown token");
+			assert_ok!(BranchPort::submit_meta_tx(Origin::signed(1), branch(), signed(5, transfer(0, b"YEE"))));
+			assert_ok!(BranchPort::submit_meta_tx(Origin::signed(1), branch(), signed(5, transfer(1, b"YEE"))));
+			assert_noop!(
+				BranchPort::submit_meta_tx(Origin::signed(2), branch(), signed(5, transfer(1, b"YEE"))),
+				"Meta transaction already relayed"
+			);
+		});
+	}
+
+	#[test]
+	fn posted_calls_are_indexed_and_anchored() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(BranchPort::register_branch(Origin::ROOT, branch(), UintAuthorityId(5)));
+
+			assert_ok!(post(1, 0));
+			assert_ok!(BranchPort::submit_meta_tx(Origin::signed(1), branch(), signed(5, checkpoint(10))));
+			assert_ok!(post(1, 1));
+			let posted: Vec<_> = (0..3)
+				.filter_map(|index| BranchPort::forced_call((branch(), index)))
+				.map(|forced| (forced.index, forced.anchor))
+				.collect();
+			assert_eq!(posted, vec![(0, 0), (1, 10)]);
+			assert_eq!(BranchPort::next_call_index(branch()), 2);
+		});
+	}
+
+	#[test]
+	fn posted_calls_hold_a_deposit_until_pruned() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(BranchPort::register_branch(Origin::ROOT, branch(), UintAuthorityId(5)));
+
+			assert_ok!(post(1, 0));
+			assert_ok!(post(2, 0));
+			assert_noop!(post(2, 1), "not enough free funds");
+			assert_ok!(post(1, 1));
+			assert_eq!(Balances::reserved_balance(&1), 20);
+			assert_eq!(Balances::reserved_balance(&2), 10);
+
+			assert_noop!(
+				BranchPort::submit_meta_tx(Origin::signed(3), branch(), signed(5, checkpoint_executing(10, 4))),
+				"Checkpoint executes calls not posted"
+			);
+			assert_ok!(BranchPort::submit_meta_tx(Origin::signed(3), branch(), signed(5, checkpoint_executing(10, 2))));
+			assert_eq!(BranchPort::first_call_index(branch()), 2);
+			assert!(BranchPort::forced_call((branch(), 1)).is_none());
+			assert!(BranchPort::forced_call((branch(), 2)).is_some());
+			assert_eq!(Balances::reserved_balance(&1), 10);
+			assert_eq!(Balances::reserved_balance(&2), 0);
+			assert_noop!(BranchPort::prune_calls(Origin::signed(3), branch()), "No executed calls to prune");
+		});
+	}
+
+	#[test]
+	fn pending_calls_are_bounded_and_pruned_in_batches() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(BranchPort::register_branch(Origin::ROOT, branch(), UintAuthorityId(5)));
+			<NextCallIndex<Test>>::insert(branch(), MAX_PENDING_CALLS);
+			assert_noop!(post(1, 0), "Too many calls pending for the branch");
+
+			let executed = MAX_PRUNED_CALLS + 1;
+			assert_ok!(BranchPort::submit_meta_tx(Origin::signed(3), branch(), signed(5, checkpoint_executing(10, executed))));
+			assert_eq!(BranchPort::first_call_index(branch()), MAX_PRUNED_CALLS);
+			assert_ok!(post(1, 0));
+
+			assert_ok!(BranchPort::prune_calls(Origin::signed(3), branch()));
+			assert_eq!(BranchPort::first_call_index(branch()), executed);
+			assert_noop!(BranchPort::prune_calls(Origin::signed(3), branch()), "No executed calls to prune");
+		});
+	}
+}
@@ -0,0 +1,321 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Transaction fees charged per byte and per call weight, paid to the block author.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use rstd::prelude::*;
+use support::{
+	Parameter, decl_storage, decl_module, decl_event, StorageValue, StorageMap,
+	dispatch::Result,
+	traits::{Currency, OnUnbalanced, Imbalance, MakePayment, WithdrawReason, ExistenceRequirement},
+};
+use runtime_primitives::Permill;
+use runtime_primitives::traits::{As, Bounded, CheckedMul, Saturating, Zero};
+use system::ensure_root;
+
+/// Relative cost of dispatching a call.
+pub type Weight = u32;
+
+/// Weight of a call, used to charge the weight part of its fee.
+pub trait Weighable {
+	fn weight(&self) -> Weight;
+}
+
+/// Finds the session key of the author of the block being built or imported.
+pub trait FindAuthor<SessionKey> {
+	fn find_author() -> Option<SessionKey>;
+}
+
+type BalanceOf<T> = <<T as Trait>::Currency as Currency<<T as system::Trait>::AccountId>>::Balance;
+type NegativeImbalanceOf<T> = <<T as Trait>::Currency as Currency<<T as system::Trait>::AccountId>>::NegativeImbalance;
+
+pub trait Trait: system::Trait {
+	/// The currency fees are paid in.
+	type Currency: Currency<Self::AccountId>;
+
+	/// The identifier of block authors.
+	type SessionKey: Parameter;
+
+	/// How the author of the current block is found.
+	type FindAuthor: FindAuthor<Self::SessionKey>;
+
+	/// Where the share of fees not going to the block author goes.
+	type OnFeeShare: OnUnbalanced<NegativeImbalanceOf<Self>>;
+
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as Fee {
+		/// Fee charged for every transaction.
+		pub BaseFee get(base_fee) config(): BalanceOf<T>;
+
+		/// Fee charged for every byte of an encoded transaction.
+		pub ByteFee get(byte_fee) config(): BalanceOf<T>;
+
+		/// Fee charged for every unit of call weight.
+		pub WeightFee get(weight_fee) config(): BalanceOf<T>;
+
+		/// Part of every fee going to `OnFeeShare` instead of the block author.
+		pub FeeShare get(fee_share) config(): Permill;
+
+		/// Account receiving the fees of blocks authored with the given session key.
+		pub AuthorAccount get(author_account) build(|config: &GenesisConfig<T>| config.author_accounts.clone()):
+			map T::SessionKey => Option<T::AccountId>;
+	}
+	add_extra_genesis {
+		config(author_accounts): Vec<(T::SessionKey, T::AccountId)>;
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Set the fee schedule.
+		fn set_fees(origin, #[compact] base_fee: BalanceOf<T>, #[compact] byte_fee: BalanceOf<T>, #[compact] weight_fee: BalanceOf<T>) -> Result {
+			ensure_root(origin)?;
+
+			<BaseFee<T>>::put(base_fee);
+			<ByteFee<T>>::put(byte_fee);
+			<WeightFee<T>>::put(weight_fee);
+
+			Self::deposit_event(RawEvent::FeesChanged(base_fee, byte_fee, weight_fee));
+			Ok(())
+		}
+
+		/// Set the part of every fee going to `OnFeeShare` instead of the block author.
+		fn set_fee_share(origin, fee_share: Permill) -> Result {
+			ensure_root(origin)?;
+
+			<FeeShare<T>>::put(fee_share);
+
+			Self::deposit_event(RawEvent::FeeShareChanged(fee_share));
+			Ok(())
+		}
+
+		/// Set the account receiving the fees of blocks authored with `key`.
+		fn set_author_account(origin, key: T::SessionKey, account: T::AccountId) -> Result {
+			ensure_root(origin)?;
+
+			<AuthorAccount<T>>::insert(&key, &account);
+
+			Self::deposit_event(RawEvent::AuthorAccountSet(key, account));
+			Ok(())
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where
+		Balance = BalanceOf<T>,
+		<T as system::Trait>::AccountId,
+		<T as Trait>::SessionKey,
+	{
+		/// Fee schedule changed: base fee, byte fee, weight fee.
+		FeesChanged(Balance, Balance, Balance),
+		/// Part of fees not going to the block author changed.
+		FeeShareChanged(Permill),
+		/// Fees of blocks authored with the session key now go to the account.
+		AuthorAccountSet(SessionKey, AccountId),
+	}
+);
+
+impl<T: Trait> Module<T> {
+
+	/// Fee of a transaction with the given encoded length and call weight.
+	pub fn compute_fee(encoded_len: usize, weight: Weight) -> BalanceOf<T> {
+		Self::length_fee(encoded_len).saturating_add(Self::weight_fee_of(weight))
+	}
+
+	/// Charge `who` the weight fee of a call, paid like the rest of its transaction fee.
+	pub fn charge_weight(who: &T::AccountId, weight: Weight) -> Result {
+		Self::charge(who, Self::weight_fee_of(weight))
+	}
+
+	/// Base and byte fee of a transaction with the given encoded length.
+	fn length_fee(encoded_len: usize) -> BalanceOf<T> {
+		let len = <BalanceOf<T> as As<u64>>::sa(encoded_len as u64);
+		Self::base_fee().saturating_add(saturating_mul(Self::byte_fee(), len))
+	}
+
+	/// Weight fee of a call with the given weight.
+	fn weight_fee_of(weight: Weight) -> BalanceOf<T> {
+		saturating_mul(Self::weight_fee(), <BalanceOf<T> as As<u64>>::sa(weight as u64))
+	}
+
+	/// Withdraw `fee` from `who`, splitting it between `OnFeeShare` and the block author.
+	fn charge(who: &T::AccountId, fee: BalanceOf<T>) -> Result {
+		if fee.is_zero() {
+			return Ok(());
+		}
+
+		let imbalance = T::Currency::withdraw(
+			who,
+			fee,
+			WithdrawReason::TransactionPayment,
+			ExistenceRequirement::KeepAlive,
+		)?;
+		let (share, rest) = imbalance.split(Self::fee_share() * fee);
+		T::OnFeeShare::on_unbalanced(share);
+		ToAuthor::<T>::on_unbalanced(rest);
+		Ok(())
+	}
+}
+
+fn saturating_mul<B: CheckedMul + Bounded>(a: B, b: B) -> B {
+	a.checked_mul(&b).unwrap_or_else(B::max_value)
+}
+
+/// Pays fees to the account of the current block author, burning them if it is unknown.
+pub struct ToAuthor<T>(rstd::marker::PhantomData<T>);
+
+impl<T: Trait> OnUnbalanced<NegativeImbalanceOf<T>> for ToAuthor<T> {
+	fn on_unbalanced(amount: NegativeImbalanceOf<T>) {
+		if let Some(author) = T::FindAuthor::find_author().and_then(<Module<T>>::author_account) {
+			T::Currency::resolve_creating(&author, amount);
+		}
+	}
+}
+
+/// Charges the base and byte fees of transactions; the weight fee is charged by `charge_weight`.
+impl<T: Trait> MakePayment<T::AccountId> for Module<T> {
+	fn make_payment(transactor: &T::AccountId, encoded_len: usize) -> Result {
+		Self::charge(transactor, Self::length_fee(encoded_len))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use runtime_io::with_externalities;
+	use primitives::{H256, Blake2Hasher};
+	use support::{impl_outer_origin, assert_ok, assert_noop};
+	use runtime_primitives::{
+		BuildStorage,
+		traits::{BlakeTwo256, IdentityLookup},
+		testing::{Digest, DigestItem, Header}
+	};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+	impl balances::Trait for Test {
+		type Balance = u64;
+		type OnFreeBalanceZero = ();
+		type OnNewAccount = ();
+		type Event = ();
+		type TransactionPayment = ();
+		type TransferPayment = ();
+		type DustRemoval = ();
+	}
+	pub struct TestAuthor;
+	impl FindAuthor<u64> for TestAuthor {
+		fn find_author() -> Option<u64> {
+			Some(7)
+		}
+	}
+	impl Trait for Test {
+		type Currency = Balances;
+		type SessionKey = u64;
+		type FindAuthor = TestAuthor;
+		type OnFeeShare = ();
+		type Event = ();
+	}
+	type Balances = balances::Module<Test>;
+	type Fee = Module<Test>;
+
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		t.extend(balances::GenesisConfig::<Test> {
+			balances: vec![(1, 1000)],
+			existential_deposit: 0,
+			transaction_base_fee: 0,
+			transaction_byte_fee: 0,
+			transfer_fee: 0,
+			creation_fee: 0,
+			vesting: vec![],
+		}.build_storage().unwrap().0);
+		t.extend(GenesisConfig::<Test> {
+			base_fee: 10,
+			byte_fee: 1,
+			weight_fee: 2,
+			fee_share: Permill::zero(),
+			author_accounts: vec![(7, 70)],
+		}.build_storage().unwrap().0);
+		t.into()
+	}
+
+	#[test]
+	fn fee_is_charged_per_byte_and_weight_and_paid_to_author() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Fee::make_payment(&1, 5));
+			assert_ok!(Fee::charge_weight(&1, 20));
+			assert_eq!(Balances::free_balance(&1), 1000 - (10 + 5 + 40));
+			assert_eq!(Balances::free_balance(&70), 10 + 5 + 40);
+		});
+	}
+
+	#[test]
+	fn fee_share_is_taken_from_author_part() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Fee::set_fee_share(Origin::ROOT, Permill::from_percent(20)));
+			assert_ok!(Fee::make_payment(&1, 40));
+			assert_eq!(Balances::free_balance(&1), 1000 - 50);
+			assert_eq!(Balances::free_balance(&70), 40);
+		});
+	}
+
+	#[test]
+	fn only_root_sets_fees() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_noop!(Fee::set_fees(Origin::signed(1), 0, 0, 0), "bad origin: expected to be a root origin");
+			assert_ok!(Fee::set_fees(Origin::ROOT, 1, 0, 0));
+			assert_eq!(Fee::compute_fee(100, 100), 1);
+		});
+	}
+
+	#[test]
+	fn fee_saturates_instead_of_overflowing() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Fee::set_fees(Origin::ROOT, 1, u64::max_value() / 2, u64::max_value() / 2));
+			assert_eq!(Fee::compute_fee(4, 0), u64::max_value());
+			assert_eq!(Fee::compute_fee(1, Weight::max_value()), u64::max_value());
+			assert_noop!(Fee::charge_weight(&1, 3), "too few free funds in account");
+		});
+	}
+}
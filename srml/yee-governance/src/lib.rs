@@ -0,0 +1,465 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! On-chain governance: council motions approved by a threshold of members are dispatched
+//! as root after a timelock.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use rstd::prelude::*;
+use parity_codec::{Encode, Decode};
+use support::{
+	Parameter, decl_storage, decl_module, decl_event, StorageValue, StorageMap,
+	dispatch::{Result, Dispatchable}, ensure,
+};
+use runtime_primitives::Permill;
+use runtime_primitives::traits::{As, Zero};
+use system::{ensure_signed, ensure_root};
+
+/// Index of a motion.
+pub type MotionIndex = u32;
+
+/// Storage key of the retired sudo key.
+const SUDO_KEY: &[u8] = b"Sudo Key";
+
+/// Smallest council the chain is left to, so that no single key rules it.
+pub const MIN_COUNCIL_SIZE: usize = 2;
+
+/// A council motion being voted on.
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct Motion<AccountId, Proposal, BlockNumber> {
+	pub proposer: AccountId,
+	pub proposal: Proposal,
+	pub ayes: Vec<AccountId>,
+	pub nays: Vec<AccountId>,
+	/// Last block votes are accepted in.
+	pub end: BlockNumber,
+}
+
+pub trait Trait: system::Trait {
+	/// The call a motion dispatches as root once approved.
+	type Proposal: Parameter + Dispatchable<Origin=<Self as system::Trait>::Origin>;
+
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as Governance {
+		/// Council members.
+		pub Members get(members) config(): Vec<T::AccountId>;
+
+		/// Part of the council that has to approve a motion.
+		pub Threshold get(threshold) config(): Permill;
+
+		/// Number of blocks a motion is open for votes.
+		pub VotingPeriod get(voting_period) config(): T::BlockNumber;
+
+		/// Number of blocks between the approval of a motion and its dispatch.
+		pub EnactmentDelay get(enactment_delay) config(): T::BlockNumber;
+
+		/// Number of motions ever proposed.
+		pub MotionCount get(motion_count): MotionIndex;
+
+		/// Motions open for votes.
+		pub Motions get(motion): map MotionIndex => Option<Motion<T::AccountId, T::Proposal, T::BlockNumber>>;
+
+		/// Indices of motions open for votes.
+		pub ActiveMotions get(active_motions): Vec<MotionIndex>;
+
+		/// Approved motions, by the block they are dispatched in.
+		pub Scheduled get(scheduled): map T::BlockNumber => Vec<(MotionIndex, T::Proposal)>;
+
+		/// Whether the sudo key has been retired.
+		pub SudoRetired get(sudo_retired) build(|_: &GenesisConfig<T>| true): bool;
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Propose a motion, voting aye on it.
+		fn propose(origin, proposal: Box<T::Proposal>) -> Result {
+			let who = ensure_signed(origin)?;
+			Self::ensure_member(&who)?;
+
+			let index = Self::motion_count();
+			<MotionCount<T>>::put(index + 1);
+
+			let end = <system::Module<T>>::block_number() + Self::voting_period();
+			let motion = Motion {
+				proposer: who.clone(),
+				proposal: *proposal,
+				ayes: vec![who.clone()],
+				nays: vec![],
+				end,
+			};
+			<ActiveMotions<T>>::mutate(|active| active.push(index));
+			Self::deposit_event(RawEvent::Proposed(who, index));
+
+			Self::tally(index, motion);
+			Ok(())
+		}
+
+		/// Vote on an open motion.
+		fn vote(origin, #[compact] index: MotionIndex, approve: bool) -> Result {
+			let who = ensure_signed(origin)?;
+			Self::ensure_member(&who)?;
+
+			let mut motion = Self::motion(index).ok_or("Motion is not open")?;
+			ensure!(<system::Module<T>>::block_number() <= motion.end, "Motion voting has ended");
+
+			motion.ayes.retain(|a| a != &who);
+			motion.nays.retain(|a| a != &who);
+			if approve {
+				motion.ayes.push(who.clone());
+			} else {
+				motion.nays.push(who.clone());
+			}
+			Self::deposit_event(RawEvent::Voted(who, index, approve));
+
+			Self::tally(index, motion);
+			Ok(())
+		}
+
+		/// Cancel an approved motion before it is dispatched.
+		fn cancel_enactment(origin, when: T::BlockNumber, #[compact] index: MotionIndex) -> Result {
+			ensure_root(origin)?;
+
+			let mut scheduled = Self::scheduled(when);
+			let len = scheduled.len();
+			scheduled.retain(|(i, _)| *i != index);
+			ensure!(scheduled.len() != len, "Motion is not scheduled at that block");
+			<Scheduled<T>>::insert(when, scheduled);
+
+			Self::deposit_event(RawEvent::Cancelled(index));
+			Ok(())
+		}
+
+		/// Add a council member.
+		fn add_member(origin, who: T::AccountId) -> Result {
+			ensure_root(origin)?;
+			ensure!(!Self::is_member(&who), "Already a member");
+
+			<Members<T>>::mutate(|members| members.push(who.clone()));
+
+			Self::deposit_event(RawEvent::MemberAdded(who));
+			Ok(())
+		}
+
+		/// Remove a council member, withdrawing its votes on open motions.
+		fn remove_member(origin, who: T::AccountId) -> Result {
+			ensure_root(origin)?;
+			ensure!(Self::is_member(&who), "Not a member");
+			ensure!(Self::members().len() > MIN_COUNCIL_SIZE, "Council can not shrink below its minimum size");
+
+			<Members<T>>::mutate(|members| members.retain(|m| m != &who));
+			Self::deposit_event(RawEvent::MemberRemoved(who.clone()));
+
+			// the thresholds change with the council, so every open motion is tallied again
+			for index in Self::active_motions() {
+				if let Some(mut motion) = Self::motion(index) {
+					motion.ayes.retain(|a| a != &who);
+					motion.nays.retain(|a| a != &who);
+					Self::tally(index, motion);
+				}
+			}
+			Ok(())
+		}
+
+		/// Set the approval threshold, voting period and enactment delay.
+		fn set_rules(origin, threshold: Permill, voting_period: T::BlockNumber, enactment_delay: T::BlockNumber) -> Result {
+			ensure_root(origin)?;
+			ensure!(threshold != Permill::from_percent(0), "Threshold can not be zero");
+			ensure!(!enactment_delay.is_zero(), "Enactment delay can not be zero");
+
+			<Threshold<T>>::put(threshold);
+			<VotingPeriod<T>>::put(voting_period);
+			<EnactmentDelay<T>>::put(enactment_delay);
+
+			Self::deposit_event(RawEvent::RulesChanged(threshold, voting_period, enactment_delay));
+			Ok(())
+		}
+
+		fn on_initialize(n: T::BlockNumber) {
+			if !Self::sudo_retired() {
+				Self::retire_sudo();
+			}
+			Self::enact(n);
+			Self::expire(n);
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where
+		<T as system::Trait>::AccountId,
+		<T as system::Trait>::BlockNumber,
+	{
+		/// Motion proposed by a member.
+		Proposed(AccountId, MotionIndex),
+		/// Member voted on a motion: aye if true.
+		Voted(AccountId, MotionIndex, bool),
+		/// Motion approved, dispatched at the given block.
+		Approved(MotionIndex, BlockNumber),
+		/// Motion rejected.
+		Rejected(MotionIndex),
+		/// Motion voting ended without approval.
+		Expired(MotionIndex),
+		/// Approved motion cancelled before dispatch.
+		Cancelled(MotionIndex),
+		/// Motion dispatched: success if true.
+		Executed(MotionIndex, bool),
+		/// Council member added.
+		MemberAdded(AccountId),
+		/// Council member removed.
+		MemberRemoved(AccountId),
+		/// Rules changed: threshold, voting period, enactment delay.
+		RulesChanged(Permill, BlockNumber, BlockNumber),
+		/// Sudo key retired, with its holder if it was set.
+		SudoRetired(Option<AccountId>),
+	}
+);
+
+impl<T: Trait> Module<T> {
+
+	pub fn is_member(who: &T::AccountId) -> bool {
+		Self::members().contains(who)
+	}
+
+	fn ensure_member(who: &T::AccountId) -> Result {
+		ensure!(Self::is_member(who), "Only council members can do this");
+		Ok(())
+	}
+
+	/// Whether `votes` out of the current council reach the threshold.
+	fn reaches_threshold(votes: usize) -> bool {
+		let members = Self::members().len() as u64;
+		if members == 0 {
+			return false;
+		}
+		let required = Self::threshold() * (members * 1_000_000);
+		votes as u64 * 1_000_000 >= required
+	}
+
+	fn tally(index: MotionIndex, motion: Motion<T::AccountId, T::Proposal, T::BlockNumber>) {
+		let members = Self::members().len();
+
+		if Self::reaches_threshold(motion.ayes.len()) {
+			Self::close(index);
+			let when = <system::Module<T>>::block_number() + Self::enactment_delay() + As::sa(1);
+			<Scheduled<T>>::mutate(when, |scheduled| scheduled.push((index, motion.proposal)));
+			Self::deposit_event(RawEvent::Approved(index, when));
+		} else if !Self::reaches_threshold(members.saturating_sub(motion.nays.len())) {
+			Self::close(index);
+			Self::deposit_event(RawEvent::Rejected(index));
+		} else {
+			<Motions<T>>::insert(index, motion);
+		}
+	}
+
+	fn close(index: MotionIndex) {
+		<Motions<T>>::remove(index);
+		<ActiveMotions<T>>::mutate(|active| active.retain(|i| *i != index));
+	}
+
+	fn enact(n: T::BlockNumber) {
+		for (index, proposal) in <Scheduled<T>>::take(n) {
+			let ok = proposal.dispatch(system::RawOrigin::Root.into()).is_ok();
+			Self::deposit_event(RawEvent::Executed(index, ok));
+		}
+	}
+
+	fn expire(n: T::BlockNumber) {
+		for index in Self::active_motions() {
+			if let Some(motion) = Self::motion(index) {
+				if motion.end < n {
+					Self::close(index);
+					Self::deposit_event(RawEvent::Expired(index));
+				}
+			}
+		}
+	}
+
+	/// One-shot migration from sudo: remove the sudo key once a council of at least
+	/// `MIN_COUNCIL_SIZE` members, enacting motions after a delay, can take over from it. Until
+	/// then the key is kept and the migration tried again every block.
+	fn retire_sudo() {
+		if Self::members().len() < MIN_COUNCIL_SIZE || Self::enactment_delay().is_zero() {
+			return;
+		}
+
+		let key = runtime_io::twox_128(SUDO_KEY);
+		let sudo_key = runtime_io::storage(&key).and_then(|v| T::AccountId::decode(&mut &v[..]));
+		runtime_io::clear_storage(&key);
+
+		if Self::threshold() == Permill::from_percent(0) {
+			<Threshold<T>>::put(Permill::from_percent(50));
+		}
+		if Self::voting_period().is_zero() {
+			<VotingPeriod<T>>::put(T::BlockNumber::sa(14_400));
+		}
+		<SudoRetired<T>>::put(true);
+
+		Self::deposit_event(RawEvent::SudoRetired(sudo_key));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use runtime_io::with_externalities;
+	use primitives::{H256, Blake2Hasher};
+	use support::{impl_outer_origin, impl_outer_dispatch, assert_ok, assert_noop};
+	use runtime_primitives::{
+		BuildStorage,
+		traits::{BlakeTwo256, IdentityLookup, OnInitialize},
+		testing::{Digest, DigestItem, Header}
+	};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	mod governance {
+		pub use super::super::*;
+	}
+
+	impl_outer_dispatch! {
+		pub enum Call for Test where origin: Origin {
+			governance::Governance,
+		}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+	impl Trait for Test {
+		type Proposal = Call;
+		type Event = ();
+	}
+	type Governance = Module<Test>;
+	type System = system::Module<Test>;
+
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		t.extend(GenesisConfig::<Test> {
+			members: vec![1, 2, 3],
+			threshold: Permill::from_percent(60),
+			voting_period: 10,
+			enactment_delay: 5,
+		}.build_storage().unwrap().0);
+		t.into()
+	}
+
+	fn add_member(who: u64) -> Box<Call> {
+		Box::new(Call::Governance(super::Call::add_member(who)))
+	}
+
+	#[test]
+	fn approved_motion_is_dispatched_after_delay() {
+		with_externalities(&mut new_test_ext(), || {
+			System::set_block_number(1);
+			assert_ok!(Governance::propose(Origin::signed(1), add_member(4)));
+			assert_ok!(Governance::vote(Origin::signed(2), 0, true));
+			assert_eq!(Governance::scheduled(7).len(), 1);
+
+			Governance::on_initialize(6);
+			assert!(!Governance::is_member(&4));
+			Governance::on_initialize(7);
+			assert!(Governance::is_member(&4));
+		});
+	}
+
+	#[test]
+	fn motion_below_threshold_is_rejected() {
+		with_externalities(&mut new_test_ext(), || {
+			System::set_block_number(1);
+			assert_ok!(Governance::propose(Origin::signed(1), add_member(4)));
+			assert_ok!(Governance::vote(Origin::signed(2), 0, false));
+			assert!(Governance::motion(0).is_some());
+			assert_ok!(Governance::vote(Origin::signed(3), 0, false));
+			assert_eq!(Governance::motion(0), None);
+			assert!(Governance::active_motions().is_empty());
+		});
+	}
+
+	#[test]
+	fn only_members_propose_and_only_root_changes_members() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_noop!(Governance::propose(Origin::signed(4), add_member(4)), "Only council members can do this");
+			assert_noop!(Governance::add_member(Origin::signed(1), 4), "bad origin: expected to be a root origin");
+		});
+	}
+
+	#[test]
+	fn sudo_key_is_retired_once_a_council_can_take_over() {
+		with_externalities(&mut new_test_ext(), || {
+			runtime_io::set_storage(&runtime_io::twox_128(SUDO_KEY), &9u64.encode());
+			<SudoRetired<Test>>::put(false);
+			<Members<Test>>::put(vec![1u64]);
+
+			Governance::on_initialize(1);
+			assert!(runtime_io::storage(&runtime_io::twox_128(SUDO_KEY)).is_some());
+			assert!(!Governance::sudo_retired());
+
+			<Members<Test>>::put(vec![1u64, 2]);
+			<EnactmentDelay<Test>>::put(0);
+			Governance::on_initialize(2);
+			assert!(!Governance::sudo_retired());
+
+			<EnactmentDelay<Test>>::put(5);
+			Governance::on_initialize(3);
+			assert_eq!(runtime_io::storage(&runtime_io::twox_128(SUDO_KEY)), None);
+			assert_eq!(Governance::members(), vec![1, 2]);
+			assert!(Governance::sudo_retired());
+		});
+	}
+
+	#[test]
+	fn removed_member_votes_are_withdrawn() {
+		with_externalities(&mut new_test_ext(), || {
+			System::set_block_number(1);
+			assert_ok!(Governance::propose(Origin::signed(1), add_member(4)));
+			assert_ok!(Governance::vote(Origin::signed(2), 0, false));
+
+			assert_ok!(Governance::remove_member(Origin::ROOT, 2));
+			let motion = Governance::motion(0).unwrap();
+			assert_eq!(motion.ayes, vec![1]);
+			assert!(motion.nays.is_empty());
+
+			assert_noop!(Governance::remove_member(Origin::ROOT, 1), "Council can not shrink below its minimum size");
+			assert_ok!(Governance::vote(Origin::signed(3), 0, true));
+			assert_eq!(Governance::scheduled(7).len(), 1);
+		});
+	}
+}
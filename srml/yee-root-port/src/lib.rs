@@ -19,20 +19,175 @@
 
 //#[cfg(feature = "std")]
 //use serde::Serialize;
-use support::{Parameter, decl_storage, decl_module};
+#[cfg(feature = "std")]
+use std::sync::{Arc, RwLock};
+use rstd::prelude::*;
+use support::{
+	Parameter, decl_storage, decl_module, decl_event, StorageValue, StorageMap,
+	dispatch::{Result, Dispatchable}, ensure,
+};
+use primitives::{H256, Blake2Hasher};
+use hash_db::HashDB;
+use trie::{MemoryDB, read_trie_value};
 use runtime_primitives::{
 	codec::{
-		Codec
+		Codec, Encode, Decode,
 	},
 	traits::{
-		Member, SimpleArithmetic,
+		Member, SimpleArithmetic, As, Zero, One, Verify, Header as HeaderT,
 		MaybeDisplay, MaybeSerializeDebug,
 	},
 };
+use inherents::{
+	RuntimeString, InherentIdentifier, ProvideInherent,
+	InherentData, MakeFatalError,
+};
+#[cfg(feature = "std")]
+use inherents::ProvideInherentData;
+use system::{ensure_inherent, ensure_signed, ensure_root};
+
+/// The identifier for the root sharding count inherent.
+pub const INHERENT_IDENTIFIER: InherentIdentifier = *b"rootshct";
+
+/// The identifier for the forced calls inherent data.
+pub const FORCED_CALLS_INHERENT_IDENTIFIER: InherentIdentifier = *b"rootfrcd";
+
+/// Most forced calls a block executes, the following ones being left to the next blocks.
+pub const MAX_FORCED_CALLS: usize = 16;
+
+/// Storage key of `RootShardNum`, for nodes reading it from the branch state.
+#[cfg(feature = "std")]
+pub const ROOT_SHARD_NUM_KEY: &[u8] = b"Sharding RootShardNum";
+
+/// Storage key of `GenesisOwnerSessionKey`, for nodes checking whether they own the branch.
+#[cfg(feature = "std")]
+pub const OWNER_SESSION_KEY_KEY: &[u8] = b"Sharding GenesisOwnerSessionKey";
+
+/// Storage key of `NextForcedIndex`, proven by `FraudProof::ForcedIndex`.
+pub const NEXT_FORCED_INDEX_KEY: &[u8] = b"Sharding NextForcedIndex";
+
+/// Prefix of the root chain storage map of the latest checkpoint of every branch,
+/// keyed by branch ID (the branch genesis hash): `Checkpoints` of yee-branch-port.
+#[cfg(feature = "std")]
+pub const ROOT_CHECKPOINTS_PREFIX: &[u8] = b"BranchPort Checkpoints";
+
+/// Branch block anchored in the root chain.
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct Checkpoint {
+	pub number: u64,
+	pub hash: Vec<u8>,
+	/// Filled in by the node signing the checkpoint, the runtime does not know it.
+	pub state_root: Vec<u8>,
+	/// Index of the next forced call after the block, filled in by the node signing the
+	/// checkpoint: the root chain prunes the calls before it.
+	pub next_forced_index: u64,
+}
+
+/// Prefix of the root chain storage map of the calls posted for every branch,
+/// keyed by branch ID (the branch genesis hash) and call index: `ForcedCalls` of yee-branch-port.
+#[cfg(feature = "std")]
+pub const ROOT_FORCED_CALLS_PREFIX: &[u8] = b"BranchPort ForcedCalls";
+
+/// Prefix of the root chain storage map of the index of the first call not pruned yet of
+/// every branch, keyed by branch ID: `FirstCallIndex` of yee-branch-port.
+#[cfg(feature = "std")]
+pub const ROOT_FIRST_CALL_INDEX_PREFIX: &[u8] = b"BranchPort FirstCallIndex";
+
+/// Prefix of the root chain storage map of the index of the next call posted for every
+/// branch, keyed by branch ID: `NextCallIndex` of yee-branch-port.
+#[cfg(feature = "std")]
+pub const ROOT_NEXT_CALL_INDEX_PREFIX: &[u8] = b"BranchPort NextCallIndex";
+
+/// Branch call posted on the root chain, which the branch must execute.
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct ForcedCall {
+	/// Sequence number of the call among the calls posted for the branch.
+	pub index: u64,
+	/// Encoded branch account dispatching the call.
+	pub sender: Vec<u8>,
+	/// Account nonce of the sender the call is signed for.
+	pub nonce: u64,
+	/// Encoded branch call.
+	pub call: Vec<u8>,
+	/// Signature of the encoded `(branch genesis hash, nonce, call)` by the sender.
+	pub signature: Vec<u8>,
+	/// Number of the latest branch block anchored on the root chain when the call was posted,
+	/// recorded by the root chain: the call is due within `ForcedInclusionPeriod` blocks of it.
+	pub anchor: u64,
+}
+
+/// Proof that a checkpoint signed by the owner does not match the branch.
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub enum FraudProof {
+	/// The checkpointed block is not the canonical block at its height.
+	ForkedBlock,
+	/// The checkpointed state root is not the one of the canonical block, given encoded.
+	StateRoot {
+		header: Vec<u8>,
+	},
+	/// The checkpointed state is not the one the canonical block transitioned to: the value of
+	/// `key` proven under the checkpointed state root by `claimed_proof` differs from the one
+	/// proven under the state root of the canonical block, given encoded, by `proof`.
+	StateTransition {
+		header: Vec<u8>,
+		key: Vec<u8>,
+		proof: Vec<Vec<u8>>,
+		claimed_proof: Vec<Vec<u8>>,
+	},
+	/// The checkpointed index of the next forced call is not the one proven under the state
+	/// root of the canonical block, given encoded, by `proof`.
+	ForcedIndex {
+		header: Vec<u8>,
+		proof: Vec<Vec<u8>>,
+	},
+}
+
+/// Meta transaction of the branch, to be executed on the root chain.
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub enum MetaTx {
+	/// Transfer `amount` of the root chain token `token` to `recipient`.
+	TokenTransfer {
+		/// Sequence number of the transfer among the transfers of the branch, which the root
+		/// chain credits once.
+		id: u64,
+		token: Vec<u8>,
+		recipient: Vec<u8>,
+		amount: u128,
+	},
+	/// Anchor a block of the branch `branch` in the root chain.
+	Checkpoint {
+		branch: Vec<u8>,
+		checkpoint: Checkpoint,
+	},
+}
+
+/// Meta transaction signed by the branch owner, as submitted to the root chain.
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct SignedMetaTx {
+	pub meta_tx: MetaTx,
+	/// Owner session key.
+	pub signer: Vec<u8>,
+	/// Signature of the encoded `meta_tx` by the owner session key.
+	pub signature: Vec<u8>,
+}
 
 pub trait Trait: system::Trait {
 	type SessionKey: Parameter + Default + MaybeSerializeDebug;
+	/// Signature of the owner session key.
+	type Signature: Parameter + Verify<Signer=Self::SessionKey>;
+	/// Signature of forced calls by their sender.
+	type AccountSignature: Parameter + Verify<Signer=Self::AccountId>;
+	/// Call executed by forced calls.
+	type Call: Parameter + Dispatchable<Origin=<Self as system::Trait>::Origin>;
 	type ShardNum: Member + MaybeSerializeDebug + Default + Copy + MaybeDisplay + SimpleArithmetic + Codec;
+
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
 }
 
 decl_storage! {
@@ -41,11 +196,801 @@ decl_storage! {
         pub GenesisOwnerSessionKey get(genesis_owner_session_key) config(): T::SessionKey;
 
 		pub GenesisShardingCount get(genesis_sharding_count) config(): T::ShardNum;
+
+		/// Sharding count of the root chain as last reported by the root chain, if it ever scaled out.
+		pub ShardingCount get(sharding_count): Option<T::ShardNum>;
+
+		/// Root chain shard the branch attaches to.
+		pub RootShardNum get(root_shard_num) build(|config: &GenesisConfig<T>| {
+			shard_num_for(&config.genesis_owner_session_key, config.genesis_sharding_count)
+		}): T::ShardNum;
+
+		/// Meta transactions of the current block, handed to the node by the offchain worker, or
+		/// of all the blocks since relaying halted.
+		pub PendingMetaTxs get(pending_meta_txs): Vec<MetaTx>;
+
+		/// Number of blocks between checkpoints, none if zero.
+		pub CheckpointInterval get(checkpoint_interval) config(): T::BlockNumber;
+
+		/// Number of blocks after a checkpoint is created during which it can be challenged.
+		pub DisputeWindow get(dispute_window) config(): T::BlockNumber;
+
+		/// Challenger who proved a checkpoint fraudulent, by checkpointed block number.
+		pub Frauds get(fraud): map T::BlockNumber => Option<T::AccountId>;
+
+		/// Whether relaying to the root chain stopped after a proven fraud.
+		pub Halted get(halted): bool;
+
+		/// Number of blocks after the anchor of a call posted on the root chain within which it must be executed.
+		pub ForcedInclusionPeriod get(forced_inclusion_period) config(): T::BlockNumber;
+
+		/// ID of the next token transfer to the root chain.
+		NextTokenTransferId get(next_token_transfer_id): u64;
+
+		/// Index of the next forced call to execute.
+		pub NextForcedIndex get(next_forced_index): u64;
+
+		/// Whether the root inherent was included in the current block.
+		DidImportRoot: bool;
     }
 }
 
 decl_module! {
     pub struct Module<T: Trait> for enum Call where origin: T::Origin {
 
+		fn deposit_event<T>() = default;
+
+		/// Import what the embedded root chain client observed: the sharding count after a
+		/// scale-out, and up to `MAX_FORCED_CALLS` of the calls posted for the branch, executed
+		/// in index order.
+		///
+		/// Inherent, mandatory in every block.
+		fn import_root(origin, sharding_count: Option<T::ShardNum>, forced_calls: Vec<ForcedCall>) -> Result {
+			ensure_inherent(origin)?;
+			ensure!(!<DidImportRoot<T>>::get(), "Root can only be imported once in a block");
+
+			if let Some(sharding_count) = sharding_count {
+				Self::do_set_sharding_count(sharding_count)?;
+			}
+			Self::execute_forced_calls(forced_calls);
+
+			<DidImportRoot<T>>::put(true);
+			Ok(())
+		}
+
+		/// Prove that a checkpoint signed by the owner does not match the branch.
+		///
+		/// Stops relaying to the root chain until root resumes it.
+		fn challenge_checkpoint(origin, signed: SignedMetaTx, proof: FraudProof) -> Result {
+			let challenger = ensure_signed(origin)?;
+			let checkpoint = Self::owner_checkpoint(&signed)?;
+
+			let number = T::BlockNumber::sa(checkpoint.number);
+			let now = <system::Module<T>>::block_number();
+			ensure!(number < now, "Checkpoint is not of a past block");
+			ensure!(now <= number + One::one() + Self::dispute_window(), "Dispute window is over");
+			ensure!(Self::fraud(number).is_none(), "Checkpoint fraud already proven");
+
+			Self::check_fraud(&checkpoint, &<system::Module<T>>::block_hash(number), proof)?;
+
+			<Frauds<T>>::insert(number, &challenger);
+			<Halted<T>>::put(true);
+
+			Self::deposit_event(RawEvent::CheckpointFraud(number, challenger));
+			Ok(())
+		}
+
+		/// Resume relaying to the root chain after a proven fraud, with the meta transactions
+		/// queued in the meantime.
+		fn resume(origin) -> Result {
+			ensure_root(origin)?;
+
+			<Halted<T>>::put(false);
+			Ok(())
+		}
+
+		fn on_finalize(_n: T::BlockNumber) {
+			assert!(<DidImportRoot<T>>::take(), "Root must be imported once in the block");
+		}
+
+		fn on_initialize(_n: T::BlockNumber) {
+			// the offchain worker handed the meta transactions of the previous block to the node,
+			// unless halted: they are kept to be handed over once relaying resumes
+			if !Self::halted() {
+				<PendingMetaTxs<T>>::kill();
+			}
+		}
+
+		/// Hand the meta transactions of the block, and a checkpoint every `CheckpointInterval`
+		/// blocks, to the node, which signs them with the owner session key and submits them
+		/// to the root chain.
+		fn offchain_worker(n: T::BlockNumber) {
+			if Self::halted() {
+				return;
+			}
+
+			for meta_tx in Self::pending_meta_txs() {
+				runtime_io::submit_extrinsic(&meta_tx);
+			}
+
+			let interval = Self::checkpoint_interval();
+			if !interval.is_zero() && (n % interval).is_zero() {
+				if let Some(meta_tx) = Self::checkpoint_meta_tx(n - One::one()) {
+					runtime_io::submit_extrinsic(&meta_tx);
+				}
+			}
+		}
     }
 }
+
+decl_event!(
+	pub enum Event<T> where
+		ShardNum = <T as Trait>::ShardNum,
+		<T as system::Trait>::AccountId,
+		<T as system::Trait>::BlockNumber,
+	{
+		/// Root chain sharding count changed; the branch now attaches to the given root shard.
+		ShardingCountChanged(ShardNum, ShardNum),
+		/// Meta transaction queued for the root chain, with its index in the pending queue.
+		MetaTxQueued(u32),
+		/// Forced call with the index executed: success if true.
+		ForcedCallExecuted(u64, bool),
+		/// Checkpoint of the block proven fraudulent by the challenger; relaying stopped.
+		CheckpointFraud(BlockNumber, AccountId),
+	}
+);
+
+impl<T: Trait> Module<T> {
+
+	fn do_set_sharding_count(sharding_count: T::ShardNum) -> Result {
+		check_sharding_count::<T>(sharding_count)?;
+
+		if sharding_count == Self::current_sharding_count() {
+			return Ok(());
+		}
+
+		let root_shard_num = shard_num_for(&Self::genesis_owner_session_key(), sharding_count);
+
+		<ShardingCount<T>>::put(sharding_count);
+		<RootShardNum<T>>::put(root_shard_num);
+
+		Self::deposit_event(RawEvent::ShardingCountChanged(sharding_count, root_shard_num));
+		Ok(())
+	}
+
+	/// Execute the forced calls continuing the index sequence, skipping the others.
+	///
+	/// A call failing its checks is posted on the root chain as is, `check_inherent` having
+	/// compared it to the one posted there: its index is consumed all the same.
+	fn execute_forced_calls(mut forced_calls: Vec<ForcedCall>) {
+		forced_calls.sort_by_key(|forced| forced.index);
+
+		let mut next = Self::next_forced_index();
+		for forced in forced_calls {
+			if forced.index != next {
+				continue;
+			}
+			let ok = Self::execute_forced_call(forced).is_ok();
+			Self::deposit_event(RawEvent::ForcedCallExecuted(next, ok));
+			next += 1;
+		}
+		<NextForcedIndex<T>>::put(next);
+	}
+
+	fn execute_forced_call(forced: ForcedCall) -> Result {
+		let sender = T::AccountId::decode(&mut &forced.sender[..]).ok_or("Invalid sender")?;
+		let signature = T::AccountSignature::decode(&mut &forced.signature[..]).ok_or("Invalid signature")?;
+		let call = <T as Trait>::Call::decode(&mut &forced.call[..]).ok_or("Invalid call")?;
+
+		ensure!(forced.nonce == <system::Module<T>>::account_nonce(&sender).as_(), "Invalid nonce");
+		let genesis_hash = <system::Module<T>>::block_hash(T::BlockNumber::zero());
+		let payload = (genesis_hash, forced.nonce, &forced.call).encode();
+		ensure!(signature.verify(&payload[..], &sender), "Invalid sender signature");
+
+		<system::Module<T>>::inc_account_nonce(&sender);
+		call.dispatch(system::RawOrigin::Signed(sender).into())
+	}
+
+	/// Forced calls seen by the node on the root chain from the next index on, in index order.
+	fn observed_forced_calls(data: &InherentData) -> Vec<ForcedCall> {
+		let next = Self::next_forced_index();
+		let mut seen: Vec<ForcedCall> = match data.get_data::<Vec<ForcedCall>>(&FORCED_CALLS_INHERENT_IDENTIFIER) {
+			Ok(Some(seen)) => seen.into_iter().filter(|forced| forced.index >= next).collect(),
+			_ => Vec::new(),
+		};
+		seen.sort_by_key(|forced| forced.index);
+		seen
+	}
+
+	/// Check the forced calls of a block: the next ones in index order, up to
+	/// `MAX_FORCED_CALLS`, each exactly as the node saw it on the root chain.
+	///
+	/// A block including a call the node did not see yet is rejected until it does.
+	fn check_forced_calls(data: &InherentData, included: &[ForcedCall]) -> rstd::result::Result<(), &'static str> {
+		ensure!(included.len() <= MAX_FORCED_CALLS, "Too many forced calls in the block");
+
+		let seen = Self::observed_forced_calls(data);
+		let next = Self::next_forced_index();
+		for (offset, forced) in included.iter().enumerate() {
+			ensure!(forced.index == next + offset as u64, "Forced calls not in index order");
+			ensure!(
+				seen.iter().find(|s| s.index == forced.index) == Some(forced),
+				"Forced call not as observed on the root chain"
+			);
+		}
+
+		if included.len() < MAX_FORCED_CALLS && !Self::overdue_forced_calls(&seen, included).is_empty() {
+			return Err("Forced calls not executed within the inclusion period");
+		}
+		Ok(())
+	}
+
+	/// Forced calls of `seen` which are overdue in the next block but not in `included`.
+	///
+	/// The deadline only depends on the anchor recorded on the root chain, so every node seeing
+	/// a call agrees on it.
+	fn overdue_forced_calls(seen: &[ForcedCall], included: &[ForcedCall]) -> Vec<u64> {
+		let number: u64 = (<system::Module<T>>::block_number() + One::one()).as_();
+		let period: u64 = Self::forced_inclusion_period().as_();
+
+		seen.iter()
+			.filter(|forced| forced.anchor + period <= number)
+			.filter(|forced| !included.iter().any(|i| i.index == forced.index))
+			.map(|forced| forced.index)
+			.collect()
+	}
+
+	/// Checkpoint meta transaction of the block `number`, which must be an ancestor of the current block.
+	fn checkpoint_meta_tx(number: T::BlockNumber) -> Option<MetaTx> {
+		let genesis_hash = <system::Module<T>>::block_hash(T::BlockNumber::zero());
+		let hash = <system::Module<T>>::block_hash(number);
+		if number.is_zero() || hash == Default::default() {
+			return None;
+		}
+
+		Some(MetaTx::Checkpoint {
+			branch: genesis_hash.as_ref().to_vec(),
+			checkpoint: Checkpoint {
+				number: number.as_(),
+				hash: hash.as_ref().to_vec(),
+				state_root: Vec::new(),
+				next_forced_index: 0,
+			},
+		})
+	}
+
+	/// Checkpoint of the branch in a meta transaction signed by the owner.
+	fn owner_checkpoint(signed: &SignedMetaTx) -> rstd::result::Result<Checkpoint, &'static str> {
+		let genesis_hash = <system::Module<T>>::block_hash(T::BlockNumber::zero());
+		let checkpoint = match &signed.meta_tx {
+			MetaTx::Checkpoint { branch, checkpoint } if branch[..] == *genesis_hash.as_ref() => checkpoint.clone(),
+			_ => return Err("Not a checkpoint of the branch"),
+		};
+
+		let owner = Self::genesis_owner_session_key();
+		ensure!(signed.signer == owner.encode(), "Not signed by the owner");
+		let signature = T::Signature::decode(&mut &signed.signature[..]).ok_or("Invalid signature")?;
+		ensure!(signature.verify(&signed.meta_tx.encode()[..], &owner), "Invalid owner signature");
+
+		Ok(checkpoint)
+	}
+
+	fn check_fraud(checkpoint: &Checkpoint, hash: &T::Hash, proof: FraudProof) -> Result {
+		match proof {
+			FraudProof::ForkedBlock => {
+				ensure!(checkpoint.hash[..] != *hash.as_ref(), "Checkpoint block is canonical");
+			},
+			FraudProof::StateRoot { header } => {
+				let header = Self::canonical_header(&header, hash)?;
+				ensure!(checkpoint.state_root[..] != *header.state_root().as_ref(), "Checkpoint state root is canonical");
+			},
+			FraudProof::StateTransition { header, key, proof, claimed_proof } => {
+				let header = Self::canonical_header(&header, hash)?;
+				let value = read_proof_value(header.state_root().as_ref(), proof, &key)?;
+				let claimed_value = read_proof_value(&checkpoint.state_root, claimed_proof, &key)?;
+				ensure!(value != claimed_value, "Checkpoint state agrees with the branch state");
+			},
+			FraudProof::ForcedIndex { header, proof } => {
+				let header = Self::canonical_header(&header, hash)?;
+				let key = runtime_io::twox_128(NEXT_FORCED_INDEX_KEY);
+				let value = read_proof_value(header.state_root().as_ref(), proof, &key)?;
+				let next_forced_index = match value {
+					Some(value) => u64::decode(&mut &value[..]).ok_or("Invalid forced call index")?,
+					None => 0,
+				};
+				ensure!(checkpoint.next_forced_index != next_forced_index, "Checkpoint forced call index is canonical");
+			},
+		}
+		Ok(())
+	}
+
+	fn canonical_header(encoded: &[u8], hash: &T::Hash) -> rstd::result::Result<T::Header, &'static str> {
+		let header = T::Header::decode(&mut &encoded[..]).ok_or("Invalid header")?;
+		ensure!(header.hash() == *hash, "Header is not the canonical block");
+		Ok(header)
+	}
+
+	/// Sharding count of the root chain, falling back to the genesis value.
+	pub fn current_sharding_count() -> T::ShardNum {
+		Self::sharding_count().unwrap_or_else(Self::genesis_sharding_count)
+	}
+
+	/// Queue a transfer of `amount` of the root chain token `token` to `recipient`, to be relayed
+	/// to the root chain.
+	pub fn queue_token_transfer(token: Vec<u8>, recipient: Vec<u8>, amount: u128) {
+		let id = Self::next_token_transfer_id();
+		<NextTokenTransferId<T>>::put(id + 1);
+		Self::queue_meta_tx(MetaTx::TokenTransfer { id, token, recipient, amount });
+	}
+
+	/// Queue a meta transaction to be relayed to the root chain.
+	pub fn queue_meta_tx(meta_tx: MetaTx) {
+		let index = <PendingMetaTxs<T>>::mutate(|pending| {
+			pending.push(meta_tx);
+			pending.len() as u32 - 1
+		});
+		Self::deposit_event(RawEvent::MetaTxQueued(index));
+	}
+}
+
+/// Root chain shard a branch with the given owner session key attaches to.
+pub fn shard_num_for<K: Encode, N: SimpleArithmetic>(key: &K, sharding_count: N) -> N {
+	if sharding_count.is_zero() {
+		return N::zero();
+	}
+	let encoded = key.encode();
+	let len = encoded.len();
+	let tail = match len {
+		0 => 0u64,
+		1 => encoded[0] as u64,
+		_ => ((encoded[len - 2] as u64) << 8) | encoded[len - 1] as u64,
+	};
+	N::sa(tail) % sharding_count
+}
+
+fn check_sharding_count<T: Trait>(sharding_count: T::ShardNum) -> Result {
+	let count: u64 = sharding_count.as_();
+	if count == 0 || count & (count - 1) != 0 {
+		return Err("Sharding count must be a power of two");
+	}
+	if sharding_count < <Module<T>>::current_sharding_count() {
+		return Err("Sharding count can not decrease");
+	}
+	Ok(())
+}
+
+/// Check a sharding count set by a block against the one the importer observed on the root
+/// chain: the root chain may have scaled out further since, but not less.
+fn check_observed_sharding_count<T: Trait>(sharding_count: T::ShardNum, data: &InherentData) -> Result {
+	check_sharding_count::<T>(sharding_count)?;
+	match extract_inherent_data::<T::ShardNum>(data) {
+		Ok(Some(observed)) if sharding_count <= observed => Ok(()),
+		Ok(Some(_)) => Err("Sharding count greater than observed on the root chain"),
+		Ok(None) => Err("Sharding count not observed on the root chain"),
+		Err(_) => Err("Invalid sharding count inherent data"),
+	}
+}
+
+/// Value of `key` in the state with the given root, read from the trie nodes of `proof`.
+fn read_proof_value(root: &[u8], proof: Vec<Vec<u8>>, key: &[u8]) -> rstd::result::Result<Option<Vec<u8>>, &'static str> {
+	let root = H256::decode(&mut &root[..]).ok_or("Invalid state root")?;
+	let mut db = MemoryDB::<Blake2Hasher>::default();
+	for node in proof {
+		db.insert(&node);
+	}
+	read_trie_value::<Blake2Hasher, _>(&db, &root, key).map_err(|_| "Invalid state proof")
+}
+
+fn extract_inherent_data<N: Decode>(data: &InherentData) -> rstd::result::Result<Option<N>, RuntimeString> {
+	data.get_data::<N>(&INHERENT_IDENTIFIER)
+}
+
+/// Provides the root chain sharding count observed by the embedded root chain client.
+#[cfg(feature = "std")]
+pub struct InherentDataProvider<N> {
+	sharding_count: Arc<RwLock<Option<N>>>,
+}
+
+#[cfg(feature = "std")]
+impl<N> InherentDataProvider<N> {
+	pub fn new(sharding_count: Arc<RwLock<Option<N>>>) -> Self {
+		Self { sharding_count }
+	}
+}
+
+#[cfg(feature = "std")]
+impl<N: Encode + Copy + Send + Sync> ProvideInherentData for InherentDataProvider<N> {
+	fn inherent_identifier(&self) -> &'static InherentIdentifier {
+		&INHERENT_IDENTIFIER
+	}
+
+	fn provide_inherent_data(&self, inherent_data: &mut InherentData) -> rstd::result::Result<(), RuntimeString> {
+		let sharding_count = *self.sharding_count.read().map_err(|_| RuntimeString::from("Sharding count lock poisoned"))?;
+		match sharding_count {
+			Some(sharding_count) => inherent_data.put_data(INHERENT_IDENTIFIER, &sharding_count),
+			None => Ok(()),
+		}
+	}
+
+	fn error_to_string(&self, error: &[u8]) -> Option<String> {
+		RuntimeString::decode(&mut &error[..]).map(Into::into)
+	}
+}
+
+/// Provides the calls posted for the branch on the root chain.
+#[cfg(feature = "std")]
+pub struct ForcedCallsDataProvider {
+	forced_calls: Arc<RwLock<Vec<ForcedCall>>>,
+}
+
+#[cfg(feature = "std")]
+impl ForcedCallsDataProvider {
+	pub fn new(forced_calls: Arc<RwLock<Vec<ForcedCall>>>) -> Self {
+		Self { forced_calls }
+	}
+}
+
+#[cfg(feature = "std")]
+impl ProvideInherentData for ForcedCallsDataProvider {
+	fn inherent_identifier(&self) -> &'static InherentIdentifier {
+		&FORCED_CALLS_INHERENT_IDENTIFIER
+	}
+
+	fn provide_inherent_data(&self, inherent_data: &mut InherentData) -> rstd::result::Result<(), RuntimeString> {
+		let forced_calls = self.forced_calls.read().map_err(|_| RuntimeString::from("Forced calls lock poisoned"))?;
+		inherent_data.put_data(FORCED_CALLS_INHERENT_IDENTIFIER, &*forced_calls)
+	}
+
+	fn error_to_string(&self, error: &[u8]) -> Option<String> {
+		RuntimeString::decode(&mut &error[..]).map(Into::into)
+	}
+}
+
+impl<T: Trait> ProvideInherent for Module<T> {
+	type Call = Call<T>;
+	type Error = MakeFatalError<RuntimeString>;
+	const INHERENT_IDENTIFIER: InherentIdentifier = INHERENT_IDENTIFIER;
+
+	fn create_inherent(data: &InherentData) -> Option<Self::Call> {
+		let sharding_count = extract_inherent_data::<T::ShardNum>(data)
+			.expect("Gets and decodes sharding count inherent data")
+			.filter(|sharding_count| {
+				*sharding_count != Self::current_sharding_count() && check_sharding_count::<T>(*sharding_count).is_ok()
+			});
+
+		let next = Self::next_forced_index();
+		let forced_calls = Self::observed_forced_calls(data).into_iter()
+			.enumerate()
+			.take_while(|(offset, forced)| forced.index == next + *offset as u64)
+			.map(|(_, forced)| forced)
+			.take(MAX_FORCED_CALLS)
+			.collect();
+
+		Some(Call::import_root(sharding_count, forced_calls))
+	}
+
+	fn check_inherent(call: &Self::Call, data: &InherentData) -> rstd::result::Result<(), Self::Error> {
+		match call {
+			Call::import_root(sharding_count, forced_calls) => {
+				if let Some(sharding_count) = sharding_count {
+					check_observed_sharding_count::<T>(*sharding_count, data).map_err(|e| RuntimeString::from(e))?;
+				}
+				Self::check_forced_calls(data, forced_calls).map_err(|e| RuntimeString::from(e).into())
+			},
+			_ => Ok(()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::collections::HashMap;
+	use std::iter;
+	use runtime_io::with_externalities;
+	use support::{impl_outer_origin, impl_outer_dispatch, assert_ok, assert_noop};
+	use state_machine::{Backend as StateBackend, InMemory, prove_read};
+	use runtime_primitives::{
+		BuildStorage,
+		traits::{BlakeTwo256, IdentityLookup, Lazy, OnInitialize},
+		testing::{Digest, DigestItem, Header, UintAuthorityId}
+	};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	impl_outer_dispatch! {
+		pub enum Call for Test where origin: Origin {
+			balances::Balances,
+		}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+	impl balances::Trait for Test {
+		type Balance = u64;
+		type OnFreeBalanceZero = ();
+		type OnNewAccount = ();
+		type Event = ();
+		type TransactionPayment = ();
+		type TransferPayment = ();
+		type DustRemoval = ();
+	}
+
+	/// Signature of `msg` by the session key, valid if it carries both.
+	#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+	pub struct TestSignature(u64, Vec<u8>);
+	impl Verify for TestSignature {
+		type Signer = UintAuthorityId;
+		fn verify<L: Lazy<[u8]>>(&self, mut msg: L, signer: &UintAuthorityId) -> bool {
+			signer.0 == self.0 && msg.get() == &self.1[..]
+		}
+	}
+
+	/// Signature of `msg` by the account, valid if it carries both.
+	#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+	pub struct TestAccountSignature(u64, Vec<u8>);
+	impl Verify for TestAccountSignature {
+		type Signer = u64;
+		fn verify<L: Lazy<[u8]>>(&self, mut msg: L, signer: &u64) -> bool {
+			*signer == self.0 && msg.get() == &self.1[..]
+		}
+	}
+
+	impl Trait for Test {
+		type SessionKey = UintAuthorityId;
+		type Signature = TestSignature;
+		type AccountSignature = TestAccountSignature;
+		type Call = Call;
+		type ShardNum = u16;
+		type Event = ();
+	}
+	type System = system::Module<Test>;
+	type Balances = balances::Module<Test>;
+	type RootPort = Module<Test>;
+
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		t.extend(balances::GenesisConfig::<Test> {
+			balances: vec![(1, 1000)],
+			existential_deposit: 0,
+			transaction_base_fee: 0,
+			transaction_byte_fee: 0,
+			transfer_fee: 0,
+			creation_fee: 0,
+			vesting: vec![],
+		}.build_storage().unwrap().0);
+		t.extend(GenesisConfig::<Test> {
+			genesis_owner_session_key: UintAuthorityId(5),
+			genesis_sharding_count: 4,
+			checkpoint_interval: 10,
+			dispute_window: 5,
+			forced_inclusion_period: 3,
+		}.build_storage().unwrap().0);
+		t.into()
+	}
+
+	fn transfer(value: u64) -> Call {
+		Call::Balances(balances::Call::transfer(2, value))
+	}
+
+	/// Call with the index posted by account 1 with the nonce, anchored at the branch block.
+	fn forced(index: u64, nonce: u64, call: Call, anchor: u64) -> ForcedCall {
+		let call = call.encode();
+		let payload = (System::block_hash(0), nonce, &call).encode();
+		ForcedCall { index, sender: 1u64.encode(), nonce, signature: TestAccountSignature(1, payload).encode(), call, anchor }
+	}
+
+	fn inherent_data(sharding_count: Option<u16>, seen: &[ForcedCall]) -> InherentData {
+		let mut data = InherentData::new();
+		if let Some(sharding_count) = sharding_count {
+			data.put_data(INHERENT_IDENTIFIER, &sharding_count).unwrap();
+		}
+		data.put_data(FORCED_CALLS_INHERENT_IDENTIFIER, &seen.to_vec()).unwrap();
+		data
+	}
+
+	fn import_root(sharding_count: Option<u16>, forced_calls: Vec<ForcedCall>) -> super::Call<Test> {
+		super::Call::import_root(sharding_count, forced_calls)
+	}
+
+	/// Checkpoint of the block signed by the owner.
+	fn signed_checkpoint(number: u64, hash: H256, state_root: H256) -> SignedMetaTx {
+		signed_checkpoint_with_index(number, hash, state_root, 0)
+	}
+
+	fn signed_checkpoint_with_index(number: u64, hash: H256, state_root: H256, next_forced_index: u64) -> SignedMetaTx {
+		let meta_tx = MetaTx::Checkpoint {
+			branch: System::block_hash(0).as_ref().to_vec(),
+			checkpoint: Checkpoint { number, hash: hash.as_ref().to_vec(), state_root: state_root.as_ref().to_vec(), next_forced_index },
+		};
+		SignedMetaTx { signer: UintAuthorityId(5).encode(), signature: TestSignature(5, meta_tx.encode()).encode(), meta_tx }
+	}
+
+	/// Header of the canonical block with the number and state root.
+	fn canonical(number: u64, state_root: H256) -> Header {
+		let header = Header::new(number, Default::default(), state_root, Default::default(), Default::default());
+		<system::BlockHash<Test>>::insert(number, header.hash());
+		header
+	}
+
+	/// Root of a state with one value under `key`, and the proof of the value.
+	fn proven_state(key: &[u8], value: &[u8]) -> (H256, Vec<Vec<u8>>) {
+		let backend = InMemory::<Blake2Hasher>::from(iter::once((key.to_vec(), value.to_vec())).collect::<HashMap<_, _>>());
+		let root = backend.storage_root(iter::empty::<(Vec<u8>, Option<Vec<u8>>)>()).0;
+		let (_, proof) = prove_read(backend, key).unwrap();
+		(root, proof)
+	}
+
+	#[test]
+	fn forced_calls_are_executed_in_index_order() {
+		with_externalities(&mut new_test_ext(), || {
+			System::set_block_number(1);
+			let calls = vec![forced(1, 1, transfer(20), 0), forced(0, 0, transfer(10), 0), forced(3, 2, transfer(40), 0)];
+			assert_ok!(RootPort::import_root(Origin::INHERENT, None, calls));
+
+			assert_eq!(Balances::free_balance(&2), 30);
+			assert_eq!(System::account_nonce(&1), 2);
+			assert_eq!(RootPort::next_forced_index(), 2);
+			assert_noop!(RootPort::import_root(Origin::INHERENT, None, vec![]), "Root can only be imported once in a block");
+		});
+	}
+
+	#[test]
+	fn included_forced_calls_must_be_the_observed_ones() {
+		with_externalities(&mut new_test_ext(), || {
+			System::set_block_number(1);
+			let seen = vec![forced(1, 1, transfer(20), 0), forced(0, 0, transfer(10), 0)];
+			let data = inherent_data(None, &seen);
+			let in_order = vec![seen[1].clone(), seen[0].clone()];
+
+			assert_eq!(RootPort::create_inherent(&data), Some(import_root(None, in_order.clone())));
+			assert!(RootPort::check_inherent(&import_root(None, in_order.clone()), &data).is_ok());
+			assert!(RootPort::check_inherent(&import_root(None, vec![seen[0].clone()]), &data).is_err());
+
+			let mut junk = in_order[0].clone();
+			junk.signature = vec![0];
+			assert!(RootPort::check_inherent(&import_root(None, vec![junk]), &data).is_err());
+			assert!(RootPort::check_inherent(&import_root(None, vec![forced(2, 2, transfer(1), 0)]), &data).is_err());
+		});
+	}
+
+	#[test]
+	fn forced_calls_are_bounded_and_due_within_the_inclusion_period() {
+		with_externalities(&mut new_test_ext(), || {
+			System::set_block_number(1);
+			let seen = (0..MAX_FORCED_CALLS as u64 + 1).map(|i| forced(i, i, transfer(1), 0)).collect::<Vec<_>>();
+			let data = inherent_data(None, &seen);
+
+			assert_eq!(RootPort::create_inherent(&data), Some(import_root(None, seen[..MAX_FORCED_CALLS].to_vec())));
+			assert!(RootPort::check_inherent(&import_root(None, seen.clone()), &data).is_err());
+			assert!(RootPort::check_inherent(&import_root(None, vec![]), &data).is_ok());
+
+			System::set_block_number(2);
+			assert!(RootPort::check_inherent(&import_root(None, vec![]), &data).is_err());
+			assert!(RootPort::check_inherent(&import_root(None, seen[..1].to_vec()), &data).is_err());
+			assert!(RootPort::check_inherent(&import_root(None, seen[..MAX_FORCED_CALLS].to_vec()), &data).is_ok());
+		});
+	}
+
+	#[test]
+	fn sharding_count_follows_the_observed_root_chain() {
+		with_externalities(&mut new_test_ext(), || {
+			let data = inherent_data(Some(8), &[]);
+			assert_eq!(RootPort::create_inherent(&data), Some(import_root(Some(8), vec![])));
+			assert!(RootPort::check_inherent(&import_root(Some(8), vec![]), &data).is_ok());
+			assert!(RootPort::check_inherent(&import_root(Some(16), vec![]), &data).is_err());
+			assert!(RootPort::check_inherent(&import_root(Some(6), vec![]), &data).is_err());
+
+			assert_ok!(RootPort::import_root(Origin::INHERENT, Some(8), vec![]));
+			assert_eq!(RootPort::current_sharding_count(), 8);
+			assert_eq!(RootPort::root_shard_num(), shard_num_for(&UintAuthorityId(5), 8u16));
+			assert_eq!(RootPort::create_inherent(&data), Some(import_root(None, vec![])));
+			assert!(RootPort::check_inherent(&import_root(Some(4), vec![]), &inherent_data(Some(4), &[])).is_err());
+		});
+	}
+
+	#[test]
+	fn fraudulent_checkpoints_are_challenged_within_the_dispute_window() {
+		with_externalities(&mut new_test_ext(), || {
+			let header = canonical(3, H256::repeat_byte(1));
+			let honest = signed_checkpoint(3, header.hash(), H256::repeat_byte(1));
+			let forked = signed_checkpoint(3, H256::repeat_byte(7), H256::repeat_byte(1));
+			let state_root = FraudProof::StateRoot { header: header.encode() };
+
+			System::set_block_number(10);
+			assert_noop!(RootPort::challenge_checkpoint(Origin::signed(9), forked.clone(), FraudProof::ForkedBlock), "Dispute window is over");
+
+			System::set_block_number(5);
+			assert_noop!(RootPort::challenge_checkpoint(Origin::signed(9), honest.clone(), FraudProof::ForkedBlock), "Checkpoint block is canonical");
+			assert_noop!(RootPort::challenge_checkpoint(Origin::signed(9), honest, state_root.clone()), "Checkpoint state root is canonical");
+
+			let mut forged = signed_checkpoint(3, header.hash(), H256::repeat_byte(2));
+			forged.signature = TestSignature(6, forged.meta_tx.encode()).encode();
+			assert_noop!(RootPort::challenge_checkpoint(Origin::signed(9), forged, state_root.clone()), "Invalid owner signature");
+
+			let lying = signed_checkpoint(3, header.hash(), H256::repeat_byte(2));
+			assert_ok!(RootPort::challenge_checkpoint(Origin::signed(9), lying, state_root));
+			assert!(RootPort::halted());
+			assert_eq!(RootPort::fraud(3), Some(9));
+			assert_noop!(RootPort::challenge_checkpoint(Origin::signed(9), forked, FraudProof::ForkedBlock), "Checkpoint fraud already proven");
+		});
+	}
+
+	#[test]
+	fn state_transition_fraud_is_proven_by_a_differing_value() {
+		with_externalities(&mut new_test_ext(), || {
+			let key = b"balance".to_vec();
+			let (root, proof) = proven_state(&key, b"1");
+			let (claimed_root, claimed_proof) = proven_state(&key, b"2");
+			let header = canonical(3, root);
+			System::set_block_number(5);
+
+			let honest = signed_checkpoint(3, header.hash(), root);
+			let agreeing = FraudProof::StateTransition { header: header.encode(), key: key.clone(), proof: proof.clone(), claimed_proof: proof.clone() };
+			assert_noop!(RootPort::challenge_checkpoint(Origin::signed(9), honest, agreeing), "Checkpoint state agrees with the branch state");
+
+			let lying = signed_checkpoint(3, header.hash(), claimed_root);
+			let unproven = FraudProof::StateTransition { header: header.encode(), key: key.clone(), proof: proof.clone(), claimed_proof: proof.clone() };
+			assert_noop!(RootPort::challenge_checkpoint(Origin::signed(9), lying.clone(), unproven), "Invalid state proof");
+
+			let proven = FraudProof::StateTransition { header: header.encode(), key, proof, claimed_proof };
+			assert_ok!(RootPort::challenge_checkpoint(Origin::signed(9), lying, proven));
+			assert!(RootPort::halted());
+		});
+	}
+
+	#[test]
+	fn misreported_forced_call_index_is_fraud() {
+		with_externalities(&mut new_test_ext(), || {
+			let (root, proof) = proven_state(&runtime_io::twox_128(NEXT_FORCED_INDEX_KEY), &3u64.encode());
+			let header = canonical(3, root);
+			System::set_block_number(5);
+			let forced_index = FraudProof::ForcedIndex { header: header.encode(), proof };
+
+			let honest = signed_checkpoint_with_index(3, header.hash(), root, 3);
+			assert_noop!(
+				RootPort::challenge_checkpoint(Origin::signed(9), honest, forced_index.clone()),
+				"Checkpoint forced call index is canonical"
+			);
+
+			let lying = signed_checkpoint_with_index(3, header.hash(), root, 7);
+			assert_ok!(RootPort::challenge_checkpoint(Origin::signed(9), lying, forced_index));
+			assert!(RootPort::halted());
+		});
+	}
+
+	#[test]
+	fn meta_transactions_are_kept_while_halted() {
+		with_externalities(&mut new_test_ext(), || {
+			RootPort::queue_token_transfer(b"YEE".to_vec(), vec![3], 10);
+			RootPort::on_initialize(2);
+			assert!(RootPort::pending_meta_txs().is_empty());
+
+			<Halted<Test>>::put(true);
+			RootPort::queue_token_transfer(b"YEE".to_vec(), vec![3], 10);
+			RootPort::on_initialize(3);
+			RootPort::queue_token_transfer(b"YEE".to_vec(), vec![3], 20);
+			assert_eq!(RootPort::pending_meta_txs().len(), 2);
+
+			assert_ok!(RootPort::resume(Origin::ROOT));
+			RootPort::on_initialize(4);
+			assert!(RootPort::pending_meta_txs().is_empty());
+		});
+	}
+}
@@ -0,0 +1,902 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Staking for Aura authorities.
+//!
+//! Accounts bond (reserve) balance and declare a session key to validate with. At the end of
+//! every era the best bonded candidates become the consensus authorities, and the era reward
+//! is shared among validators by the number of blocks they authored.
+//!
+//! Bonds are slashed for equivocation, proven by an extrinsic carrying two Aura seals of the
+//! same slot, and for being offline more than the grace number of times in an era: for every
+//! missed Aura slot, and for every block whose author reports an authority that authored no block
+//! for two rounds of slots through the offline report inherent.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+use runtime_io::with_storage;
+use rstd::prelude::*;
+use parity_codec::{Encode, Decode};
+use support::{
+	Parameter, decl_storage, decl_module, decl_event, StorageValue, StorageMap,
+	dispatch::Result, ensure,
+	traits::{Currency, ReservableCurrency, OnUnbalanced},
+};
+use runtime_primitives::Permill;
+use runtime_primitives::traits::{As, Zero, Verify};
+#[cfg(feature = "std")]
+use runtime_primitives::RuntimeString;
+#[cfg(feature = "std")]
+use inherents::{InherentData, InherentIdentifier, ProvideInherentData};
+use system::{ensure_signed, ensure_root};
+use yee_fee::FindAuthor;
+
+/// Index of an era.
+pub type EraIndex = u32;
+
+type BalanceOf<T> = <<T as Trait>::Currency as Currency<<T as system::Trait>::AccountId>>::Balance;
+type NegativeImbalanceOf<T> = <<T as Trait>::Currency as Currency<<T as system::Trait>::AccountId>>::NegativeImbalance;
+
+/// Bonded balance of a staker.
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct StakingLedger<Balance> {
+	/// Balance at stake.
+	pub active: Balance,
+	/// Unbonded balance, and the era it can be withdrawn in.
+	pub unlocking: Vec<(Balance, EraIndex)>,
+}
+
+/// Two Aura seals of the same slot by the same authority, over different blocks.
+///
+/// A seal is the authority signature of the encoded `(slot, pre_hash)`, where `pre_hash`
+/// is the hash of the block header without the seal.
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct EquivocationProof<SessionKey, Signature, Hash> {
+	pub offender: SessionKey,
+	pub slot: u64,
+	pub first: (Hash, Signature),
+	pub second: (Hash, Signature),
+}
+
+/// Notified of the authorities elected for a new era, at the start of the next block.
+pub trait OnNewAuthorities<SessionKey> {
+	/// Fails if the change can not be made yet, to be notified again in the next block.
+	fn on_new_authorities(authorities: &[SessionKey]) -> Result;
+}
+
+impl<SessionKey> OnNewAuthorities<SessionKey> for () {
+	fn on_new_authorities(_: &[SessionKey]) -> Result {
+		Ok(())
+	}
+}
+
+pub trait Trait: system::Trait + consensus::Trait {
+	/// The currency bonds are reserved in.
+	type Currency: ReservableCurrency<Self::AccountId>;
+
+	/// Signature of Aura seals.
+	type Signature: Parameter + Verify<Signer=<Self as consensus::Trait>::SessionKey>;
+
+	/// How the author of the current block is found.
+	type FindAuthor: FindAuthor<<Self as consensus::Trait>::SessionKey>;
+
+	/// Where slashed bonds go.
+	type OnSlash: OnUnbalanced<NegativeImbalanceOf<Self>>;
+
+	/// Notified when elected authorities change, e.g. to keep a finality gadget in sync.
+	type OnNewAuthorities: OnNewAuthorities<<Self as consensus::Trait>::SessionKey>;
+
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as Staking {
+		/// Number of authorities elected every era.
+		pub ValidatorCount get(validator_count) config(): u32;
+
+		/// Bond needed to validate.
+		pub MinimumBond get(minimum_bond) config(): BalanceOf<T>;
+
+		/// Number of blocks in an era.
+		pub EraLength get(era_length) config(): T::BlockNumber;
+
+		/// Number of eras unbonded balance stays reserved.
+		pub BondingDuration get(bonding_duration) config(): EraIndex;
+
+		/// Reward shared among the validators of an era.
+		pub EraReward get(era_reward) config(): BalanceOf<T>;
+
+		/// Part of the bond slashed for equivocation.
+		pub EquivocationSlash get(equivocation_slash) config(): Permill;
+
+		/// Part of the bond slashed for being offline.
+		pub OfflineSlash get(offline_slash) config(): Permill;
+
+		/// Number of slots a validator can miss in an era before being slashed.
+		pub OfflineSlashGrace get(offline_slash_grace) config(): u32;
+
+		/// Current era.
+		pub CurrentEra get(current_era): EraIndex;
+
+		/// Bonded balance of a staker.
+		pub Ledger get(ledger): map T::AccountId => Option<StakingLedger<BalanceOf<T>>>;
+
+		/// Staker validating with a session key, or that did until the key was retired.
+		pub Validators get(validator_stash): map <T as consensus::Trait>::SessionKey => Option<T::AccountId>;
+
+		/// Session key a staker validates with.
+		pub ValidatorKey get(validator_key): map T::AccountId => Option<<T as consensus::Trait>::SessionKey>;
+
+		/// Session keys standing for election.
+		pub Candidates get(candidates): Vec<<T as consensus::Trait>::SessionKey>;
+
+		/// Session keys no longer standing for election whose staker is still answerable for
+		/// their misbehaviour: until the given era, set once they left the authorities.
+		pub RetiringKeys get(retiring_keys): Vec<(<T as consensus::Trait>::SessionKey, Option<EraIndex>)>;
+
+		/// Blocks authored in the current era, by session key.
+		pub EraPoints get(era_points): Vec<(<T as consensus::Trait>::SessionKey, u32)>;
+
+		/// Authorities elected but not yet accepted by `OnNewAuthorities`.
+		pub PendingAuthorities get(pending_authorities): Option<Vec<<T as consensus::Trait>::SessionKey>>;
+
+		/// Whether the authorities were ever notified to `OnNewAuthorities`, which chains
+		/// upgraded to staking were not at genesis.
+		pub AuthoritiesNoted get(authorities_noted): bool;
+
+		/// Last block authored, or the start of the era it was elected in, by session key.
+		pub LastAuthored get(last_authored): map <T as consensus::Trait>::SessionKey => T::BlockNumber;
+
+		/// Slots missed in the current era, by session key.
+		pub OfflineCount get(offline_count): map <T as consensus::Trait>::SessionKey => u32;
+
+		/// Equivocations already punished, by offender and slot.
+		pub Equivocations get(equivocation_reported): map (<T as consensus::Trait>::SessionKey, u64) => bool;
+	}
+	add_extra_genesis {
+		/// Stakers bonding part of their genesis balance, and the session key they validate with.
+		config(stakers): Vec<(T::AccountId, BalanceOf<T>, <T as consensus::Trait>::SessionKey)>;
+
+		build(|storage: &mut runtime_primitives::StorageOverlay, _: &mut runtime_primitives::ChildrenStorageOverlay, config: &GenesisConfig<T>| {
+			with_storage(storage, || {
+				for (stash, value, key) in &config.stakers {
+					let origin = || T::Origin::from(system::RawOrigin::Signed(stash.clone()));
+					<Module<T>>::bond(origin(), *value).expect("Genesis stakers have the balance they bond");
+					<Module<T>>::validate(origin(), key.clone()).expect("Genesis stakers bond the minimum with unique keys");
+				}
+			});
+		});
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Bond more of the sender's balance.
+		fn bond(origin, #[compact] value: BalanceOf<T>) -> Result {
+			let who = ensure_signed(origin)?;
+			ensure!(!value.is_zero(), "Can not bond zero");
+
+			T::Currency::reserve(&who, value)?;
+			let mut ledger = Self::ledger(&who).unwrap_or_default();
+			ledger.active = ledger.active + value;
+			<Ledger<T>>::insert(&who, ledger);
+
+			Self::deposit_event(RawEvent::Bonded(who, value));
+			Ok(())
+		}
+
+		/// Unbond part of the sender's bond, withdrawable after the bonding duration.
+		fn unbond(origin, #[compact] value: BalanceOf<T>) -> Result {
+			let who = ensure_signed(origin)?;
+			let mut ledger = Self::ledger(&who).ok_or("Not bonded")?;
+
+			let value = rstd::cmp::min(value, ledger.active);
+			ensure!(!value.is_zero(), "Nothing to unbond");
+			ledger.active = ledger.active - value;
+			ledger.unlocking.push((value, Self::current_era() + Self::bonding_duration()));
+			let below_minimum = ledger.active < Self::minimum_bond();
+			<Ledger<T>>::insert(&who, ledger);
+
+			if below_minimum {
+				Self::do_chill(&who);
+			}
+
+			Self::deposit_event(RawEvent::Unbonded(who, value));
+			Ok(())
+		}
+
+		/// Withdraw unbonded balance whose bonding duration passed.
+		fn withdraw_unbonded(origin) -> Result {
+			let who = ensure_signed(origin)?;
+			let mut ledger = Self::ledger(&who).ok_or("Not bonded")?;
+			ensure!(!Self::has_retiring_key(&who), "Retired session key still answerable for misbehaviour");
+
+			let era = Self::current_era();
+			let mut value = <BalanceOf<T>>::zero();
+			ledger.unlocking.retain(|(amount, until)| {
+				if *until <= era {
+					value = value + *amount;
+					false
+				} else {
+					true
+				}
+			});
+			ensure!(!value.is_zero(), "Nothing to withdraw");
+
+			T::Currency::unreserve(&who, value);
+			if ledger.active.is_zero() && ledger.unlocking.is_empty() {
+				<Ledger<T>>::remove(&who);
+			} else {
+				<Ledger<T>>::insert(&who, ledger);
+			}
+
+			Self::deposit_event(RawEvent::Withdrawn(who, value));
+			Ok(())
+		}
+
+		/// Stand for election with the given session key.
+		fn validate(origin, key: <T as consensus::Trait>::SessionKey) -> Result {
+			let who = ensure_signed(origin)?;
+			let ledger = Self::ledger(&who).ok_or("Not bonded")?;
+			ensure!(ledger.active >= Self::minimum_bond(), "Bond below the minimum");
+			ensure!(Self::validator_stash(&key).map_or(true, |stash| stash == who), "Session key already used");
+
+			Self::do_chill(&who);
+			<RetiringKeys<T>>::mutate(|keys| keys.retain(|(k, _)| k != &key));
+			<Validators<T>>::insert(&key, &who);
+			<ValidatorKey<T>>::insert(&who, &key);
+			<Candidates<T>>::mutate(|candidates| candidates.push(key.clone()));
+
+			Self::deposit_event(RawEvent::Validating(who, key));
+			Ok(())
+		}
+
+		/// Stop standing for election.
+		fn chill(origin) -> Result {
+			let who = ensure_signed(origin)?;
+			ensure!(Self::validator_key(&who).is_some(), "Not validating");
+
+			Self::do_chill(&who);
+			Ok(())
+		}
+
+		/// Punish an authority for sealing two blocks in one slot.
+		fn report_equivocation(origin, proof: EquivocationProof<<T as consensus::Trait>::SessionKey, T::Signature, T::Hash>) -> Result {
+			ensure_signed(origin)?;
+			let EquivocationProof { offender, slot, first, second } = proof;
+
+			ensure!(first.0 != second.0, "Seals are of the same block");
+			ensure!(!Self::equivocation_reported((offender.clone(), slot)), "Equivocation already reported");
+			ensure!(Self::verify_seal(&offender, slot, &first), "Invalid first seal");
+			ensure!(Self::verify_seal(&offender, slot, &second), "Invalid second seal");
+			let stash = Self::validator_stash(&offender).ok_or("Offender is not a validator")?;
+
+			<Equivocations<T>>::insert((offender.clone(), slot), true);
+			let slashed = Self::slash(&stash, Self::equivocation_slash());
+			Self::do_chill(&stash);
+
+			Self::deposit_event(RawEvent::Equivocation(offender, slot, slashed));
+			Ok(())
+		}
+
+		/// Set the number of authorities elected every era.
+		fn set_validator_count(origin, #[compact] count: u32) -> Result {
+			ensure_root(origin)?;
+			ensure!(count > 0, "Validator count can not be zero");
+
+			<ValidatorCount<T>>::put(count);
+			Ok(())
+		}
+
+		/// Set the reward shared among the validators of an era.
+		fn set_era_reward(origin, #[compact] reward: BalanceOf<T>) -> Result {
+			ensure_root(origin)?;
+
+			<EraReward<T>>::put(reward);
+			Ok(())
+		}
+
+		fn on_initialize(_n: T::BlockNumber) {
+			if !Self::authorities_noted() {
+				<AuthoritiesNoted<T>>::put(true);
+				<PendingAuthorities<T>>::put(<consensus::Module<T>>::authorities());
+			}
+
+			if let Some(pending) = Self::pending_authorities() {
+				if T::OnNewAuthorities::on_new_authorities(&pending).is_ok() {
+					<PendingAuthorities<T>>::kill();
+				}
+			}
+		}
+
+		fn on_finalize(n: T::BlockNumber) {
+			if let Some(author) = T::FindAuthor::find_author() {
+				Self::note_authored(author, n);
+			}
+
+			let era_length = Self::era_length();
+			if !era_length.is_zero() && (n % era_length).is_zero() {
+				Self::new_era(n);
+			}
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where
+		Balance = BalanceOf<T>,
+		<T as system::Trait>::AccountId,
+		SessionKey = <T as consensus::Trait>::SessionKey,
+	{
+		/// Staker bonded more balance.
+		Bonded(AccountId, Balance),
+		/// Staker unbonded balance.
+		Unbonded(AccountId, Balance),
+		/// Staker withdrew unbonded balance.
+		Withdrawn(AccountId, Balance),
+		/// Staker stands for election with the session key.
+		Validating(AccountId, SessionKey),
+		/// Staker no longer stands for election.
+		Chilled(AccountId),
+		/// Validator rewarded for the blocks it authored.
+		Reward(AccountId, Balance),
+		/// Authority sealed two blocks in the slot, slashed by the amount.
+		Equivocation(SessionKey, u64, Balance),
+		/// Authority missed too many slots, slashed by the amount.
+		Offline(SessionKey, Balance),
+		/// New era started with the number of elected authorities.
+		NewEra(EraIndex, u32),
+	}
+);
+
+impl<T: Trait> Module<T> {
+
+	fn verify_seal(
+		offender: &<T as consensus::Trait>::SessionKey,
+		slot: u64,
+		(pre_hash, signature): &(T::Hash, T::Signature),
+	) -> bool {
+		let payload = (slot, pre_hash).encode();
+		signature.verify(&payload[..], offender)
+	}
+
+	/// Stop standing for election, the session key staying tied to its staker, bond included,
+	/// until `release_retired_keys` frees it.
+	fn do_chill(who: &T::AccountId) {
+		if let Some(key) = <ValidatorKey<T>>::take(who) {
+			<Candidates<T>>::mutate(|candidates| candidates.retain(|k| k != &key));
+			<RetiringKeys<T>>::mutate(|keys| keys.push((key, None)));
+			Self::deposit_event(RawEvent::Chilled(who.clone()));
+		}
+	}
+
+	fn has_retiring_key(who: &T::AccountId) -> bool {
+		Self::retiring_keys().iter().any(|(key, _)| Self::validator_stash(key).as_ref() == Some(who))
+	}
+
+	/// Free the retired session keys that left the authorities at least the bonding duration ago,
+	/// starting the count for those that just left.
+	fn release_retired_keys(era: EraIndex) {
+		let authorities = <consensus::Module<T>>::authorities();
+		let until = era + Self::bonding_duration();
+		let keys = Self::retiring_keys().into_iter()
+			.filter_map(|(key, release)| match release {
+				Some(release) if release <= era => {
+					<Validators<T>>::remove(&key);
+					None
+				},
+				Some(release) => Some((key, Some(release))),
+				None if authorities.contains(&key) => Some((key, None)),
+				None => Some((key, Some(until))),
+			})
+			.collect::<Vec<_>>();
+		<RetiringKeys<T>>::put(keys);
+	}
+
+	/// Slash part of the bond of `who`, unbonded balance still reserved included, returning the
+	/// slashed amount.
+	fn slash(who: &T::AccountId, fraction: Permill) -> BalanceOf<T> {
+		let mut ledger = match Self::ledger(who) {
+			Some(ledger) => ledger,
+			None => return Zero::zero(),
+		};
+
+		let mut amount = fraction * ledger.active;
+		ledger.active = ledger.active - amount;
+		for (value, _) in ledger.unlocking.iter_mut() {
+			let slashed = fraction * *value;
+			*value = *value - slashed;
+			amount = amount + slashed;
+		}
+		ledger.unlocking.retain(|(value, _)| !value.is_zero());
+
+		let (imbalance, missing) = T::Currency::slash_reserved(who, amount);
+		<Ledger<T>>::insert(who, ledger);
+		T::OnSlash::on_unbalanced(imbalance);
+		amount - missing
+	}
+
+	fn note_authored(author: <T as consensus::Trait>::SessionKey, n: T::BlockNumber) {
+		<LastAuthored<T>>::insert(&author, n);
+		<EraPoints<T>>::mutate(|points| {
+			match points.iter_mut().find(|(key, _)| key == &author) {
+				Some((_, count)) => *count += 1,
+				None => points.push((author, 1)),
+			}
+		});
+	}
+
+	/// Whether the authority authored no block for two rounds of slots.
+	fn is_offline(key: &<T as consensus::Trait>::SessionKey, authority_count: usize) -> bool {
+		let now = <system::Module<T>>::block_number();
+		let rounds = <T::BlockNumber as As<u64>>::sa(2 * authority_count as u64);
+		now > Self::last_authored(key) + rounds
+	}
+
+	/// Indices of the authorities that authored no block for two rounds of slots.
+	pub fn offline_authorities() -> Vec<u32> {
+		let authorities = <consensus::Module<T>>::authorities();
+		authorities.iter().enumerate()
+			.filter(|(_, key)| Self::is_offline(key, authorities.len()))
+			.map(|(index, _)| index as u32)
+			.collect()
+	}
+
+	fn note_offline(key: &<T as consensus::Trait>::SessionKey) {
+		let count = Self::offline_count(key) + 1;
+		if count <= Self::offline_slash_grace() {
+			<OfflineCount<T>>::insert(key, count);
+			return;
+		}
+
+		<OfflineCount<T>>::remove(key);
+		if let Some(stash) = Self::validator_stash(key) {
+			let slashed = Self::slash(&stash, Self::offline_slash());
+			Self::do_chill(&stash);
+			Self::deposit_event(RawEvent::Offline(key.clone(), slashed));
+		}
+	}
+
+	fn new_era(n: T::BlockNumber) {
+		Self::reward_validators();
+		for key in <consensus::Module<T>>::authorities() {
+			<OfflineCount<T>>::remove(&key);
+		}
+
+		let era = Self::current_era() + 1;
+		<CurrentEra<T>>::put(era);
+
+		let elected = Self::elect();
+		for key in &elected {
+			<LastAuthored<T>>::insert(key, n);
+		}
+		if !elected.is_empty() && elected != <consensus::Module<T>>::authorities() {
+			<consensus::Module<T>>::set_authorities(&elected);
+			<PendingAuthorities<T>>::put(&elected);
+		}
+		Self::release_retired_keys(era);
+
+		Self::deposit_event(RawEvent::NewEra(era, elected.len() as u32));
+	}
+
+	fn reward_validators() {
+		let points = <EraPoints<T>>::take();
+		let total: u64 = points.iter().map(|(_, count)| *count as u64).sum();
+		if total == 0 {
+			return;
+		}
+
+		let era_reward = Self::era_reward();
+		for (key, count) in points {
+			if let Some(stash) = Self::validator_stash(&key) {
+				let reward = era_reward * <BalanceOf<T> as As<u64>>::sa(count as u64)
+					/ <BalanceOf<T> as As<u64>>::sa(total);
+				if T::Currency::deposit_into_existing(&stash, reward).is_ok() {
+					Self::deposit_event(RawEvent::Reward(stash, reward));
+				}
+			}
+		}
+	}
+
+	/// Candidates with the biggest bonds, up to the validator count.
+	fn elect() -> Vec<<T as consensus::Trait>::SessionKey> {
+		let mut candidates: Vec<_> = Self::candidates().into_iter()
+			.filter_map(|key| {
+				let stash = Self::validator_stash(&key)?;
+				let ledger = Self::ledger(&stash)?;
+				Some((key, ledger.active))
+			})
+			.collect();
+		candidates.sort_by(|a, b| b.1.cmp(&a.1));
+
+		candidates.into_iter()
+			.take(Self::validator_count() as usize)
+			.map(|(key, _)| key)
+			.collect()
+	}
+}
+
+/// Slashes authorities that missed their Aura slots.
+impl<T: Trait> aura::HandleReport for Module<T> {
+	fn handle_report(report: aura::AuraReport) {
+		let authorities = <consensus::Module<T>>::authorities();
+		report.punish(authorities.len(), |index, _| {
+			if let Some(key) = authorities.get(index) {
+				Self::note_offline(key);
+			}
+		});
+	}
+}
+
+/// Offline report inherent: indices of the authorities the block author found offline.
+///
+/// Reports are checked against the state rather than the view of the importer, which is not
+/// that of the author while syncing: only authorities that authored no block for two rounds of
+/// slots are noted offline.
+pub struct OfflineReport<T>(rstd::marker::PhantomData<T>);
+
+impl<T: Trait> consensus::InherentOfflineReport for OfflineReport<T> {
+	type Inherent = Vec<u32>;
+
+	fn is_empty(inherent: &Self::Inherent) -> bool {
+		inherent.is_empty()
+	}
+
+	fn handle_report(report: Self::Inherent) {
+		let authorities = <consensus::Module<T>>::authorities();
+		for index in report {
+			if let Some(key) = authorities.get(index as usize) {
+				if <Module<T>>::is_offline(key, authorities.len()) {
+					<Module<T>>::note_offline(key);
+				}
+			}
+		}
+	}
+
+	fn check_inherent(contained: &Self::Inherent, _expected: &Self::Inherent) -> rstd::result::Result<(), &'static str> {
+		let authority_count = <consensus::Module<T>>::authorities().len() as u32;
+		ensure!(contained.iter().all(|index| *index < authority_count), "Reported authority out of range");
+		ensure!(contained.windows(2).all(|pair| pair[0] < pair[1]), "Reported authorities not sorted");
+		Ok(())
+	}
+}
+
+/// Provides the authorities found offline at the best block, for the offline report inherent.
+#[cfg(feature = "std")]
+pub struct OfflineReportDataProvider {
+	offline_authorities: Box<dyn Fn() -> Vec<u32> + Send + Sync>,
+}
+
+#[cfg(feature = "std")]
+impl OfflineReportDataProvider {
+	pub fn new<F: Fn() -> Vec<u32> + Send + Sync + 'static>(offline_authorities: F) -> Self {
+		Self { offline_authorities: Box::new(offline_authorities) }
+	}
+}
+
+#[cfg(feature = "std")]
+impl ProvideInherentData for OfflineReportDataProvider {
+	fn inherent_identifier(&self) -> &'static InherentIdentifier {
+		&consensus::INHERENT_IDENTIFIER
+	}
+
+	fn provide_inherent_data(&self, inherent_data: &mut InherentData) -> rstd::result::Result<(), RuntimeString> {
+		inherent_data.put_data(consensus::INHERENT_IDENTIFIER, &(self.offline_authorities)())
+	}
+
+	fn error_to_string(&self, error: &[u8]) -> Option<String> {
+		RuntimeString::decode(&mut &error[..]).map(Into::into)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use runtime_io::with_externalities;
+	use primitives::{H256, Blake2Hasher};
+	use support::{impl_outer_origin, assert_ok, assert_noop};
+	use consensus::InherentOfflineReport;
+	use std::cell::{Cell, RefCell};
+	use runtime_primitives::{
+		BuildStorage,
+		traits::{BlakeTwo256, IdentityLookup, OnInitialize, OnFinalize, Lazy},
+		testing::{Digest, DigestItem, Header, UintAuthorityId}
+	};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+	impl consensus::Trait for Test {
+		type Log = DigestItem;
+		type SessionKey = UintAuthorityId;
+		type InherentOfflineReport = ();
+	}
+	impl balances::Trait for Test {
+		type Balance = u64;
+		type OnFreeBalanceZero = ();
+		type OnNewAccount = ();
+		type Event = ();
+		type TransactionPayment = ();
+		type TransferPayment = ();
+		type DustRemoval = ();
+	}
+
+	/// Signature of `msg` by the authority, valid if it carries both.
+	#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+	pub struct TestSignature(u64, Vec<u8>);
+	impl Verify for TestSignature {
+		type Signer = UintAuthorityId;
+		fn verify<L: Lazy<[u8]>>(&self, mut msg: L, signer: &UintAuthorityId) -> bool {
+			signer.0 == self.0 && msg.get() == &self.1[..]
+		}
+	}
+
+	thread_local! {
+		static NOTIFIED: RefCell<Vec<Vec<UintAuthorityId>>> = RefCell::new(vec![]);
+		static ACCEPTING: Cell<bool> = Cell::new(true);
+	}
+
+	/// Records the authorities it accepts, while accepting.
+	pub struct TestNotifier;
+	impl OnNewAuthorities<UintAuthorityId> for TestNotifier {
+		fn on_new_authorities(authorities: &[UintAuthorityId]) -> Result {
+			ensure!(ACCEPTING.with(|a| a.get()), "Change pending");
+			NOTIFIED.with(|n| n.borrow_mut().push(authorities.to_vec()));
+			Ok(())
+		}
+	}
+
+	pub struct TestAuthor;
+	impl FindAuthor<UintAuthorityId> for TestAuthor {
+		fn find_author() -> Option<UintAuthorityId> {
+			Some(UintAuthorityId(1))
+		}
+	}
+
+	impl Trait for Test {
+		type Currency = Balances;
+		type Signature = TestSignature;
+		type FindAuthor = TestAuthor;
+		type OnSlash = ();
+		type OnNewAuthorities = TestNotifier;
+		type Event = ();
+	}
+	type System = system::Module<Test>;
+	type Balances = balances::Module<Test>;
+	type Consensus = consensus::Module<Test>;
+	type Staking = Module<Test>;
+
+	fn new_test_ext(stakers: Vec<(u64, u64, UintAuthorityId)>) -> runtime_io::TestExternalities<Blake2Hasher> {
+		let (mut t, mut c) = system::GenesisConfig::<Test>::default().build_storage().unwrap();
+		balances::GenesisConfig::<Test> {
+			balances: vec![(10, 1000), (20, 1000)],
+			existential_deposit: 0,
+			transaction_base_fee: 0,
+			transaction_byte_fee: 0,
+			transfer_fee: 0,
+			creation_fee: 0,
+			vesting: vec![],
+		}.assimilate_storage(&mut t, &mut c).unwrap();
+		GenesisConfig::<Test> {
+			validator_count: 2,
+			minimum_bond: 100,
+			era_length: 2,
+			bonding_duration: 1,
+			era_reward: 60,
+			equivocation_slash: Permill::from_percent(10),
+			offline_slash: Permill::from_percent(1),
+			offline_slash_grace: 2,
+			stakers,
+		}.assimilate_storage(&mut t, &mut c).unwrap();
+		t.into()
+	}
+
+	fn seal(key: u64, slot: u64, pre_hash: H256) -> (H256, TestSignature) {
+		(pre_hash, TestSignature(key, (slot, pre_hash).encode()))
+	}
+
+	#[test]
+	fn unbonded_balance_is_withdrawn_after_bonding_duration() {
+		with_externalities(&mut new_test_ext(vec![]), || {
+			assert_ok!(Staking::bond(Origin::signed(10), 500));
+			assert_eq!(Balances::reserved_balance(&10), 500);
+
+			assert_ok!(Staking::unbond(Origin::signed(10), 200));
+			assert_noop!(Staking::withdraw_unbonded(Origin::signed(10)), "Nothing to withdraw");
+
+			Staking::on_finalize(2);
+			assert_ok!(Staking::withdraw_unbonded(Origin::signed(10)));
+			assert_eq!(Balances::reserved_balance(&10), 300);
+			assert_eq!(Balances::free_balance(&10), 700);
+		});
+	}
+
+	#[test]
+	fn best_bonded_candidates_are_elected_and_rewarded() {
+		with_externalities(&mut new_test_ext(vec![]), || {
+			assert_ok!(Staking::bond(Origin::signed(10), 300));
+			assert_ok!(Staking::validate(Origin::signed(10), UintAuthorityId(1)));
+			assert_ok!(Staking::bond(Origin::signed(20), 200));
+			assert_ok!(Staking::validate(Origin::signed(20), UintAuthorityId(2)));
+
+			Staking::on_finalize(1);
+			Staking::on_finalize(2);
+			assert_eq!(Consensus::authorities(), vec![UintAuthorityId(1), UintAuthorityId(2)]);
+			assert_eq!(Balances::free_balance(&10), 700 + 60);
+			assert_eq!(Staking::current_era(), 1);
+		});
+	}
+
+	#[test]
+	fn equivocation_is_slashed_once() {
+		with_externalities(&mut new_test_ext(vec![]), || {
+			assert_ok!(Staking::bond(Origin::signed(10), 500));
+			assert_ok!(Staking::validate(Origin::signed(10), UintAuthorityId(1)));
+
+			let proof = EquivocationProof {
+				offender: UintAuthorityId(1),
+				slot: 7,
+				first: seal(1, 7, H256::repeat_byte(1)),
+				second: seal(1, 7, H256::repeat_byte(2)),
+			};
+			assert_noop!(
+				Staking::report_equivocation(Origin::signed(20), EquivocationProof { second: seal(2, 7, H256::repeat_byte(2)), ..proof.clone() }),
+				"Invalid second seal"
+			);
+			assert_ok!(Staking::report_equivocation(Origin::signed(20), proof.clone()));
+			assert_eq!(Balances::reserved_balance(&10), 450);
+			assert_eq!(Staking::validator_key(&10), None);
+			assert_noop!(Staking::report_equivocation(Origin::signed(20), proof), "Equivocation already reported");
+		});
+	}
+
+	#[test]
+	fn chilled_validator_is_slashed_until_bonding_duration_after_leaving() {
+		with_externalities(&mut new_test_ext(vec![]), || {
+			assert_ok!(Staking::bond(Origin::signed(10), 500));
+			assert_ok!(Staking::validate(Origin::signed(10), UintAuthorityId(1)));
+			Staking::on_finalize(2);
+			assert_eq!(Consensus::authorities(), vec![UintAuthorityId(1)]);
+
+			assert_ok!(Staking::unbond(Origin::signed(10), 500));
+			assert_eq!(Staking::validator_key(&10), None);
+			let proof = EquivocationProof {
+				offender: UintAuthorityId(1),
+				slot: 7,
+				first: seal(1, 7, H256::repeat_byte(1)),
+				second: seal(1, 7, H256::repeat_byte(2)),
+			};
+			assert_ok!(Staking::report_equivocation(Origin::signed(20), proof));
+			assert_eq!(Balances::reserved_balance(&10), 450);
+
+			assert_ok!(Staking::bond(Origin::signed(20), 200));
+			assert_ok!(Staking::validate(Origin::signed(20), UintAuthorityId(2)));
+			Staking::on_finalize(4);
+			assert_eq!(Consensus::authorities(), vec![UintAuthorityId(2)]);
+			assert_eq!(Staking::validator_stash(&UintAuthorityId(1)), Some(10));
+			assert_noop!(
+				Staking::withdraw_unbonded(Origin::signed(10)),
+				"Retired session key still answerable for misbehaviour"
+			);
+
+			Staking::on_finalize(6);
+			assert_eq!(Staking::validator_stash(&UintAuthorityId(1)), None);
+			assert_ok!(Staking::withdraw_unbonded(Origin::signed(10)));
+			assert_eq!(Balances::reserved_balance(&10), 0);
+		});
+	}
+
+	#[test]
+	fn offline_beyond_grace_is_slashed() {
+		with_externalities(&mut new_test_ext(vec![]), || {
+			assert_ok!(Staking::bond(Origin::signed(10), 500));
+			assert_ok!(Staking::validate(Origin::signed(10), UintAuthorityId(1)));
+
+			Staking::note_offline(&UintAuthorityId(1));
+			Staking::note_offline(&UintAuthorityId(1));
+			assert_eq!(Balances::reserved_balance(&10), 500);
+
+			Staking::note_offline(&UintAuthorityId(1));
+			assert_eq!(Balances::reserved_balance(&10), 495);
+			assert_eq!(Staking::candidates(), vec![]);
+		});
+	}
+
+	#[test]
+	fn unbonding_balance_is_slashed() {
+		with_externalities(&mut new_test_ext(vec![]), || {
+			assert_ok!(Staking::bond(Origin::signed(10), 500));
+			assert_ok!(Staking::validate(Origin::signed(10), UintAuthorityId(1)));
+			assert_ok!(Staking::unbond(Origin::signed(10), 300));
+
+			assert_eq!(Staking::slash(&10, Permill::from_percent(10)), 50);
+			assert_eq!(Balances::reserved_balance(&10), 450);
+			assert_eq!(Staking::ledger(&10), Some(StakingLedger { active: 180, unlocking: vec![(270, 1)] }));
+		});
+	}
+
+	#[test]
+	fn genesis_stakers_are_bonded_and_elected() {
+		with_externalities(&mut new_test_ext(vec![(10, 300, UintAuthorityId(1)), (20, 200, UintAuthorityId(2))]), || {
+			assert_eq!(Balances::reserved_balance(&10), 300);
+			assert_eq!(Staking::validator_stash(&UintAuthorityId(2)), Some(20));
+
+			Staking::on_finalize(1);
+			Staking::on_finalize(2);
+			assert_eq!(Consensus::authorities(), vec![UintAuthorityId(1), UintAuthorityId(2)]);
+		});
+	}
+
+	#[test]
+	fn only_authorities_offline_in_the_state_are_reported() {
+		with_externalities(&mut new_test_ext(vec![(10, 300, UintAuthorityId(1)), (20, 200, UintAuthorityId(2))]), || {
+			Staking::on_finalize(2);
+			System::set_block_number(3);
+			Staking::note_authored(UintAuthorityId(1), 3);
+
+			System::set_block_number(7);
+			assert_eq!(Staking::offline_authorities(), vec![1]);
+			assert_ok!(<OfflineReport<Test> as InherentOfflineReport>::check_inherent(&vec![0, 1], &vec![]));
+			assert!(<OfflineReport<Test> as InherentOfflineReport>::check_inherent(&vec![2], &vec![]).is_err());
+
+			<OfflineReport<Test> as InherentOfflineReport>::handle_report(vec![0, 1]);
+			assert_eq!(Staking::offline_count(&UintAuthorityId(1)), 0);
+			assert_eq!(Staking::offline_count(&UintAuthorityId(2)), 1);
+		});
+	}
+
+	#[test]
+	fn refused_authorities_are_notified_until_accepted() {
+		with_externalities(&mut new_test_ext(vec![(10, 300, UintAuthorityId(1))]), || {
+			Staking::on_initialize(1);
+			assert_eq!(NOTIFIED.with(|n| n.borrow_mut().split_off(0)), vec![vec![]]);
+
+			Staking::on_finalize(2);
+			ACCEPTING.with(|a| a.set(false));
+			Staking::on_initialize(3);
+			assert_eq!(Staking::pending_authorities(), Some(vec![UintAuthorityId(1)]));
+
+			ACCEPTING.with(|a| a.set(true));
+			Staking::on_initialize(4);
+			assert_eq!(NOTIFIED.with(|n| n.borrow_mut().split_off(0)), vec![vec![UintAuthorityId(1)]]);
+			assert_eq!(Staking::pending_authorities(), None);
+		});
+	}
+}
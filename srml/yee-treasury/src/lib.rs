@@ -0,0 +1,301 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Treasury of the branch, funded by dust, fees and block rewards and spent through approved proposals.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use parity_codec::{Encode, Decode};
+use support::{
+	decl_storage, decl_module, decl_event, StorageValue, StorageMap,
+	dispatch::Result,
+	traits::{Currency, ReservableCurrency, OnUnbalanced, Imbalance},
+};
+use runtime_primitives::traits::{StaticLookup, Zero};
+use system::{ensure_signed, ensure_root};
+
+/// Seed of the treasury pot account.
+const POT_SEED: &[u8; 32] = b"yee/treasury/pot................";
+
+type BalanceOf<T> = <<T as Trait>::Currency as Currency<<T as system::Trait>::AccountId>>::Balance;
+type NegativeImbalanceOf<T> = <<T as Trait>::Currency as Currency<<T as system::Trait>::AccountId>>::NegativeImbalance;
+
+/// Index of a spend proposal.
+pub type ProposalIndex = u32;
+
+/// A spend proposal.
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct Proposal<AccountId, Balance> {
+	pub proposer: AccountId,
+	pub value: Balance,
+	pub beneficiary: AccountId,
+	pub bond: Balance,
+}
+
+pub trait Trait: system::Trait {
+	/// The currency the treasury holds.
+	type Currency: Currency<Self::AccountId> + ReservableCurrency<Self::AccountId>;
+
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as Treasury {
+		/// Amount reserved from the proposer of a spend, slashed if the proposal is rejected.
+		pub ProposalBond get(proposal_bond) config(): BalanceOf<T>;
+
+		/// Amount minted into the treasury with every block.
+		pub BlockReward get(block_reward) config(): BalanceOf<T>;
+
+		/// Number of proposals ever made.
+		pub ProposalCount get(proposal_count): ProposalIndex;
+
+		/// Pending spend proposals.
+		pub Proposals get(proposals): map ProposalIndex => Option<Proposal<T::AccountId, BalanceOf<T>>>;
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Propose to spend `value` from the treasury for `beneficiary`, reserving the proposal bond.
+		fn propose_spend(origin, #[compact] value: BalanceOf<T>, beneficiary: <T::Lookup as StaticLookup>::Source) -> Result {
+			let proposer = ensure_signed(origin)?;
+			let beneficiary = T::Lookup::lookup(beneficiary)?;
+
+			let bond = Self::proposal_bond();
+			T::Currency::reserve(&proposer, bond)
+				.map_err(|_| "Proposer's balance too low")?;
+
+			let index = Self::proposal_count();
+			<ProposalCount<T>>::put(index + 1);
+			<Proposals<T>>::insert(index, Proposal { proposer, value, beneficiary, bond });
+
+			Self::deposit_event(RawEvent::Proposed(index));
+			Ok(())
+		}
+
+		/// Approve a spend proposal, paying the beneficiary and returning the bond.
+		fn approve_proposal(origin, #[compact] proposal_id: ProposalIndex) -> Result {
+			ensure_root(origin)?;
+
+			let proposal = Self::proposals(proposal_id).ok_or("No proposal at that index")?;
+
+			T::Currency::transfer(&Self::account_id(), &proposal.beneficiary, proposal.value)
+				.map_err(|_| "Treasury balance too low")?;
+			T::Currency::unreserve(&proposal.proposer, proposal.bond);
+			<Proposals<T>>::remove(proposal_id);
+
+			Self::deposit_event(RawEvent::Awarded(proposal_id, proposal.value, proposal.beneficiary));
+			Ok(())
+		}
+
+		/// Reject a spend proposal, slashing the bond into the treasury.
+		fn reject_proposal(origin, #[compact] proposal_id: ProposalIndex) -> Result {
+			ensure_root(origin)?;
+
+			let proposal = <Proposals<T>>::take(proposal_id).ok_or("No proposal at that index")?;
+
+			let (imbalance, _) = T::Currency::slash_reserved(&proposal.proposer, proposal.bond);
+			let slashed = imbalance.peek();
+			Self::on_unbalanced(imbalance);
+
+			Self::deposit_event(RawEvent::Rejected(proposal_id, slashed));
+			Ok(())
+		}
+
+		/// Set the amount minted into the treasury with every block.
+		fn set_block_reward(origin, #[compact] block_reward: BalanceOf<T>) -> Result {
+			ensure_root(origin)?;
+
+			<BlockReward<T>>::put(block_reward);
+
+			Self::deposit_event(RawEvent::BlockRewardChanged(block_reward));
+			Ok(())
+		}
+
+		fn on_finalize(_n: T::BlockNumber) {
+			let block_reward = Self::block_reward();
+			if !block_reward.is_zero() {
+				let imbalance = T::Currency::deposit_creating(&Self::account_id(), block_reward);
+				Self::deposit_event(RawEvent::Deposit(imbalance.peek()));
+			}
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where
+		Balance = BalanceOf<T>,
+		<T as system::Trait>::AccountId,
+	{
+		/// New spend proposal.
+		Proposed(ProposalIndex),
+		/// Proposal approved and paid to the beneficiary.
+		Awarded(ProposalIndex, Balance, AccountId),
+		/// Proposal rejected and its bond slashed.
+		Rejected(ProposalIndex, Balance),
+		/// Funds deposited into the treasury.
+		Deposit(Balance),
+		/// Block reward changed.
+		BlockRewardChanged(Balance),
+	}
+);
+
+impl<T: Trait> Module<T> {
+
+	/// The account holding the treasury funds.
+	pub fn account_id() -> T::AccountId {
+		pot_account()
+	}
+
+	/// Free balance of the treasury.
+	pub fn pot() -> BalanceOf<T> {
+		T::Currency::free_balance(&Self::account_id())
+	}
+}
+
+/// The treasury pot account, for any account id type decodable from 32 bytes.
+pub fn pot_account<AccountId: Decode + Default>() -> AccountId {
+	AccountId::decode(&mut &POT_SEED[..]).unwrap_or_default()
+}
+
+impl<T: Trait> OnUnbalanced<NegativeImbalanceOf<T>> for Module<T> {
+	fn on_unbalanced(amount: NegativeImbalanceOf<T>) {
+		let value = amount.peek();
+		T::Currency::resolve_creating(&Self::account_id(), amount);
+		Self::deposit_event(RawEvent::Deposit(value));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use runtime_io::with_externalities;
+	use primitives::{H256, Blake2Hasher};
+	use support::{impl_outer_origin, assert_ok, assert_noop};
+	use runtime_primitives::{
+		BuildStorage,
+		traits::{BlakeTwo256, IdentityLookup, OnFinalize},
+		testing::{Digest, DigestItem, Header}
+	};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+	impl balances::Trait for Test {
+		type Balance = u64;
+		type OnFreeBalanceZero = ();
+		type OnNewAccount = ();
+		type Event = ();
+		type TransactionPayment = ();
+		type TransferPayment = ();
+		type DustRemoval = ();
+	}
+	impl Trait for Test {
+		type Currency = Balances;
+		type Event = ();
+	}
+	type Balances = balances::Module<Test>;
+	type Treasury = Module<Test>;
+
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		t.extend(balances::GenesisConfig::<Test> {
+			balances: vec![(1, 100), (pot_account(), 1000)],
+			existential_deposit: 0,
+			transaction_base_fee: 0,
+			transaction_byte_fee: 0,
+			transfer_fee: 0,
+			creation_fee: 0,
+			vesting: vec![],
+		}.build_storage().unwrap().0);
+		t.extend(GenesisConfig::<Test> {
+			proposal_bond: 10,
+			block_reward: 0,
+		}.build_storage().unwrap().0);
+		t.into()
+	}
+
+	#[test]
+	fn approved_proposal_is_paid_and_bond_returned() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Treasury::propose_spend(Origin::signed(1), 300, 2));
+			assert_eq!(Balances::reserved_balance(&1), 10);
+
+			assert_ok!(Treasury::approve_proposal(Origin::ROOT, 0));
+			assert_eq!(Balances::free_balance(&2), 300);
+			assert_eq!(Treasury::pot(), 700);
+			assert_eq!(Balances::free_balance(&1), 100);
+			assert_eq!(Treasury::proposals(0), None);
+		});
+	}
+
+	#[test]
+	fn rejected_proposal_bond_goes_to_treasury() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Treasury::propose_spend(Origin::signed(1), 300, 2));
+			assert_ok!(Treasury::reject_proposal(Origin::ROOT, 0));
+			assert_eq!(Balances::free_balance(&1), 90);
+			assert_eq!(Balances::reserved_balance(&1), 0);
+			assert_eq!(Treasury::pot(), 1010);
+		});
+	}
+
+	#[test]
+	fn proposals_need_bond_and_root_approval() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_noop!(Treasury::propose_spend(Origin::signed(3), 300, 2), "Proposer's balance too low");
+			assert_ok!(Treasury::propose_spend(Origin::signed(1), 3000, 2));
+			assert_noop!(Treasury::approve_proposal(Origin::signed(1), 0), "bad origin: expected to be a root origin");
+			assert_noop!(Treasury::approve_proposal(Origin::ROOT, 0), "Treasury balance too low");
+			assert_noop!(Treasury::approve_proposal(Origin::ROOT, 1), "No proposal at that index");
+		});
+	}
+
+	#[test]
+	fn block_reward_is_minted_into_treasury() {
+		with_externalities(&mut new_test_ext(), || {
+			<Treasury as OnFinalize<u64>>::on_finalize(1);
+			assert_eq!(Treasury::pot(), 1000);
+
+			assert_ok!(Treasury::set_block_reward(Origin::ROOT, 5));
+			<Treasury as OnFinalize<u64>>::on_finalize(2);
+			assert_eq!(Treasury::pot(), 1005);
+		});
+	}
+}
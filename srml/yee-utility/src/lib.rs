@@ -0,0 +1,368 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Multisig accounts dispatching calls once M of N signatories approve them, and call batching.
+//!
+//! A multisig account has no key: its ID is derived from its sorted signatories and threshold.
+//! A call is dispatched from it when the threshold-th signatory approves it with `as_multi`.
+//! The signatory opening an operation reserves a deposit for its storage until it is executed
+//! or cancelled.
+//!
+//! `batch_non_atomic` dispatches calls in order from the same origin, stopping at the first
+//! failure. It is not atomic: the calls dispatched before the failure keep their effects.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use rstd::prelude::*;
+use parity_codec::{Encode, Decode};
+use support::{
+	Parameter, decl_storage, decl_module, decl_event, StorageValue, StorageDoubleMap,
+	dispatch::{Result, Dispatchable}, ensure,
+	traits::{Currency, ReservableCurrency},
+};
+use runtime_primitives::traits::Hash;
+use system::ensure_signed;
+
+type BalanceOf<T> = <<T as Trait>::Currency as Currency<<T as system::Trait>::AccountId>>::Balance;
+
+/// Domain separator of multisig account IDs.
+const MULTISIG_SEED: &[u8] = b"yee/multisig";
+
+/// Pending multisig operation.
+#[derive(Encode, Decode, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct Multisig<AccountId, BlockNumber, Balance> {
+	/// Block the operation was opened in.
+	pub when: BlockNumber,
+	/// Deposit reserved from the opener.
+	pub deposit: Balance,
+	/// Signatories who approved the operation, the first one opened it.
+	pub approvals: Vec<AccountId>,
+}
+
+pub trait Trait: system::Trait {
+	/// The currency multisig deposits are reserved in.
+	type Currency: ReservableCurrency<Self::AccountId>;
+
+	/// The call dispatched by multisig accounts and batches.
+	type Call: Parameter + Dispatchable<Origin=<Self as system::Trait>::Origin>;
+
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as Utility {
+		/// Amount reserved from the opener of a multisig operation while it is pending.
+		pub MultisigDeposit get(multisig_deposit) config(): BalanceOf<T>;
+
+		/// Pending operations of a multisig account, by call hash.
+		pub Multisigs get(multisig): double_map T::AccountId, blake2_256(T::Hash)
+			=> Option<Multisig<T::AccountId, T::BlockNumber, BalanceOf<T>>>;
+	}
+}
+
+decl_module! {
+	pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+		fn deposit_event<T>() = default;
+
+		/// Dispatch calls in order from the origin, stopping at the first failure.
+		///
+		/// Not atomic: the calls dispatched before a failure are not reverted, and the batch
+		/// succeeds with a `BatchInterrupted` event.
+		fn batch_non_atomic(origin, calls: Vec<<T as Trait>::Call>) -> Result {
+			let origin: Option<system::RawOrigin<T::AccountId>> = origin.into();
+			let origin = origin.ok_or("Unsupported origin for batch")?;
+
+			for (index, call) in calls.into_iter().enumerate() {
+				if let Err(e) = call.dispatch(origin.clone().into()) {
+					Self::deposit_event(RawEvent::BatchInterrupted(index as u32, e.as_bytes().to_vec()));
+					return Ok(());
+				}
+			}
+
+			Self::deposit_event(RawEvent::BatchCompleted);
+			Ok(())
+		}
+
+		/// Approve `call` from the multisig account of the sender, `other_signatories` and
+		/// `threshold`, dispatching it if this is the threshold-th approval.
+		///
+		/// `other_signatories` must be sorted and must not contain the sender.
+		fn as_multi(origin, threshold: u16, other_signatories: Vec<T::AccountId>, call: Box<<T as Trait>::Call>) -> Result {
+			let who = ensure_signed(origin)?;
+			let id = Self::ensure_multisig(&who, threshold, other_signatories)?;
+			let call_hash = T::Hashing::hash_of(&*call);
+
+			let approvals = Self::approve(&id, &call_hash, who)?;
+			if approvals >= threshold as usize {
+				Self::close(&id, &call_hash);
+				let ok = (*call).dispatch(system::RawOrigin::Signed(id.clone()).into()).is_ok();
+				Self::deposit_event(RawEvent::MultisigExecuted(id, call_hash, ok));
+			}
+			Ok(())
+		}
+
+		/// Approve the call with `call_hash` without providing it. The final approval must be
+		/// given with `as_multi`.
+		fn approve_as_multi(origin, threshold: u16, other_signatories: Vec<T::AccountId>, call_hash: T::Hash) -> Result {
+			let who = ensure_signed(origin)?;
+			let id = Self::ensure_multisig(&who, threshold, other_signatories)?;
+
+			let approvals = Self::multisig(&id, &call_hash).map(|m| m.approvals.len()).unwrap_or_default();
+			ensure!(approvals + 1 < threshold as usize, "Final approval must be given with as_multi");
+
+			Self::approve(&id, &call_hash, who)?;
+			Ok(())
+		}
+
+		/// Cancel a pending operation. Only the signatory who opened it can cancel it.
+		fn cancel_as_multi(origin, threshold: u16, other_signatories: Vec<T::AccountId>, call_hash: T::Hash) -> Result {
+			let who = ensure_signed(origin)?;
+			let id = Self::ensure_multisig(&who, threshold, other_signatories)?;
+
+			let multisig = Self::multisig(&id, &call_hash).ok_or("No pending operation for the call")?;
+			ensure!(multisig.approvals.first() == Some(&who), "Only the opener can cancel");
+
+			Self::close(&id, &call_hash);
+
+			Self::deposit_event(RawEvent::MultisigCancelled(who, id, call_hash));
+			Ok(())
+		}
+	}
+}
+
+decl_event!(
+	pub enum Event<T> where
+		<T as system::Trait>::AccountId,
+		<T as system::Trait>::Hash,
+	{
+		/// Batch dispatched all of its calls.
+		BatchCompleted,
+		/// Batch stopped at the call with the given index, with the error.
+		BatchInterrupted(u32, Vec<u8>),
+		/// Signatory opened an operation of the multisig account.
+		NewMultisig(AccountId, AccountId, Hash),
+		/// Signatory approved an operation of the multisig account.
+		MultisigApproval(AccountId, AccountId, Hash),
+		/// Operation of the multisig account dispatched: success if true.
+		MultisigExecuted(AccountId, Hash, bool),
+		/// Signatory cancelled an operation of the multisig account.
+		MultisigCancelled(AccountId, AccountId, Hash),
+	}
+);
+
+impl<T: Trait> Module<T> {
+
+	/// Account ID of the multisig with the given sorted signatories and threshold.
+	pub fn multi_account_id(signatories: &[T::AccountId], threshold: u16) -> T::AccountId {
+		let entropy = T::Hashing::hash_of(&(MULTISIG_SEED, signatories, threshold));
+		T::AccountId::decode(&mut entropy.as_ref()).unwrap_or_default()
+	}
+
+	/// Check the signatories and threshold, returning the multisig account ID.
+	fn ensure_multisig(who: &T::AccountId, threshold: u16, other_signatories: Vec<T::AccountId>) -> rstd::result::Result<T::AccountId, &'static str> {
+		ensure!(!other_signatories.is_empty(), "Multisig needs other signatories");
+		ensure!(threshold >= 1, "Threshold must be at least 1");
+		ensure!(threshold as usize <= other_signatories.len() + 1, "Threshold exceeds the number of signatories");
+
+		let signatories = Self::insert_sorted(who, other_signatories)?;
+		Ok(Self::multi_account_id(&signatories, threshold))
+	}
+
+	/// Insert `who` into the sorted, duplicate free `others`.
+	fn insert_sorted(who: &T::AccountId, others: Vec<T::AccountId>) -> rstd::result::Result<Vec<T::AccountId>, &'static str> {
+		ensure!(others.windows(2).all(|w| w[0] < w[1]), "Signatories must be sorted and unique");
+		let index = match others.binary_search(who) {
+			Ok(_) => return Err("Sender is in the other signatories"),
+			Err(index) => index,
+		};
+		let mut signatories = others;
+		signatories.insert(index, who.clone());
+		Ok(signatories)
+	}
+
+	/// Record the approval of `who`, returning the number of approvals.
+	fn approve(id: &T::AccountId, call_hash: &T::Hash, who: T::AccountId) -> rstd::result::Result<usize, &'static str> {
+		let multisig = match Self::multisig(id, call_hash) {
+			Some(mut multisig) => {
+				ensure!(!multisig.approvals.contains(&who), "Already approved");
+				multisig.approvals.push(who.clone());
+				Self::deposit_event(RawEvent::MultisigApproval(who, id.clone(), call_hash.clone()));
+				multisig
+			},
+			None => {
+				let deposit = Self::multisig_deposit();
+				T::Currency::reserve(&who, deposit).map_err(|_| "Opener's balance too low for the deposit")?;
+				Self::deposit_event(RawEvent::NewMultisig(who.clone(), id.clone(), call_hash.clone()));
+				Multisig {
+					when: <system::Module<T>>::block_number(),
+					deposit,
+					approvals: vec![who],
+				}
+			},
+		};
+		let approvals = multisig.approvals.len();
+		<Multisigs<T>>::insert(id, call_hash, multisig);
+		Ok(approvals)
+	}
+
+	/// Remove a pending operation, returning the deposit to its opener.
+	fn close(id: &T::AccountId, call_hash: &T::Hash) {
+		if let Some(multisig) = <Multisigs<T>>::take(id, call_hash) {
+			if let Some(opener) = multisig.approvals.first() {
+				T::Currency::unreserve(opener, multisig.deposit);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use runtime_io::with_externalities;
+	use primitives::{H256, Blake2Hasher};
+	use support::{impl_outer_origin, impl_outer_dispatch, assert_ok, assert_noop};
+	use runtime_primitives::{
+		BuildStorage,
+		traits::{BlakeTwo256, IdentityLookup},
+		testing::{Digest, DigestItem, Header}
+	};
+
+	impl_outer_origin! {
+		pub enum Origin for Test {}
+	}
+
+	mod utility {
+		pub use super::super::*;
+	}
+
+	impl_outer_dispatch! {
+		pub enum Call for Test where origin: Origin {
+			utility::Utility,
+			balances::Balances,
+		}
+	}
+
+	#[derive(Clone, Eq, PartialEq)]
+	pub struct Test;
+	impl system::Trait for Test {
+		type Origin = Origin;
+		type Index = u64;
+		type BlockNumber = u64;
+		type Hash = H256;
+		type Hashing = BlakeTwo256;
+		type Digest = Digest;
+		type AccountId = u64;
+		type Lookup = IdentityLookup<Self::AccountId>;
+		type Header = Header;
+		type Event = ();
+		type Log = DigestItem;
+	}
+	impl balances::Trait for Test {
+		type Balance = u64;
+		type OnFreeBalanceZero = ();
+		type OnNewAccount = ();
+		type Event = ();
+		type TransactionPayment = ();
+		type TransferPayment = ();
+		type DustRemoval = ();
+	}
+	impl Trait for Test {
+		type Currency = Balances;
+		type Call = Call;
+		type Event = ();
+	}
+	type Balances = balances::Module<Test>;
+	type Utility = Module<Test>;
+
+	fn new_test_ext() -> runtime_io::TestExternalities<Blake2Hasher> {
+		let mut t = system::GenesisConfig::<Test>::default().build_storage().unwrap().0;
+		let multi = Utility::multi_account_id(&[1, 2, 3], 2);
+		t.extend(balances::GenesisConfig::<Test> {
+			balances: vec![(1, 100), (2, 100), (3, 100), (multi, 100)],
+			existential_deposit: 0,
+			transaction_base_fee: 0,
+			transaction_byte_fee: 0,
+			transfer_fee: 0,
+			creation_fee: 0,
+			vesting: vec![],
+		}.build_storage().unwrap().0);
+		t.extend(GenesisConfig::<Test> {
+			multisig_deposit: 5,
+		}.build_storage().unwrap().0);
+		t.into()
+	}
+
+	fn transfer(to: u64, value: u64) -> Call {
+		Call::Balances(balances::Call::transfer(to, value))
+	}
+
+	#[test]
+	fn multisig_call_is_dispatched_at_threshold() {
+		with_externalities(&mut new_test_ext(), || {
+			let multi = Utility::multi_account_id(&[1, 2, 3], 2);
+			let call = Box::new(transfer(4, 40));
+			let hash = BlakeTwo256::hash_of(&*call);
+
+			assert_ok!(Utility::as_multi(Origin::signed(1), 2, vec![2, 3], call.clone()));
+			assert_eq!(Balances::free_balance(&4), 0);
+			assert_eq!(Balances::reserved_balance(&1), 5);
+			assert!(Utility::multisig(&multi, &hash).is_some());
+
+			assert_ok!(Utility::as_multi(Origin::signed(3), 2, vec![1, 2], call));
+			assert_eq!(Balances::free_balance(&4), 40);
+			assert_eq!(Balances::free_balance(&multi), 60);
+			assert_eq!(Balances::reserved_balance(&1), 0);
+			assert_eq!(Utility::multisig(&multi, &hash), None);
+		});
+	}
+
+	#[test]
+	fn signatories_must_be_sorted_and_exclude_sender() {
+		with_externalities(&mut new_test_ext(), || {
+			let call = Box::new(transfer(4, 40));
+			assert_noop!(Utility::as_multi(Origin::signed(1), 2, vec![3, 2], call.clone()), "Signatories must be sorted and unique");
+			assert_noop!(Utility::as_multi(Origin::signed(1), 2, vec![1, 2], call.clone()), "Sender is in the other signatories");
+			assert_noop!(Utility::as_multi(Origin::signed(1), 4, vec![2, 3], call), "Threshold exceeds the number of signatories");
+		});
+	}
+
+	#[test]
+	fn only_opener_cancels() {
+		with_externalities(&mut new_test_ext(), || {
+			let hash = BlakeTwo256::hash_of(&transfer(4, 40));
+			assert_noop!(Utility::approve_as_multi(Origin::signed(5), 3, vec![2, 3], hash), "Opener's balance too low for the deposit");
+			assert_ok!(Utility::approve_as_multi(Origin::signed(1), 3, vec![2, 3], hash));
+			assert_eq!(Balances::free_balance(&1), 95);
+			assert_noop!(Utility::cancel_as_multi(Origin::signed(2), 3, vec![1, 3], hash), "Only the opener can cancel");
+			assert_ok!(Utility::cancel_as_multi(Origin::signed(1), 3, vec![2, 3], hash));
+			assert_eq!(Balances::free_balance(&1), 100);
+			assert_eq!(Utility::multisig(&Utility::multi_account_id(&[1, 2, 3], 3), &hash), None);
+		});
+	}
+
+	#[test]
+	fn batch_stops_at_first_failure_keeping_earlier_calls() {
+		with_externalities(&mut new_test_ext(), || {
+			assert_ok!(Utility::batch_non_atomic(Origin::signed(1), vec![transfer(4, 10), transfer(4, 1000), transfer(4, 10)]));
+			assert_eq!(Balances::free_balance(&4), 10);
+			assert_eq!(Balances::free_balance(&1), 90);
+		});
+	}
+}
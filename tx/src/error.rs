@@ -0,0 +1,23 @@
+//! Transaction building errors.
+
+use error_chain::*;
+
+error_chain! {
+	errors {
+		/// The mortal era does not start at the given block, which is not its birth.
+		EraBirth(period: u64, number: u64) {
+			description("mortal era does not start at the block"),
+			display("Mortal era of period {} does not start at block #{}, give its birth block", period, number),
+		}
+		/// The address is neither an account index nor an account ID.
+		InvalidAddress(address: String) {
+			description("invalid address"),
+			display("Invalid address: {}", address),
+		}
+		/// The encoded call is not a call of the runtime.
+		InvalidCall {
+			description("invalid call"),
+			display("Invalid encoded call"),
+		}
+	}
+}
@@ -0,0 +1,215 @@
+// Copyright (C) 2019 Yee Foundation.
+//
+// This file is part of YeeChain.
+//
+// YeeChain is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// YeeChain is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with YeeChain.  If not, see <https://www.gnu.org/licenses/>.
+
+//! Branch transactions built and signed off-chain.
+//!
+//! ```ignore
+//! let birth = era_birth(64, finalized_number);
+//! let xt = TransactionBuilder::new(transfer(dest, 100))
+//! 	.nonce(nonce)
+//! 	.mortal(64, birth, birth_hash)
+//! 	.sign(&pair, genesis_hash)?;
+//! ```
+//!
+//! The signature covers the nonce, the call, the era and the hash of the block the era starts
+//! at, the genesis hash for immortal transactions.
+
+mod error;
+
+pub use error::{Error, ErrorKind, Result};
+
+use std::str::FromStr;
+use parity_codec::{Compact, Decode, Encode};
+use primitives::{blake2_256, sr25519, crypto::{Pair, Ss58Codec}, H256};
+use runtime_primitives::generic::Era;
+use yee_branch_runtime::{
+	AccountId, AccountIndex, Address, Balance, BalancesCall, BlockNumber, Call, Hash, Nonce, UncheckedExtrinsic,
+};
+
+/// Payloads longer than this are signed by their hash, like the runtime checks them.
+const MAX_PAYLOAD_LEN: usize = 256;
+
+/// Builder of a signed transaction of the branch runtime.
+pub struct TransactionBuilder {
+	call: Call,
+	nonce: Nonce,
+	era: Option<(u64, BlockNumber, Hash)>,
+	index: Option<AccountIndex>,
+}
+
+impl TransactionBuilder {
+	/// Immortal transaction with nonce 0 of the call.
+	pub fn new(call: Call) -> Self {
+		TransactionBuilder {
+			call,
+			nonce: 0,
+			era: None,
+			index: None,
+		}
+	}
+
+	/// Nonce of the transaction, the next nonce of the sender.
+	pub fn nonce(mut self, nonce: Nonce) -> Self {
+		self.nonce = nonce;
+		self
+	}
+
+	/// Make the transaction valid for `period` blocks from the block the era starts at, given
+	/// by `era_birth` for a recent block.
+	///
+	/// The period is rounded to a power of two between 4 and 65536.
+	pub fn mortal(mut self, period: u64, birth: BlockNumber, hash: Hash) -> Self {
+		self.era = Some((period, birth, hash));
+		self
+	}
+
+	/// Make the transaction valid forever, which allows replaying it on a reset account.
+	pub fn immortal(mut self) -> Self {
+		self.era = None;
+		self
+	}
+
+	/// Send from the account index of the signer instead of its account ID, which is shorter.
+	pub fn index(mut self, index: AccountIndex) -> Self {
+		self.index = Some(index);
+		self
+	}
+
+	/// Sign the transaction with the key of the sender.
+	pub fn sign(self, pair: &sr25519::Pair, genesis_hash: Hash) -> Result<UncheckedExtrinsic> {
+		let (era, checkpoint) = match self.era {
+			Some((period, birth, hash)) => {
+				let era = Era::mortal(period, birth);
+				if era.birth(birth) != birth {
+					return Err(ErrorKind::EraBirth(period, birth).into());
+				}
+				(era, hash)
+			},
+			None => (Era::Immortal, genesis_hash),
+		};
+
+		let signature = (Compact(self.nonce), &self.call, era, checkpoint).using_encoded(|payload| {
+			if payload.len() > MAX_PAYLOAD_LEN {
+				pair.sign(&blake2_256(payload)[..])
+			} else {
+				pair.sign(payload)
+			}
+		});
+
+		let sender = match self.index {
+			Some(index) => indices::address::Address::Index(index),
+			None => indices::address::Address::Id(pair.public()),
+		};
+
+		Ok(UncheckedExtrinsic::new_signed(self.nonce, self.call, sender, signature, era))
+	}
+}
+
+/// Block the mortal era of `period` blocks covering the block `number` starts at.
+pub fn era_birth(period: u64, number: BlockNumber) -> BlockNumber {
+	Era::mortal(period, number).birth(number)
+}
+
+/// Transfer of `value` to `dest`.
+pub fn transfer(dest: Address, value: Balance) -> Call {
+	Call::Balances(BalancesCall::transfer(dest, value))
+}
+
+/// Decode a call of the runtime.
+pub fn decode_call(encoded: &[u8]) -> Result<Call> {
+	let input = &mut &encoded[..];
+	match Call::decode(input) {
+		Some(call) if input.is_empty() => Ok(call),
+		_ => Err(ErrorKind::InvalidCall.into()),
+	}
+}
+
+/// Parse an address: an account index, or an SS58 or hex account ID.
+pub fn parse_address(address: &str) -> Result<Address> {
+	if let Ok(index) = address.parse::<AccountIndex>() {
+		return Ok(indices::address::Address::Index(index));
+	}
+	if let Ok(id) = AccountId::from_ss58check(address) {
+		return Ok(indices::address::Address::Id(id));
+	}
+	H256::from_str(address.trim_start_matches("0x"))
+		.map(|id| indices::address::Address::Id(AccountId::from_raw(id.0)))
+		.map_err(|_| ErrorKind::InvalidAddress(address.to_string()).into())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn pair() -> sr25519::Pair {
+		sr25519::Pair::from_string("//Alice", None).unwrap()
+	}
+
+	fn payload_signed_by(xt: &UncheckedExtrinsic, payload: &[u8]) -> bool {
+		let (_, ref signature, _, _) = xt.signature.clone().unwrap();
+		sr25519::Pair::verify(signature, payload, &pair().public())
+	}
+
+	#[test]
+	fn immortal_transaction_is_signed_over_genesis_hash() {
+		let call = transfer(parse_address("7").unwrap(), 100);
+		let genesis_hash = Hash::repeat_byte(1);
+		let xt = TransactionBuilder::new(call.clone()).nonce(3).sign(&pair(), genesis_hash).unwrap();
+
+		let payload = (Compact(3u64), &call, Era::Immortal, genesis_hash).encode();
+		assert!(payload_signed_by(&xt, &payload));
+		assert_eq!(UncheckedExtrinsic::decode(&mut &xt.encode()[..]), Some(xt));
+	}
+
+	#[test]
+	fn mortal_transaction_is_signed_over_block_hash() {
+		let call = transfer(parse_address("7").unwrap(), 100);
+		let block_hash = Hash::repeat_byte(2);
+		let xt = TransactionBuilder::new(call.clone()).mortal(64, 1000, block_hash)
+			.sign(&pair(), Hash::repeat_byte(1)).unwrap();
+
+		let payload = (Compact(0u64), &call, Era::mortal(64, 1000), block_hash).encode();
+		assert!(payload_signed_by(&xt, &payload));
+	}
+
+	#[test]
+	fn long_period_must_start_at_block() {
+		let call = transfer(parse_address("7").unwrap(), 100);
+		let result = TransactionBuilder::new(call).mortal(8192, 8193, Hash::default()).sign(&pair(), Hash::default());
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn long_period_starts_at_era_birth() {
+		let call = transfer(parse_address("7").unwrap(), 100);
+		let birth = era_birth(8192, 8193);
+		assert_eq!(birth, 8192);
+		let xt = TransactionBuilder::new(call.clone()).mortal(8192, birth, Hash::repeat_byte(2))
+			.sign(&pair(), Hash::default()).unwrap();
+
+		let payload = (Compact(0u64), &call, Era::mortal(8192, 8193), Hash::repeat_byte(2)).encode();
+		assert!(payload_signed_by(&xt, &payload));
+	}
+
+	#[test]
+	fn addresses_are_parsed() {
+		assert_eq!(parse_address("42").unwrap(), indices::address::Address::Index(42));
+		let id = pair().public();
+		assert_eq!(parse_address(&id.to_ss58check()).unwrap(), indices::address::Address::Id(id.clone()));
+		assert!(parse_address("nope").is_err());
+	}
+}